@@ -0,0 +1,103 @@
+use crate::transport::AddressFamily;
+use libp2p::multiaddr::Protocol;
+use libp2p::Multiaddr;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+// Whether an address can be reached from the internet. Addresses without an IP, like /dns or /onion3, are
+// assumed to be, except for localhost
+pub(crate) fn is_global(addr: &Multiaddr) -> bool {
+    match addr.iter().next() {
+        Some(Protocol::Ip4(ip)) => is_global_ipv4(&ip),
+        Some(Protocol::Ip6(ip)) => is_global_ipv6(&ip),
+        Some(Protocol::Dns(name) | Protocol::Dns4(name) | Protocol::Dns6(name)) => {
+            name != "localhost" && !name.ends_with(".localhost")
+        }
+        _ => true,
+    }
+}
+
+// The address peers reach a listen address on, per the configured translations. A translation from an
+// unspecified IP (0.0.0.0 or ::) applies to every interface address with the same port
+pub(crate) fn translate(map: &[(Multiaddr, Multiaddr)], address: &Multiaddr) -> Option<Multiaddr> {
+    map.iter()
+        .find(|(listen, _)| {
+            listen.iter().count() == address.iter().count()
+                && listen.iter().zip(address.iter()).all(|pair| match pair {
+                    (Protocol::Ip4(listen), Protocol::Ip4(_)) if listen.is_unspecified() => true,
+                    (Protocol::Ip6(listen), Protocol::Ip6(_)) if listen.is_unspecified() => true,
+                    (listen, address) => listen == address,
+                })
+        })
+        .map(|(_, advertised)| advertised.clone())
+}
+
+// Listeners need an IP, a listen address starting with /dns, /dns4 or /dns6 is resolved once to the
+// first address of the matching family, other addresses are returned as is
+pub(crate) async fn resolve_listen(
+    addr: &Multiaddr,
+    family: AddressFamily,
+) -> Result<Multiaddr, io::Error> {
+    let mut protocols = addr.iter();
+    let (name, wants): (_, fn(&IpAddr) -> bool) = match protocols.next() {
+        Some(Protocol::Dns(name)) => (name, |_| true),
+        Some(Protocol::Dns4(name)) => (name, IpAddr::is_ipv4),
+        Some(Protocol::Dns6(name)) => (name, IpAddr::is_ipv6),
+        _ => return Ok(addr.clone()),
+    };
+
+    let ip = tokio::net::lookup_host((name.as_ref(), 0))
+        .await?
+        .map(|socket| socket.ip())
+        .find(|ip| wants(ip) && family.allows(&Multiaddr::from(*ip)))
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} did not resolve to a usable IP", name),
+            )
+        })?;
+
+    Ok(protocols.fold(Multiaddr::from(ip), |addr, protocol| addr.with(protocol)))
+}
+
+fn is_global_ipv4(ip: &Ipv4Addr) -> bool {
+    let [a, b, c, _] = ip.octets();
+
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        // "this network" 0.0.0.0/8
+        || a == 0
+        // Carrier-grade NAT 100.64.0.0/10
+        || (a == 100 && (b & 0b1100_0000) == 64)
+        // IETF protocol assignments 192.0.0.0/24
+        || (a == 192 && b == 0 && c == 0)
+        // Benchmarking 198.18.0.0/15
+        || (a == 198 && (b & 0xfe) == 18)
+        // Reserved 240.0.0.0/4
+        || a >= 240)
+}
+
+fn is_global_ipv6(ip: &Ipv6Addr) -> bool {
+    if let Some(ipv4) = ip.to_ipv4_mapped() {
+        return is_global_ipv4(&ipv4);
+    }
+
+    let segments = ip.segments();
+
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_multicast()
+        // Unique local fc00::/7
+        || (segments[0] & 0xfe00) == 0xfc00
+        // Link-local fe80::/10
+        || (segments[0] & 0xffc0) == 0xfe80
+        // Documentation 2001:db8::/32
+        || (segments[0] == 0x2001 && segments[1] == 0x0db8)
+        // Discard-only 100::/64
+        || (segments[0] == 0x0100 && segments[1..4] == [0, 0, 0]))
+}
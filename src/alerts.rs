@@ -0,0 +1,66 @@
+use crate::SplashEvent;
+use std::time::Duration;
+use tokio::time::Instant;
+
+pub(crate) const ALERT_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// When to raise alert events, unset thresholds never alert
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AlertThresholds {
+    /// Alert once fewer peers than this are connected
+    pub min_peers: Option<usize>,
+    /// Alert once no offer was received for this long
+    pub offer_stall: Option<Duration>,
+}
+
+// Evaluates the thresholds on every check, each alert is raised once until its condition clears
+pub(crate) struct Alerts {
+    thresholds: AlertThresholds,
+    peer_count_low: bool,
+    last_offer: Instant,
+    offer_flow_stalled: bool,
+}
+
+impl Alerts {
+    pub(crate) fn new(thresholds: AlertThresholds) -> Self {
+        Alerts {
+            thresholds,
+            peer_count_low: false,
+            last_offer: Instant::now(),
+            offer_flow_stalled: false,
+        }
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.thresholds.min_peers.is_some() || self.thresholds.offer_stall.is_some()
+    }
+
+    pub(crate) fn offer_received(&mut self) {
+        self.last_offer = Instant::now();
+        self.offer_flow_stalled = false;
+    }
+
+    pub(crate) fn check(&mut self, peers: usize) -> Vec<SplashEvent> {
+        let mut events = Vec::new();
+
+        if let Some(min_peers) = self.thresholds.min_peers {
+            if peers < min_peers && !self.peer_count_low {
+                self.peer_count_low = true;
+                events.push(SplashEvent::PeerCountLow { peers, min_peers });
+            } else if peers >= min_peers && self.peer_count_low {
+                self.peer_count_low = false;
+                events.push(SplashEvent::PeerCountRecovered(peers));
+            }
+        }
+
+        if let Some(offer_stall) = self.thresholds.offer_stall {
+            let since = self.last_offer.elapsed();
+            if since >= offer_stall && !self.offer_flow_stalled {
+                self.offer_flow_stalled = true;
+                events.push(SplashEvent::OfferFlowStalled(since));
+            }
+        }
+
+        events
+    }
+}
@@ -0,0 +1,1356 @@
+use crate::behaviour::{BehaviourConfig, SplashBehaviour};
+use crate::{
+    capabilities, chaos, connections, control, dns, events, gate, middleware, offer,
+    offer_from_bytes, queue, throttle, topics, transport, AddressFamily, AlertThresholds, Cidr,
+    Command, ConnectionInfo, DedupConfig, InboundOffer, InboundPipeline, MeshConfig, NodeState,
+    OfferCompression, OfferMetadata, OfferStatus, OutboundOffer, PeerScoring, PropagationRecord,
+    PublishReceipt, RawMessage, SecurityUpgrades, ShutdownSummary, SplashError, SplashEvent,
+    Submission, DEFAULT_DIAL_CONCURRENCY_FACTOR, DEFAULT_DIAL_TIMEOUT, DEFAULT_HANDSHAKE_TIMEOUT,
+    DEFAULT_INBOUND_OFFER_BURST, DEFAULT_INBOUND_OFFER_RATE,
+    DEFAULT_MAX_NEGOTIATING_INBOUND_STREAMS, DEFAULT_MESH_READY_PEERS, DEFAULT_TARGET_PEERS,
+    EVENT_BUFFER, MAX_OFFER_SIZE, RAW_MESSAGE_BUFFER,
+};
+use libp2p::multiaddr::Protocol;
+use libp2p::{connection_limits, identity, noise, relay, yamux, Multiaddr, PeerId};
+use log::warn;
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::num::NonZeroU8;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tokio::sync::mpsc::{self, Receiver, Sender};
+use tokio::sync::oneshot;
+use tokio::time;
+
+pub struct Splash {
+    pub listen_addresses: Vec<Multiaddr>,
+    pub internal_listen_addresses: Vec<Multiaddr>,
+    pub external_addresses: Vec<Multiaddr>,
+    pub(crate) advertise_map: Vec<(Multiaddr, Multiaddr)>,
+    pub(crate) private_addresses: bool,
+    pub known_peers: Vec<Multiaddr>,
+    pub(crate) fallback_peers: Vec<Multiaddr>,
+    pub(crate) cluster_service: Option<(String, Option<u16>)>,
+    pub keys: identity::Keypair,
+    pub(crate) network_name: String,
+    pub(crate) namespace: Option<String>,
+    pub(crate) compression: OfferCompression,
+    pub(crate) submission: Sender<Submission>,
+    pub(crate) submission_receiver: Option<Receiver<Submission>>,
+    pub(crate) commands: Sender<Command>,
+    pub(crate) command_receiver: Option<Receiver<Command>>,
+    pub(crate) restored_state: Option<NodeState>,
+    pub(crate) chaos: chaos::ChaosConfig,
+    pub(crate) transport: transport::TransportKind,
+    pub(crate) websocket_tls: Option<libp2p::websocket::tls::Config>,
+    pub(crate) auth_token: Option<String>,
+    pub(crate) dedup: DedupConfig,
+    pub(crate) mesh: MeshConfig,
+    pub(crate) peer_scoring: Option<PeerScoring>,
+    pub(crate) target_peers: usize,
+    pub(crate) introducer: bool,
+    pub(crate) min_peers: Option<usize>,
+    pub(crate) max_peers: Option<usize>,
+    pub(crate) pending: Option<Arc<Mutex<queue::PendingQueue>>>,
+    pub(crate) publish_retry_max_age: Option<Duration>,
+    pub(crate) trusted_relays: Vec<Multiaddr>,
+    pub(crate) operator: Option<String>,
+    pub(crate) circuit_relays: Vec<Multiaddr>,
+    pub(crate) circuit_relay_server: bool,
+    pub(crate) canary_responder: bool,
+    pub(crate) sync_window: Option<Duration>,
+    pub(crate) backfill: Option<Duration>,
+    pub(crate) relay_limits: RelayLimits,
+    pub(crate) handshake_timeout: Duration,
+    pub(crate) dial_timeout: Duration,
+    pub(crate) dial_concurrency_factor: NonZeroU8,
+    pub(crate) rate_limits: throttle::RateLimits,
+    pub(crate) publish_rate: Option<(f64, u32)>,
+    pub(crate) inbound_rate: (f64, u32),
+    pub(crate) propagation_log: Option<usize>,
+    pub(crate) publish_versions: Vec<u32>,
+    pub(crate) topics: Vec<String>,
+    pub(crate) max_negotiating_inbound_streams: usize,
+    pub(crate) raw_messages: broadcast::Sender<RawMessage>,
+    pub(crate) upnp: bool,
+    pub(crate) lazy_bootstrap: bool,
+    pub(crate) proxy: Option<SocketAddr>,
+    pub(crate) tor: bool,
+    pub(crate) onion_address: Option<Multiaddr>,
+    pub(crate) security: SecurityUpgrades,
+    pub(crate) address_family: AddressFamily,
+    pub(crate) inbound_middleware: Vec<middleware::InboundMiddleware>,
+    pub(crate) outbound_middleware: Vec<middleware::OutboundMiddleware>,
+    pub(crate) connection_limits: connection_limits::ConnectionLimits,
+    pub(crate) max_connections_per_ip: Option<usize>,
+    pub(crate) gate_rules: gate::Rules,
+    pub(crate) blocklist: Option<(String, PeerId)>,
+    pub(crate) alert_thresholds: AlertThresholds,
+    pub(crate) status_interval: Option<Duration>,
+    pub(crate) mesh_ready_peers: usize,
+    pub(crate) mesh_ready_wait: Option<Duration>,
+}
+
+pub struct SplashContext {
+    pub node: Splash,
+    pub events: mpsc::Receiver<SplashEvent>,
+}
+
+impl SplashContext {
+    /// Every gossip message before validation, for analysis tools. Receivers that fall more than
+    /// RAW_MESSAGE_BUFFER messages behind skip ahead and get `RecvError::Lagged`
+    pub fn raw_messages(&self) -> broadcast::Receiver<RawMessage> {
+        self.node.raw_messages.subscribe()
+    }
+}
+
+impl Clone for Splash {
+    fn clone(&self) -> Self {
+        Splash {
+            listen_addresses: self.listen_addresses.clone(),
+            internal_listen_addresses: self.internal_listen_addresses.clone(),
+            external_addresses: self.external_addresses.clone(),
+            advertise_map: self.advertise_map.clone(),
+            private_addresses: self.private_addresses,
+            known_peers: self.known_peers.clone(),
+            fallback_peers: self.fallback_peers.clone(),
+            cluster_service: self.cluster_service.clone(),
+            keys: self.keys.clone(),
+            network_name: self.network_name.clone(),
+            namespace: self.namespace.clone(),
+            compression: self.compression,
+            submission: self.submission.clone(),
+            submission_receiver: None,
+            commands: self.commands.clone(),
+            command_receiver: None,
+            restored_state: None,
+            chaos: self.chaos.clone(),
+            transport: self.transport,
+            websocket_tls: self.websocket_tls.clone(),
+            auth_token: self.auth_token.clone(),
+            dedup: self.dedup.clone(),
+            mesh: self.mesh.clone(),
+            peer_scoring: self.peer_scoring.clone(),
+            target_peers: self.target_peers,
+            introducer: self.introducer,
+            min_peers: self.min_peers,
+            max_peers: self.max_peers,
+            pending: self.pending.clone(),
+            publish_retry_max_age: self.publish_retry_max_age,
+            trusted_relays: self.trusted_relays.clone(),
+            operator: self.operator.clone(),
+            circuit_relays: self.circuit_relays.clone(),
+            circuit_relay_server: self.circuit_relay_server,
+            canary_responder: self.canary_responder,
+            sync_window: self.sync_window,
+            backfill: self.backfill,
+            relay_limits: self.relay_limits,
+            handshake_timeout: self.handshake_timeout,
+            dial_timeout: self.dial_timeout,
+            dial_concurrency_factor: self.dial_concurrency_factor,
+            rate_limits: self.rate_limits,
+            publish_rate: self.publish_rate,
+            inbound_rate: self.inbound_rate,
+            propagation_log: self.propagation_log,
+            publish_versions: self.publish_versions.clone(),
+            topics: self.topics.clone(),
+            max_negotiating_inbound_streams: self.max_negotiating_inbound_streams,
+            raw_messages: self.raw_messages.clone(),
+            upnp: self.upnp,
+            lazy_bootstrap: self.lazy_bootstrap,
+            proxy: self.proxy,
+            tor: self.tor,
+            onion_address: self.onion_address.clone(),
+            security: self.security,
+            address_family: self.address_family,
+            inbound_middleware: self.inbound_middleware.clone(),
+            outbound_middleware: self.outbound_middleware.clone(),
+            connection_limits: self.connection_limits.clone(),
+            max_connections_per_ip: self.max_connections_per_ip,
+            gate_rules: self.gate_rules.clone(),
+            blocklist: self.blocklist.clone(),
+            alert_thresholds: self.alert_thresholds,
+            status_interval: self.status_interval,
+            mesh_ready_peers: self.mesh_ready_peers,
+            mesh_ready_wait: self.mesh_ready_wait,
+        }
+    }
+}
+
+// Limits of the circuit relay server, unset ones keep the libp2p defaults
+#[derive(Clone, Copy, Default)]
+pub(crate) struct RelayLimits {
+    pub(crate) max_reservations: Option<usize>,
+    pub(crate) max_circuits: Option<usize>,
+    pub(crate) max_circuit_duration: Option<Duration>,
+    pub(crate) max_circuit_bytes: Option<u64>,
+}
+
+impl RelayLimits {
+    pub(crate) fn config(&self) -> relay::Config {
+        let mut config = relay::Config::default();
+        if let Some(max_reservations) = self.max_reservations {
+            config.max_reservations = max_reservations;
+        }
+        if let Some(max_circuits) = self.max_circuits {
+            config.max_circuits = max_circuits;
+        }
+        if let Some(max_circuit_duration) = self.max_circuit_duration {
+            config.max_circuit_duration = max_circuit_duration;
+        }
+        if let Some(max_circuit_bytes) = self.max_circuit_bytes {
+            config.max_circuit_bytes = max_circuit_bytes;
+        }
+        config
+    }
+}
+
+impl Splash {
+    pub fn new() -> Splash {
+        let (submission_sender, submission_receiver) =
+            tokio::sync::mpsc::channel::<Submission>(100);
+        let (command_sender, command_receiver) = tokio::sync::mpsc::channel::<Command>(10);
+        let (raw_messages, _) = broadcast::channel(RAW_MESSAGE_BUFFER);
+
+        Splash {
+            known_peers: Vec::new(),
+            fallback_peers: Vec::new(),
+            cluster_service: None,
+            listen_addresses: Vec::new(),
+            internal_listen_addresses: Vec::new(),
+            external_addresses: Vec::new(),
+            advertise_map: Vec::new(),
+            private_addresses: false,
+            keys: identity::Keypair::generate_ed25519(),
+            network_name: "splash".to_string(),
+            namespace: None,
+            compression: OfferCompression::Off,
+            submission: submission_sender,
+            submission_receiver: Some(submission_receiver),
+            commands: command_sender,
+            command_receiver: Some(command_receiver),
+            restored_state: None,
+            chaos: chaos::ChaosConfig::default(),
+            transport: transport::TransportKind::default(),
+            websocket_tls: None,
+            auth_token: None,
+            dedup: DedupConfig::default(),
+            mesh: MeshConfig::default(),
+            peer_scoring: Some(PeerScoring::default()),
+            target_peers: DEFAULT_TARGET_PEERS,
+            introducer: false,
+            min_peers: None,
+            max_peers: None,
+            pending: None,
+            publish_retry_max_age: None,
+            trusted_relays: Vec::new(),
+            operator: None,
+            circuit_relays: Vec::new(),
+            circuit_relay_server: false,
+            canary_responder: false,
+            sync_window: None,
+            backfill: None,
+            relay_limits: RelayLimits::default(),
+            handshake_timeout: DEFAULT_HANDSHAKE_TIMEOUT,
+            dial_timeout: DEFAULT_DIAL_TIMEOUT,
+            dial_concurrency_factor: NonZeroU8::new(DEFAULT_DIAL_CONCURRENCY_FACTOR)
+                .expect("dial concurrency factor is non-zero"),
+            rate_limits: throttle::RateLimits::default(),
+            publish_rate: None,
+            inbound_rate: (DEFAULT_INBOUND_OFFER_RATE, DEFAULT_INBOUND_OFFER_BURST),
+            propagation_log: None,
+            publish_versions: vec![1],
+            topics: Vec::new(),
+            max_negotiating_inbound_streams: DEFAULT_MAX_NEGOTIATING_INBOUND_STREAMS,
+            raw_messages,
+            upnp: true,
+            lazy_bootstrap: false,
+            proxy: None,
+            tor: false,
+            onion_address: None,
+            security: SecurityUpgrades::default(),
+            address_family: AddressFamily::default(),
+            inbound_middleware: Vec::new(),
+            outbound_middleware: Vec::new(),
+            connection_limits: connection_limits::ConnectionLimits::default(),
+            max_connections_per_ip: None,
+            gate_rules: gate::Rules::default(),
+            blocklist: None,
+            alert_thresholds: AlertThresholds::default(),
+            status_interval: None,
+            mesh_ready_peers: DEFAULT_MESH_READY_PEERS,
+            mesh_ready_wait: None,
+        }
+    }
+
+    pub fn validate_offer(offer: &str) -> Result<(), SplashError> {
+        if offer.len() > MAX_OFFER_SIZE {
+            return Err(SplashError::OfferTooLarge(MAX_OFFER_SIZE));
+        }
+
+        if !offer.starts_with("offer1") || !offer::is_bech32_offer(offer) {
+            return Err(SplashError::InvalidOfferFormat);
+        }
+
+        // TODO: more validations?
+
+        Ok(())
+    }
+
+    pub async fn broadcast_offer(&self, offer: &str) -> Result<(), SplashError> {
+        self.submit(offer, OfferMetadata::default(), None, None)
+            .await
+    }
+
+    /// Like `broadcast_offer`, with metadata for the v2 offers topic and an optional TTL as in
+    /// `broadcast_offer_with_ttl`. Nodes on the v1 topic only get the bare offer, and so does everyone
+    /// for offers large enough to be chunked, chunks carry no envelope
+    pub async fn broadcast_offer_with_metadata(
+        &self,
+        offer: &str,
+        metadata: OfferMetadata,
+        ttl: Option<Duration>,
+    ) -> Result<(), SplashError> {
+        self.submit(offer, metadata, None, ttl).await
+    }
+
+    /// Like `broadcast_offer`, but the offer is dropped if it can't be published within the TTL,
+    /// e.g. while the node has no peers, instead of being published late from the retry queue
+    pub async fn broadcast_offer_with_ttl(
+        &self,
+        offer: &str,
+        ttl: Duration,
+    ) -> Result<(), SplashError> {
+        self.submit(offer, OfferMetadata::default(), None, Some(ttl))
+            .await
+    }
+
+    /// Like `broadcast_offer`, but resolves once the offer was handed to gossipsub
+    pub async fn broadcast_offer_and_wait(
+        &self,
+        offer: &str,
+    ) -> Result<PublishReceipt, SplashError> {
+        self.submit_and_wait(offer, OfferMetadata::default(), None)
+            .await
+    }
+
+    /// Like `broadcast_offer_and_wait`, with metadata as in `broadcast_offer_with_metadata`
+    pub async fn broadcast_offer_and_wait_with_metadata(
+        &self,
+        offer: &str,
+        metadata: OfferMetadata,
+        ttl: Option<Duration>,
+    ) -> Result<PublishReceipt, SplashError> {
+        self.submit_and_wait(offer, metadata, ttl).await
+    }
+
+    /// Like `broadcast_offer_and_wait`, with a TTL as in `broadcast_offer_with_ttl`
+    pub async fn broadcast_offer_and_wait_with_ttl(
+        &self,
+        offer: &str,
+        ttl: Duration,
+    ) -> Result<PublishReceipt, SplashError> {
+        self.submit_and_wait(offer, OfferMetadata::default(), Some(ttl))
+            .await
+    }
+
+    /// Like `broadcast_offer_and_wait`, but fails if the offer didn't reach any mesh peer
+    pub async fn broadcast_offer_and_propagate(
+        &self,
+        offer: &str,
+    ) -> Result<PublishReceipt, SplashError> {
+        propagated(
+            self.submit_and_wait(offer, OfferMetadata::default(), None)
+                .await?,
+        )
+    }
+
+    /// Like `broadcast_offer_and_propagate`, with metadata as in `broadcast_offer_with_metadata`
+    pub async fn broadcast_offer_and_propagate_with_metadata(
+        &self,
+        offer: &str,
+        metadata: OfferMetadata,
+        ttl: Option<Duration>,
+    ) -> Result<PublishReceipt, SplashError> {
+        propagated(self.submit_and_wait(offer, metadata, ttl).await?)
+    }
+
+    /// Like `broadcast_offer_and_propagate`, with a TTL as in `broadcast_offer_with_ttl`
+    pub async fn broadcast_offer_and_propagate_with_ttl(
+        &self,
+        offer: &str,
+        ttl: Duration,
+    ) -> Result<PublishReceipt, SplashError> {
+        propagated(
+            self.submit_and_wait(offer, OfferMetadata::default(), Some(ttl))
+                .await?,
+        )
+    }
+
+    async fn submit_and_wait(
+        &self,
+        offer: &str,
+        metadata: OfferMetadata,
+        ttl: Option<Duration>,
+    ) -> Result<PublishReceipt, SplashError> {
+        let (receipt_sender, receipt_receiver) = oneshot::channel();
+
+        self.submit(offer, metadata, Some(receipt_sender), ttl)
+            .await?;
+
+        receipt_receiver
+            .await
+            .map_err(|_| SplashError::NotRunning)?
+    }
+
+    async fn submit(
+        &self,
+        offer: &str,
+        mut metadata: OfferMetadata,
+        receipt: Option<oneshot::Sender<Result<PublishReceipt, SplashError>>>,
+        ttl: Option<Duration>,
+    ) -> Result<(), SplashError> {
+        let offer = if self.outbound_middleware.is_empty() {
+            offer.to_string()
+        } else {
+            let offer = OutboundOffer {
+                offer: offer.to_string(),
+                annotations: BTreeMap::new(),
+            };
+            middleware::run_outbound(&self.outbound_middleware, offer)
+                .await
+                .map_err(SplashError::Rejected)?
+                .offer
+        };
+        let offer = offer.as_str();
+
+        // Validated after the middleware, which may have normalized the offer
+        Splash::validate_offer(offer)?;
+
+        // Offers over the publish rate wait in the submission channel, once it's full they are refused
+        if self.publish_rate.is_some() && self.submission.capacity() == 0 {
+            return Err(SplashError::RateLimited);
+        }
+
+        // A TTL doubles as the expiry hint, unless one was given
+        if metadata.expires_at.is_none() {
+            metadata.expires_at = ttl.map(|ttl| queue::unix_timestamp() + ttl.as_secs());
+        }
+
+        // Persist before queueing, so an accepted offer survives a crash
+        if let Some(pending) = self.pending.as_ref() {
+            pending
+                .lock()
+                .expect("pending queue lock poisoned")
+                .push(
+                    offer,
+                    ttl.map(|ttl| queue::unix_timestamp() + ttl.as_secs()),
+                    &metadata,
+                )
+                .map_err(SplashError::QueueError)?;
+        }
+
+        self.submission
+            .send(Submission {
+                offer: offer.as_bytes().to_vec(),
+                metadata,
+                expires_at: ttl.map(|ttl| time::Instant::now() + ttl),
+                receipt,
+            })
+            .await
+            .map_err(|_| SplashError::SendError)
+    }
+
+    /// Broadcast an offer file, encoding it first if it holds a serialized SpendBundle
+    pub async fn broadcast_offer_file(
+        &self,
+        path: impl AsRef<std::path::Path>,
+    ) -> Result<(), SplashError> {
+        let bytes = tokio::fs::read(path).await?;
+        self.broadcast_offer(&offer_from_bytes(&bytes)?).await
+    }
+
+    /// Snapshot identity, address book and dedup state of the running node
+    pub async fn export_state(&self) -> Result<NodeState, SplashError> {
+        let (response_sender, response_receiver) = oneshot::channel();
+
+        self.commands
+            .send(Command::ExportState(response_sender))
+            .await
+            .map_err(|_| SplashError::NotRunning)?;
+
+        response_receiver.await.map_err(|_| SplashError::NotRunning)
+    }
+
+    pub fn network_name(&self) -> &str {
+        &self.network_name
+    }
+
+    /// Gossipsub topic offers are published on, the first custom topic if there are any
+    pub fn offers_topic(&self) -> String {
+        match self.topics.first() {
+            Some(topic) => topic.clone(),
+            None => self.versioned_offers_topic(1),
+        }
+    }
+
+    /// Gossipsub topic of the given offers format version, e.g. /splash/offers/2
+    pub fn versioned_offers_topic(&self, version: u32) -> String {
+        format!("{}/offers/{}", self.protocol_prefix(), version)
+    }
+
+    /// Gossipsub topic zstd compressed offers are published on
+    pub fn compressed_offers_topic(&self) -> String {
+        format!("{}/offers-zstd/1", self.protocol_prefix())
+    }
+
+    /// Gossipsub topic of notices about offers, e.g. cancellations
+    pub fn control_topic(&self) -> String {
+        format!("{}/control/1", self.protocol_prefix())
+    }
+
+    /// Gossipsub topic of offer status changes observed on-chain, e.g. taken offers
+    pub fn status_topic(&self) -> String {
+        format!("{}/status/1", self.protocol_prefix())
+    }
+
+    /// Gossipsub topic of `splash selftest` canaries, separate so they never reach offer consumers
+    pub fn selftest_topic(&self) -> String {
+        format!("{}/selftest/1", self.protocol_prefix())
+    }
+
+    /// Gossipsub topic the chunks of offers too large for a single message are published on
+    pub fn chunked_offers_topic(&self) -> String {
+        format!("{}/offer-chunks/1", self.protocol_prefix())
+    }
+
+    // Optional features announced to peers in the identify agent version
+    pub(crate) fn capabilities(&self) -> Vec<&'static str> {
+        let mut capabilities = vec![capabilities::CHUNKS, capabilities::CONTROL];
+        if self.topics.is_empty() {
+            capabilities.push(capabilities::OFFERS_V2);
+        }
+        if self.compression != OfferCompression::Off {
+            capabilities.push(capabilities::COMPRESSION);
+        }
+        if self.circuit_relay_server {
+            capabilities.push(capabilities::RELAY_SERVER);
+        }
+        if self.canary_responder {
+            capabilities.push(capabilities::CANARY_RESPONDER);
+        }
+        if self.sync_window.is_some() {
+            capabilities.push(capabilities::SYNC);
+        }
+        capabilities
+    }
+
+    // Prefix of the topic and all protocol names, e.g. /splash or /acme/splash with a namespace
+    pub(crate) fn protocol_prefix(&self) -> String {
+        match self.namespace.as_ref() {
+            Some(namespace) => format!("/{}/{}", namespace, self.network_name),
+            None => format!("/{}", self.network_name),
+        }
+    }
+
+    /// Open connections with their negotiated protocols, for troubleshooting
+    pub async fn connections(&self) -> Result<Vec<ConnectionInfo>, SplashError> {
+        let (response_sender, response_receiver) = oneshot::channel();
+
+        self.commands
+            .send(Command::Connections(response_sender))
+            .await
+            .map_err(|_| SplashError::NotRunning)?;
+
+        response_receiver.await.map_err(|_| SplashError::NotRunning)
+    }
+
+    /// First-seen time, duplicates and delivering peers of recently received offers, empty unless enabled
+    /// with `with_propagation_analytics`
+    pub async fn propagation(&self) -> Result<Vec<PropagationRecord>, SplashError> {
+        let (response_sender, response_receiver) = oneshot::channel();
+
+        self.commands
+            .send(Command::Propagation(response_sender))
+            .await
+            .map_err(|_| SplashError::NotRunning)?;
+
+        response_receiver.await.map_err(|_| SplashError::NotRunning)
+    }
+
+    /// Announce that an offer was cancelled, identified by the hex SHA-256 of the offer
+    pub async fn broadcast_cancellation(&self, offer_id: &str) -> Result<(), SplashError> {
+        let message = control::ControlMessage::OfferCancelled {
+            offer_id: control::offer_id(offer_id)?,
+        };
+        self.publish_control(message).await
+    }
+
+    /// Announce a change of an offer's on-chain state, identified by the hex SHA-256 of the offer
+    pub async fn submit_offer_status(
+        &self,
+        offer_id: &str,
+        status: OfferStatus,
+    ) -> Result<(), SplashError> {
+        let message = control::ControlMessage::OfferStatus {
+            offer_id: control::offer_id(offer_id)?,
+            status,
+        };
+        self.publish_control(message).await
+    }
+
+    async fn publish_control(&self, message: control::ControlMessage) -> Result<(), SplashError> {
+        let (response_sender, response_receiver) = oneshot::channel();
+
+        self.commands
+            .send(Command::PublishControl(message, response_sender))
+            .await
+            .map_err(|_| SplashError::NotRunning)?;
+
+        response_receiver
+            .await
+            .map_err(|_| SplashError::NotRunning)?
+    }
+
+    /// Fetch an offer we missed, identified by the hex SHA-256 of the offer, from a sync responder that
+    /// announced it in the DHT. Only the newest offers within the responders' window can be fetched
+    pub async fn fetch_offer(&self, offer_id: &str) -> Result<String, SplashError> {
+        let offer_id = control::offer_id(offer_id)?;
+        let (response_sender, response_receiver) = oneshot::channel();
+
+        self.commands
+            .send(Command::FetchOffer(offer_id, response_sender))
+            .await
+            .map_err(|_| SplashError::NotRunning)?;
+
+        response_receiver
+            .await
+            .map_err(|_| SplashError::NotRunning)?
+    }
+
+    /// Resolves with the mesh size once the offers topic has the peers set by `with_mesh_ready_peers`,
+    /// right away if it already had them
+    pub async fn wait_for_mesh(&self) -> Result<usize, SplashError> {
+        let (response_sender, response_receiver) = oneshot::channel();
+
+        self.commands
+            .send(Command::WaitForMesh(response_sender))
+            .await
+            .map_err(|_| SplashError::NotRunning)?;
+
+        response_receiver.await.map_err(|_| SplashError::NotRunning)
+    }
+
+    /// Send a canary to a connected peer running a canary responder, resolves with the round trip time
+    pub async fn canary(&self, peer_id: PeerId) -> Result<Duration, SplashError> {
+        let (response_sender, response_receiver) = oneshot::channel();
+
+        self.commands
+            .send(Command::Canary(peer_id, response_sender))
+            .await
+            .map_err(|_| SplashError::NotRunning)?;
+
+        response_receiver
+            .await
+            .map_err(|_| SplashError::NotRunning)?
+    }
+
+    /// Stop accepting offers and give queued publishes and relay pushes up to `drain` to complete, then stop
+    /// the node. The summary reports what couldn't be delivered in time
+    pub async fn shutdown(&self, drain: Duration) -> Result<ShutdownSummary, SplashError> {
+        let (response_sender, response_receiver) = oneshot::channel();
+
+        self.commands
+            .send(Command::Shutdown(drain, response_sender))
+            .await
+            .map_err(|_| SplashError::NotRunning)?;
+
+        response_receiver.await.map_err(|_| SplashError::NotRunning)
+    }
+
+    pub fn with_listen_addresses(mut self, listen_addresses: Vec<Multiaddr>) -> Self {
+        self.listen_addresses = listen_addresses;
+        self
+    }
+
+    /// Listen on these addresses too, but never advertise them to the network
+    pub fn with_internal_listen_addresses(mut self, listen_addresses: Vec<Multiaddr>) -> Self {
+        self.internal_listen_addresses = listen_addresses;
+        self
+    }
+
+    /// Accept and advertise loopback and private addresses, for networks on a single host or LAN
+    pub fn with_private_addresses(mut self) -> Self {
+        self.private_addresses = true;
+        self
+    }
+
+    /// Advertise exactly these addresses, e.g. behind a port forward or with a static public IP.
+    /// Addresses observed by peers are then neither probed nor advertised
+    pub fn with_external_addresses(mut self, external_addresses: Vec<Multiaddr>) -> Self {
+        self.external_addresses = external_addresses;
+        self
+    }
+
+    /// Advertise a listen address as a different one, e.g. the address and port a NAT or a Kubernetes
+    /// Service forwards to it. A listen address with an unspecified IP maps all interfaces it binds to
+    pub fn with_advertise_map(mut self, advertise_map: Vec<(Multiaddr, Multiaddr)>) -> Self {
+        self.advertise_map = advertise_map;
+        self
+    }
+
+    pub fn with_known_peers(mut self, known_peers: Vec<Multiaddr>) -> Self {
+        self.known_peers = known_peers;
+        self
+    }
+
+    /// Start even if no bootstrap peer is known or reachable and keep bootstrapping in the background,
+    /// for embedded nodes that have to come up without connectivity
+    pub fn with_lazy_bootstrap(mut self) -> Self {
+        self.lazy_bootstrap = true;
+        self
+    }
+
+    /// Peer with the other replicas of a cluster, found via DNS like a Kubernetes headless Service. With a
+    /// port the name's A/AAAA records are dialed on that port, without one its SRV records are used
+    pub fn with_cluster_discovery(mut self, service: impl Into<String>, port: Option<u16>) -> Self {
+        self.cluster_service = Some((service.into(), port));
+        self
+    }
+
+    /// Bootstrap from these peers, in addition to the compiled-in ones, if the DNS introducer can't be resolved
+    pub fn with_fallback_peers(mut self, fallback_peers: Vec<Multiaddr>) -> Self {
+        self.fallback_peers = fallback_peers;
+        self
+    }
+
+    pub fn with_keys(mut self, keys: identity::Keypair) -> Self {
+        self.keys = keys;
+        self
+    }
+
+    /// Continue from a snapshot taken with `export_state`, this replaces the keys
+    pub fn with_state(mut self, state: NodeState) -> Result<Self, identity::DecodingError> {
+        self.keys = state.keys()?;
+        self.restored_state = Some(state);
+        Ok(self)
+    }
+
+    pub fn with_testnet(mut self) -> Self {
+        self.network_name = "splash-testnet".to_string();
+        self
+    }
+
+    /// Gossip offers zstd compressed on their own topic, in addition to or instead of the plain topic
+    pub fn with_offer_compression(mut self, compression: OfferCompression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Prefix the topic and all protocol names, e.g. /acme/splash/offers/1, so nodes of a separate
+    /// deployment never talk to the public network. The DNS introducer is still looked up by network name
+    pub fn with_topic_namespace(mut self, namespace: &str) -> Self {
+        let namespace = namespace.trim_matches('/');
+        self.namespace = (!namespace.is_empty()).then(|| namespace.to_string());
+        self
+    }
+
+    /// Serve /wss listen addresses with this PEM certificate chain and private key
+    pub fn with_websocket_tls(
+        mut self,
+        cert_file: impl AsRef<std::path::Path>,
+        key_file: impl AsRef<std::path::Path>,
+    ) -> std::io::Result<Self> {
+        self.websocket_tls = Some(transport::load_websocket_tls(
+            cert_file.as_ref(),
+            key_file.as_ref(),
+        )?);
+        Ok(self)
+    }
+
+    /// Require peers to prove possession of a shared token before their gossip is accepted
+    pub fn with_auth_token(mut self, token: String) -> Self {
+        self.auth_token = Some(token);
+        self
+    }
+
+    pub fn with_dedup(mut self, dedup: DedupConfig) -> Self {
+        self.dedup = dedup;
+        self
+    }
+
+    pub fn with_mesh_config(mut self, mesh: MeshConfig) -> Self {
+        self.mesh = mesh;
+        self
+    }
+
+    /// Gossipsub peer scoring is enabled by default, invalid offers lower the score of the peer sending them
+    pub fn with_peer_scoring(mut self, scoring: PeerScoring) -> Self {
+        self.peer_scoring = Some(scoring);
+        self
+    }
+
+    pub fn without_peer_scoring(mut self) -> Self {
+        self.peer_scoring = None;
+        self
+    }
+
+    /// Tune the node for bootstrap duty: Kademlia always answers queries, even before the node
+    /// knows it is reachable, and discovery keeps at least 100 peers connected
+    pub fn with_introducer(mut self) -> Self {
+        self.introducer = true;
+        self
+    }
+
+    /// Peer count below which the DHT is queried for new peers every 10 seconds
+    pub fn with_target_peers(mut self, target_peers: usize) -> Self {
+        self.target_peers = target_peers;
+        self
+    }
+
+    /// Dial peers from the routing table and restored address book while fewer gossipsub peers are connected
+    pub fn with_min_peers(mut self, min_peers: usize) -> Self {
+        self.min_peers = Some(min_peers);
+        self
+    }
+
+    /// Disconnect peers outside the mesh or with the lowest score while more gossipsub peers are connected
+    pub fn with_max_peers(mut self, max_peers: usize) -> Self {
+        self.max_peers = Some(max_peers);
+        self
+    }
+
+    /// Persist accepted but unpublished offers to this file and retry them, also after a restart
+    pub fn with_submission_queue(mut self, path: impl Into<PathBuf>) -> std::io::Result<Self> {
+        let queue = queue::PendingQueue::load(path.into())?;
+        self.pending = Some(Arc::new(Mutex::new(queue)));
+        Ok(self)
+    }
+
+    /// Keep offers that couldn't be published for lack of peers, e.g. right after startup, and retry them
+    /// as the mesh forms. Offers still unpublished after `max_age` are dropped as `OfferExpired`. Without
+    /// a submission queue file the retried offers are kept in memory only
+    pub fn with_publish_retry(mut self, max_age: Duration) -> Self {
+        self.publish_retry_max_age = Some(max_age);
+        if self.pending.is_none() {
+            self.pending = Some(Arc::new(Mutex::new(queue::PendingQueue::in_memory())));
+        }
+        self
+    }
+
+    /// Additionally push submitted offers directly to these peers, independent of the mesh
+    pub fn with_trusted_relays(mut self, trusted_relays: Vec<Multiaddr>) -> Self {
+        self.trusted_relays = trusted_relays;
+        self
+    }
+
+    /// Announce an operator label and optional contact in the identify agent version
+    pub fn with_operator(mut self, name: &str, contact: Option<&str>) -> Self {
+        let sanitize = |s: &str| -> String {
+            s.chars()
+                .filter(|c| !c.is_control() && !matches!(c, '(' | ')' | ';'))
+                .collect()
+        };
+
+        self.operator = Some(match contact {
+            Some(contact) => format!("{}; {}", sanitize(name), sanitize(contact)),
+            None => sanitize(name),
+        });
+        self
+    }
+
+    /// Be reachable through these circuit relay v2 servers and upgrade to direct connections via hole punching
+    pub fn with_circuit_relays(mut self, relays: Vec<Multiaddr>) -> Self {
+        self.circuit_relays = relays;
+        self
+    }
+
+    /// Relay connections for NATed peers, only useful on publicly reachable nodes
+    pub fn with_circuit_relay_server(mut self) -> Self {
+        self.circuit_relay_server = true;
+        self
+    }
+
+    /// Answer canaries of other nodes checking their connectivity, see `Splash::canary`. Each peer is
+    /// answered at most once every 10 seconds
+    pub fn with_canary_responder(mut self) -> Self {
+        self.canary_responder = true;
+        self
+    }
+
+    /// Keep the offers of the last `window` and answer the sync requests of starting nodes with them,
+    /// each peer at most once a minute
+    pub fn with_sync_responder(mut self, window: Duration) -> Self {
+        self.sync_window = Some(window);
+        self
+    }
+
+    /// On startup, ask the first sync responders we identify for the offers of the last `window` and
+    /// emit them as `OfferReceived`, unless they were already received
+    pub fn with_backfill(mut self, window: Duration) -> Self {
+        self.backfill = Some(window);
+        self
+    }
+
+    /// Number of peers the relay server holds a slot for at a time
+    pub fn with_relay_max_reservations(mut self, max_reservations: usize) -> Self {
+        self.relay_limits.max_reservations = Some(max_reservations);
+        self
+    }
+
+    /// Number of connections relayed at a time
+    pub fn with_relay_max_circuits(mut self, max_circuits: usize) -> Self {
+        self.relay_limits.max_circuits = Some(max_circuits);
+        self
+    }
+
+    /// Relayed connections are closed after this time, hole punching usually replaces them sooner
+    pub fn with_relay_max_circuit_duration(mut self, max_circuit_duration: Duration) -> Self {
+        self.relay_limits.max_circuit_duration = Some(max_circuit_duration);
+        self
+    }
+
+    /// Relayed connections are closed after transferring this many bytes in each direction
+    pub fn with_relay_max_circuit_bytes(mut self, max_circuit_bytes: u64) -> Self {
+        self.relay_limits.max_circuit_bytes = Some(max_circuit_bytes);
+        self
+    }
+
+    /// Drop connections that don't complete the noise and yamux handshake within this time
+    pub fn with_handshake_timeout(mut self, timeout: Duration) -> Self {
+        self.handshake_timeout = timeout;
+        self
+    }
+
+    /// Give up on a TCP connect after this time, so stale DHT addresses don't hold up bootstrap
+    pub fn with_dial_timeout(mut self, timeout: Duration) -> Self {
+        self.dial_timeout = timeout;
+        self
+    }
+
+    /// Addresses of a single peer that are dialed at the same time
+    pub fn with_dial_concurrency_factor(mut self, factor: NonZeroU8) -> Self {
+        self.dial_concurrency_factor = factor;
+        self
+    }
+
+    /// Dials in progress across all peers, further dials are denied until some complete
+    pub fn with_max_pending_dials(mut self, max: u32) -> Self {
+        self.connection_limits = self.connection_limits.with_max_pending_outgoing(Some(max));
+        self
+    }
+
+    /// Throttle what each connection sends, in bytes per second
+    pub fn with_max_upload_rate(mut self, bytes_per_second: u64) -> Self {
+        self.rate_limits.upload = Some(bytes_per_second);
+        self
+    }
+
+    /// Throttle what each connection receives, in bytes per second
+    pub fn with_max_download_rate(mut self, bytes_per_second: u64) -> Self {
+        self.rate_limits.download = Some(bytes_per_second);
+        self
+    }
+
+    /// Publish at most this many offers per second on average, with bursts of up to `burst` offers.
+    /// Protects the network from integrations submitting thousands of offers at once
+    pub fn with_publish_rate_limit(mut self, offers_per_second: f64, burst: u32) -> Self {
+        self.publish_rate = Some((offers_per_second, burst));
+        self
+    }
+
+    /// Accept at most this many gossiped offers per second from each peer, with bursts of up to `burst`.
+    /// Excess offers are rejected, which also lowers the peer's score. Defaults to 20 per second, bursts of 100
+    pub fn with_inbound_rate_limit(mut self, offers_per_second: f64, burst: u32) -> Self {
+        self.inbound_rate = (offers_per_second, burst);
+        self
+    }
+
+    /// Offers topic versions to publish on, all versions are subscribed to regardless.
+    /// Publishing on the old and the new version migrates without losing nodes of either
+    pub fn with_publish_topic_versions(mut self, versions: Vec<u32>) -> Self {
+        self.publish_versions = versions;
+        self
+    }
+
+    /// Gossip offers on these topics instead of the versioned offers topics, e.g. for experiments.
+    /// The names are used as given, without the namespace prefix
+    pub fn with_topics(mut self, topics: Vec<String>) -> Self {
+        self.topics = topics;
+        self
+    }
+
+    /// Track how the last `capacity` received offers propagated to us, see `Splash::propagation`
+    pub fn with_propagation_analytics(mut self, capacity: usize) -> Self {
+        self.propagation_log = Some(capacity);
+        self
+    }
+
+    /// Limit of inbound substreams per connection that are still negotiating their protocol
+    pub fn with_max_negotiating_inbound_streams(mut self, max: usize) -> Self {
+        self.max_negotiating_inbound_streams = max;
+        self
+    }
+
+    /// Connection limits are unbounded unless set, connections over a limit are denied
+    pub fn with_max_inbound_connections(mut self, max: u32) -> Self {
+        self.connection_limits = self
+            .connection_limits
+            .with_max_established_incoming(Some(max));
+        self
+    }
+
+    pub fn with_max_outbound_connections(mut self, max: u32) -> Self {
+        self.connection_limits = self
+            .connection_limits
+            .with_max_established_outgoing(Some(max));
+        self
+    }
+
+    pub fn with_max_connections_per_peer(mut self, max: u32) -> Self {
+        self.connection_limits = self
+            .connection_limits
+            .with_max_established_per_peer(Some(max));
+        self
+    }
+
+    /// Counts all connections with a remote IP, but only denies inbound ones
+    pub fn with_max_connections_per_ip(mut self, max: usize) -> Self {
+        self.max_connections_per_ip = Some(max);
+        self
+    }
+
+    /// Only connect with hosts in these networks, in both directions
+    pub fn with_allowed_cidrs(mut self, cidrs: Vec<Cidr>) -> Self {
+        self.gate_rules.allowed_cidrs = cidrs;
+        self
+    }
+
+    /// Never connect with hosts in these networks, in both directions
+    pub fn with_denied_cidrs(mut self, cidrs: Vec<Cidr>) -> Self {
+        self.gate_rules.denied_cidrs = cidrs;
+        self
+    }
+
+    pub fn with_denied_peers(mut self, peers: Vec<PeerId>) -> Self {
+        self.gate_rules.denied_peers = peers.into_iter().collect();
+        self
+    }
+
+    /// Deny the networks and peers of a community blocklist, fetched hourly from `url` in the deny file
+    /// format. `<url>.sig` must hold the hex encoded signature of the list by `signer`, an ed25519 peer ID
+    pub fn with_blocklist_subscription(mut self, url: String, signer: PeerId) -> Self {
+        self.blocklist = Some((url, signer));
+        self
+    }
+
+    /// Emit `PeerCountLow`, `PeerCountRecovered` and `OfferFlowStalled` events per these thresholds
+    pub fn with_alert_thresholds(mut self, thresholds: AlertThresholds) -> Self {
+        self.alert_thresholds = thresholds;
+        self
+    }
+
+    /// Emit a `Status` summary of peers, mesh, offer rate, pending submissions and the dedup cache every
+    /// `interval`, one heartbeat instead of reconstructing the state from the other events
+    pub fn with_status_interval(mut self, interval: Duration) -> Self {
+        self.status_interval = Some(interval);
+        self
+    }
+
+    /// Mesh peers on the offers topic needed for `MeshReady` and `wait_for_mesh`, 1 by default
+    pub fn with_mesh_ready_peers(mut self, peers: usize) -> Self {
+        self.mesh_ready_peers = peers;
+        self
+    }
+
+    /// Let `build` wait up to `timeout` for the mesh to be ready, so offers submitted right after it
+    /// returns are published to peers. The node starts anyway once the timeout passed
+    pub fn with_wait_for_mesh(mut self, timeout: Duration) -> Self {
+        self.mesh_ready_wait = Some(timeout);
+        self
+    }
+
+    /// Ask the router to forward our listen ports via UPnP, enabled by default
+    pub fn with_upnp(mut self, enabled: bool) -> Self {
+        self.upnp = enabled;
+        self
+    }
+
+    /// Security protocols offered on connections, both Noise and TLS by default
+    pub fn with_security(mut self, security: SecurityUpgrades) -> Self {
+        self.security = security;
+        self
+    }
+
+    /// Only listen on, dial and remember addresses of this IP family
+    pub fn with_address_family(mut self, address_family: AddressFamily) -> Self {
+        self.address_family = address_family;
+        self
+    }
+
+    /// Runs between receiving an offer and emitting `OfferReceived`, in the order registered.
+    /// Middleware can inspect, annotate or rewrite the offer, or drop it by returning `None`,
+    /// dropped gossip is ignored rather than rejected so the sender isn't penalized
+    pub fn with_inbound_middleware<F, Fut>(mut self, middleware: F) -> Self
+    where
+        F: Fn(InboundOffer) -> Fut + Send + Sync + 'static,
+        Fut: std::future::Future<Output = Option<InboundOffer>> + Send + 'static,
+    {
+        self.inbound_middleware
+            .push(middleware::inbound(middleware));
+        self
+    }
+
+    /// Runs on every submitted offer before it's validated, queued and published, in the order registered.
+    /// Middleware can normalize, tag or check the offer, an `Err` rejects the submission with that reason
+    pub fn with_outbound_middleware<F, Fut>(mut self, middleware: F) -> Self
+    where
+        F: Fn(OutboundOffer) -> Fut + Send + Sync + 'static,
+        Fut: std::future::Future<Output = Result<OutboundOffer, String>> + Send + 'static,
+    {
+        self.outbound_middleware
+            .push(middleware::outbound(middleware));
+        self
+    }
+
+    /// Dial all TCP connections and the DNS introducer lookup through this SOCKS5 proxy
+    pub fn with_proxy(mut self, proxy: SocketAddr) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    /// Privacy mode: dial everything through Tor's SOCKS port, never reveal our IP and only advertise the
+    /// onion service address, which Tor has to forward to one of our listen addresses
+    pub fn with_tor(mut self, socks_proxy: SocketAddr, onion_address: Option<Multiaddr>) -> Self {
+        self.proxy = Some(socks_proxy);
+        self.tor = true;
+        self.onion_address = onion_address;
+        self
+    }
+
+    #[cfg(feature = "testing")]
+    pub fn with_chaos(mut self, chaos: chaos::ChaosConfig) -> Self {
+        self.chaos = chaos;
+        self
+    }
+
+    #[cfg(feature = "simulation")]
+    pub(crate) fn with_memory_transport(mut self) -> Self {
+        self.transport = transport::TransportKind::Memory;
+        self
+    }
+
+    pub async fn build(mut self) -> Result<SplashContext, Box<dyn std::error::Error>> {
+        let (tx, event_rx) = mpsc::channel(EVENT_BUFFER);
+        let mut event_tx = events::EventSender::new(tx);
+
+        // Check if known_peers is empty and resolve from DNS if necessary
+        let dns_introducers = self.known_peers.is_empty();
+        if dns_introducers {
+            match dns::resolve_introducers(self.network_name.clone(), self.proxy).await {
+                Ok(peers) => self.known_peers = peers,
+                Err(e) => {
+                    // Peers restored from a state file are another way into the network
+                    let restored_peers = self
+                        .restored_state
+                        .as_ref()
+                        .map_or(0, |state| state.peers.len());
+                    self.known_peers = dns::fallback_peers(&self.network_name)
+                        .into_iter()
+                        .chain(self.fallback_peers.iter().cloned())
+                        .collect();
+
+                    if self.known_peers.is_empty() && restored_peers == 0 && !self.lazy_bootstrap {
+                        return Err(format!("Failed to resolve peers from DNS: {}", e).into());
+                    }
+
+                    event_tx.send(SplashEvent::BootstrapDegraded {
+                        reason: e,
+                        fallback_peers: self.known_peers.len(),
+                        restored_peers,
+                    });
+                }
+            }
+        }
+
+        let family = self.address_family;
+        self.known_peers.retain(|addr| family.allows(addr));
+        if self.known_peers.is_empty() {
+            warn!("No known peer with a {:?} address", family);
+        }
+
+        let negotiated_security = transport::NegotiatedSecurity::default();
+        let connection_book = connections::ConnectionBook::default();
+
+        let offer_topics = self.offer_topics()?;
+        let behaviour_config = self.behaviour_config()?;
+        let swarm = libp2p::SwarmBuilder::with_existing_identity(self.keys.clone())
+            .with_tokio()
+            .with_other_transport(|key| {
+                let config = transport::TransportConfig {
+                    kind: self.transport,
+                    websocket_tls: self.websocket_tls.clone(),
+                    handshake_timeout: self.handshake_timeout,
+                    dial_timeout: self.dial_timeout,
+                    proxy: self.proxy,
+                    security: self.security,
+                    family: self.address_family,
+                    rate_limits: self.rate_limits,
+                };
+                transport::build_transport(key, config, negotiated_security.clone())
+            })?
+            .with_relay_client(noise::Config::new, yamux::Config::default)?
+            .with_behaviour(|key, relay_client| {
+                SplashBehaviour::new(behaviour_config, key, Some(relay_client))
+            })?
+            .with_swarm_config(|c| {
+                c.with_idle_connection_timeout(Duration::from_secs(60))
+                    .with_max_negotiating_inbound_streams(self.max_negotiating_inbound_streams)
+                    .with_dial_concurrency_factor(self.dial_concurrency_factor)
+            })
+            .build();
+
+        self.run(
+            swarm,
+            event_tx,
+            offer_topics,
+            negotiated_security,
+            connection_book,
+            dns_introducers,
+        )
+        .await?;
+
+        // Events meanwhile wait in the channel, beyond its capacity they are dropped and reported
+        if let Some(timeout) = self.mesh_ready_wait {
+            if time::timeout(timeout, self.wait_for_mesh()).await.is_err() {
+                warn!(
+                    "Offers topic mesh not ready after {}s, starting anyway",
+                    timeout.as_secs()
+                );
+            }
+        }
+
+        Ok(SplashContext {
+            node: self,
+            events: event_rx,
+        })
+    }
+
+    /// The behaviour this node runs, for embedders composing `SplashBehaviour` into their own swarm
+    pub fn behaviour_config(&self) -> Result<BehaviourConfig, String> {
+        let mut known_peers = Vec::new();
+        for addr in self.known_peers.iter() {
+            let Some(Protocol::P2p(peer_id)) = addr.iter().last() else {
+                return Err("Expect peer multiaddr to contain peer ID.".to_string());
+            };
+            known_peers.push((peer_id, addr.clone()));
+        }
+
+        if let Some(state) = self.restored_state.as_ref() {
+            for peer in state.peers.iter() {
+                for addr in peer
+                    .addresses
+                    .iter()
+                    .filter(|addr| self.address_family.allows(addr))
+                {
+                    known_peers.push((peer.peer_id, addr.clone()));
+                }
+            }
+        }
+
+        let use_relays = !self.circuit_relays.is_empty();
+
+        Ok(BehaviourConfig {
+            protocol_prefix: self.protocol_prefix(),
+            offers_topic: self.offers_topic(),
+            offer_topics: self.offer_topics()?.names(),
+            other_topics: vec![self.control_topic(), self.status_topic()],
+            mesh: self.mesh.clone(),
+            dedup: self.dedup.clone(),
+            peer_scoring: self.peer_scoring.clone(),
+            dht_server: self.introducer,
+            known_peers,
+            agent_version: capabilities::agent_version(
+                self.operator.as_deref(),
+                &self.capabilities(),
+            ),
+            auth: self.auth_token.is_some(),
+            circuit_relays: use_relays,
+            relay_server: self
+                .circuit_relay_server
+                .then(|| self.relay_limits.config()),
+            // Hole punching, AutoNAT and UPnP would all expose our IP address
+            hole_punching: use_relays && !self.tor,
+            autonat: !self.tor && self.external_addresses.is_empty(),
+            upnp: self.upnp && !self.tor,
+            canary_responder: self.canary_responder,
+            sync_responder: self.sync_window.is_some(),
+            connection_limits: self.connection_limits.clone(),
+            max_connections_per_ip: self.max_connections_per_ip,
+            allowed_cidrs: self.gate_rules.allowed_cidrs.clone(),
+            denied_cidrs: self.gate_rules.denied_cidrs.clone(),
+            denied_peers: self.gate_rules.denied_peers.clone(),
+        })
+    }
+
+    /// Handles offers topic messages like this node would, for embedders driving `SplashBehaviour`
+    pub fn inbound_pipeline(&self) -> Result<InboundPipeline, String> {
+        InboundPipeline::new(self)
+    }
+
+    // The offers topics per format version, compression and custom topics
+    pub(crate) fn offer_topics(&self) -> Result<topics::OfferTopics, String> {
+        if let Some(version) = self
+            .publish_versions
+            .iter()
+            .find(|version| !topics::VERSIONS.contains(version))
+        {
+            return Err(format!("Unknown offers topic version: {}", version));
+        }
+        if self.publish_versions.is_empty() && self.compression != OfferCompression::Only {
+            return Err("No offers topic version to publish on".to_string());
+        }
+
+        // Custom topics replace the versioned ones and are all published on
+        let plain_topics = if self.topics.is_empty() {
+            topics::VERSIONS
+                .iter()
+                .map(|version| {
+                    (
+                        self.versioned_offers_topic(*version),
+                        self.publish_versions.contains(version),
+                    )
+                })
+                .collect()
+        } else {
+            self.topics
+                .iter()
+                .map(|topic| (topic.clone(), true))
+                .collect()
+        };
+
+        let envelope_topic = self
+            .topics
+            .is_empty()
+            .then(|| self.versioned_offers_topic(topics::ENVELOPE_VERSION));
+
+        Ok(topics::OfferTopics::new(
+            plain_topics,
+            envelope_topic,
+            self.compressed_offers_topic(),
+            self.chunked_offers_topic(),
+            self.compression,
+        ))
+    }
+}
+
+// Publishing only counts as propagated if the offer reached at least one mesh peer
+fn propagated(receipt: PublishReceipt) -> Result<PublishReceipt, SplashError> {
+    if receipt.mesh_peers == 0 {
+        return Err(SplashError::NotPropagated);
+    }
+
+    Ok(receipt)
+}
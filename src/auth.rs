@@ -0,0 +1,114 @@
+use libp2p::request_response::{self, ProtocolSupport};
+use libp2p::{PeerId, StreamProtocol};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+use tokio::time::Instant;
+
+// Peers failing to prove the shared token within this time are disconnected
+const AUTH_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AuthChallenge {
+    nonce: Vec<u8>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AuthProof {
+    proof: Vec<u8>,
+}
+
+pub(crate) type Behaviour = request_response::json::Behaviour<AuthChallenge, AuthProof>;
+
+pub(crate) fn behaviour(protocol_prefix: &str) -> Behaviour {
+    request_response::json::Behaviour::new(
+        [(
+            StreamProtocol::try_from_owned(format!("{}/auth/1", protocol_prefix))
+                .expect("protocol name is valid"),
+            ProtocolSupport::Full,
+        )],
+        request_response::Config::default().with_request_timeout(AUTH_TIMEOUT),
+    )
+}
+
+// Tracks which peers proved possession of the shared token
+pub(crate) struct Authenticator {
+    token: String,
+    pending: HashMap<PeerId, (Vec<u8>, Instant)>,
+    authenticated: HashSet<PeerId>,
+}
+
+impl Authenticator {
+    pub(crate) fn new(token: String) -> Self {
+        Authenticator {
+            token,
+            pending: HashMap::new(),
+            authenticated: HashSet::new(),
+        }
+    }
+
+    pub(crate) fn challenge(&mut self, peer_id: PeerId) -> AuthChallenge {
+        let mut nonce = vec![0u8; 32];
+        rand::thread_rng().fill_bytes(&mut nonce);
+
+        self.pending
+            .insert(peer_id, (nonce.clone(), Instant::now() + AUTH_TIMEOUT));
+
+        AuthChallenge { nonce }
+    }
+
+    // The proof is bound to the answering peer, so it can't be relayed by a man in the middle
+    pub(crate) fn prove(&self, local_peer_id: &PeerId, challenge: &AuthChallenge) -> AuthProof {
+        AuthProof {
+            proof: digest(&self.token, &challenge.nonce, local_peer_id),
+        }
+    }
+
+    pub(crate) fn verify(&mut self, peer_id: PeerId, proof: &AuthProof) -> bool {
+        let Some((nonce, _)) = self.pending.remove(&peer_id) else {
+            return false;
+        };
+
+        if digest(&self.token, &nonce, &peer_id) != proof.proof {
+            return false;
+        }
+
+        self.authenticated.insert(peer_id);
+        true
+    }
+
+    pub(crate) fn is_authenticated(&self, peer_id: &PeerId) -> bool {
+        self.authenticated.contains(peer_id)
+    }
+
+    pub(crate) fn expired(&mut self) -> Vec<PeerId> {
+        let now = Instant::now();
+        let expired: Vec<PeerId> = self
+            .pending
+            .iter()
+            .filter(|(_, (_, deadline))| *deadline <= now)
+            .map(|(peer_id, _)| *peer_id)
+            .collect();
+
+        for peer_id in expired.iter() {
+            self.pending.remove(peer_id);
+        }
+
+        expired
+    }
+
+    pub(crate) fn forget(&mut self, peer_id: &PeerId) {
+        self.pending.remove(peer_id);
+        self.authenticated.remove(peer_id);
+    }
+}
+
+fn digest(token: &str, nonce: &[u8], peer_id: &PeerId) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update(token.as_bytes());
+    hasher.update(nonce);
+    hasher.update(peer_id.to_bytes());
+    hasher.finalize().to_vec()
+}
@@ -0,0 +1,241 @@
+use crate::{
+    auth, canary, dht, dialback, gate, limits, offer_hash, push, sync, topic_offer_hash, Cidr,
+    DedupConfig, MeshConfig, PeerScoring, Splash, MAX_MESSAGE_SIZE,
+};
+use libp2p::swarm::behaviour::toggle::Toggle;
+use libp2p::swarm::NetworkBehaviour;
+use libp2p::{
+    autonat, connection_limits, dcutr, gossipsub, identify, identity, kad, relay, upnp, Multiaddr,
+    PeerId, StreamProtocol,
+};
+use std::collections::HashSet;
+use std::io;
+use std::time::Duration;
+
+/// What `SplashBehaviour::new` builds, independent of a `Splash`. `Splash::behaviour_config` describes
+/// the behaviour a configured node would run, `Default` that of a node with the default options
+pub struct BehaviourConfig {
+    /// Prefix of all protocol names, e.g. /splash or /acme/splash with a namespace
+    pub protocol_prefix: String,
+    /// The original offers topic, its message ids are the plain offer hash
+    pub offers_topic: String,
+    /// Every offers topic to join, scored with peer scoring
+    pub offer_topics: Vec<String>,
+    /// Further topics to join, e.g. control and status
+    pub other_topics: Vec<String>,
+    pub mesh: MeshConfig,
+    pub dedup: DedupConfig,
+    pub peer_scoring: Option<PeerScoring>,
+    /// Answer DHT queries regardless of our reachability, for introducers
+    pub dht_server: bool,
+    /// Peers to bootstrap the DHT from
+    pub known_peers: Vec<(PeerId, Multiaddr)>,
+    /// Announced in identify, see `capabilities::agent_version`
+    pub agent_version: String,
+    /// Require the pre-auth handshake, the token itself is checked by the node loop
+    pub auth: bool,
+    /// Use the relay client passed to `SplashBehaviour::new` for circuit relays
+    pub circuit_relays: bool,
+    /// Run a circuit relay server with this configuration
+    pub relay_server: Option<relay::Config>,
+    /// Hole punching, AutoNAT and UPnP all expose our IP address
+    pub hole_punching: bool,
+    pub autonat: bool,
+    pub upnp: bool,
+    pub canary_responder: bool,
+    pub sync_responder: bool,
+    pub connection_limits: connection_limits::ConnectionLimits,
+    pub max_connections_per_ip: Option<usize>,
+    /// An empty allowlist allows everyone not denied
+    pub allowed_cidrs: Vec<Cidr>,
+    pub denied_cidrs: Vec<Cidr>,
+    pub denied_peers: HashSet<PeerId>,
+}
+
+impl Default for BehaviourConfig {
+    fn default() -> Self {
+        Splash::new()
+            .behaviour_config()
+            .expect("the default options are valid")
+    }
+}
+
+/// All protocols of a Splash node. `Splash::build` runs it in a swarm of its own, embedders that already
+/// run a libp2p swarm can compose it into their behaviour with `SplashBehaviour::new` and drive it themselves
+#[derive(NetworkBehaviour)]
+pub struct SplashBehaviour {
+    pub(crate) gossipsub: gossipsub::Behaviour,
+    pub(crate) kademlia: kad::Behaviour<kad::store::MemoryStore>,
+    pub(crate) identify: identify::Behaviour,
+    pub(crate) auth: Toggle<auth::Behaviour>,
+    pub(crate) push: push::Behaviour,
+    pub(crate) relay_client: Toggle<relay::client::Behaviour>,
+    pub(crate) relay_server: Toggle<relay::Behaviour>,
+    pub(crate) dcutr: Toggle<dcutr::Behaviour>,
+    pub(crate) autonat: Toggle<autonat::Behaviour>,
+    pub(crate) upnp: Toggle<upnp::tokio::Behaviour>,
+    pub(crate) dial_back: dialback::Behaviour,
+    pub(crate) canary: canary::Behaviour,
+    pub(crate) sync: sync::Behaviour,
+    pub(crate) connection_limits: connection_limits::Behaviour,
+    pub(crate) ip_limits: limits::IpLimits,
+    pub(crate) gate: gate::ConnectionGate,
+}
+
+impl SplashBehaviour {
+    /// The behaviour as described by `config`. Without a relay client circuit relays are not used, the
+    /// client comes with the transport, see `SwarmBuilder::with_relay_client`
+    pub fn new(
+        config: BehaviourConfig,
+        key: &identity::Keypair,
+        relay_client: Option<relay::client::Behaviour>,
+    ) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        // We can take the hash of message and use it as an ID. Other topics than the original
+        // one hash the topic as well, so an offer can be published on every topic version
+        let original_topic = gossipsub::IdentTopic::new(&config.offers_topic).hash();
+        let unique_offer_fn = move |message: &gossipsub::Message| {
+            if message.topic == original_topic {
+                gossipsub::MessageId::from(offer_hash(&message.data).to_string())
+            } else {
+                gossipsub::MessageId::from(
+                    topic_offer_hash(&message.topic, &message.data).to_string(),
+                )
+            }
+        };
+
+        // Set a custom gossipsub configuration
+        let gossipsub_config = gossipsub::ConfigBuilder::default()
+            .heartbeat_interval(config.mesh.heartbeat_interval)
+            .mesh_n(config.mesh.mesh_n)
+            .mesh_n_low(config.mesh.mesh_n_low)
+            .mesh_n_high(config.mesh.mesh_n_high)
+            .mesh_outbound_min(config.mesh.mesh_outbound_min)
+            .history_length(config.mesh.history_length)
+            .history_gossip(config.mesh.history_gossip)
+            .message_id_fn(unique_offer_fn) // No duplicate offers will be propagated.
+            .duplicate_cache_time(config.dedup.duplicate_cache_time)
+            .max_transmit_size(MAX_MESSAGE_SIZE)
+            .validate_messages()
+            .validation_mode(gossipsub::ValidationMode::Permissive)
+            .build()
+            .map_err(|msg| io::Error::new(io::ErrorKind::Other, msg))?; // Temporary hack because `build` does not return a proper `std::error::Error`.
+
+        // Generate a dummy keypair for signing gossipsub messages
+        // TODO: use gossipsub::MessageAuthenticity::RandomAuthor and disable signing for even more privacy (once enough nodes are updated)
+        let dummy_key = identity::Keypair::generate_ed25519();
+
+        // build a gossipsub network behaviour
+        let mut gossipsub = gossipsub::Behaviour::new(
+            gossipsub::MessageAuthenticity::Signed(dummy_key),
+            gossipsub_config,
+        )?;
+
+        if let Some(scoring) = config.peer_scoring.as_ref() {
+            let offer_topics = config
+                .offer_topics
+                .iter()
+                .map(|topic| gossipsub::IdentTopic::new(topic).hash())
+                .collect();
+            let (params, thresholds) = scoring.params(offer_topics);
+            gossipsub
+                .with_peer_score(params, thresholds)
+                .map_err(|msg| io::Error::new(io::ErrorKind::Other, msg))?;
+        }
+
+        // Create a Kademlia behaviour.
+        let mut cfg = kad::Config::new(
+            StreamProtocol::try_from_owned(format!("{}/kad/1", config.protocol_prefix))
+                .expect("protocol name is valid"),
+        );
+
+        cfg.set_query_timeout(Duration::from_secs(60));
+        let store = dht::store(key.public().to_peer_id());
+
+        let mut kademlia = kad::Behaviour::with_config(key.public().to_peer_id(), store, cfg);
+        if config.dht_server {
+            kademlia.set_mode(Some(kad::Mode::Server));
+        }
+
+        for (peer_id, addr) in config.known_peers {
+            kademlia.add_address(&peer_id, addr);
+        }
+
+        // Without any known peer this fails, a lazy bootstrap retries from the event loop
+        kademlia.bootstrap().ok();
+
+        let identify = identify::Behaviour::new(
+            identify::Config::new(
+                format!("{}/id/1", config.protocol_prefix),
+                key.public().clone(),
+            )
+            .with_agent_version(config.agent_version)
+            // Listen addresses that should be advertised are added as external addresses instead
+            .with_hide_listen_addrs(true),
+        );
+
+        let auth = Toggle::from(
+            config
+                .auth
+                .then(|| auth::behaviour(&config.protocol_prefix)),
+        );
+
+        let local_peer_id = key.public().to_peer_id();
+
+        Ok(SplashBehaviour {
+            gossipsub,
+            kademlia,
+            identify,
+            auth,
+            push: push::behaviour(&config.protocol_prefix),
+            dial_back: dialback::behaviour(&config.protocol_prefix),
+            canary: canary::behaviour(&config.protocol_prefix, config.canary_responder),
+            sync: sync::behaviour(&config.protocol_prefix, config.sync_responder),
+            connection_limits: connection_limits::Behaviour::new(config.connection_limits),
+            ip_limits: limits::IpLimits::new(config.max_connections_per_ip),
+            gate: gate::ConnectionGate::new(gate::Rules {
+                allowed_cidrs: config.allowed_cidrs,
+                denied_cidrs: config.denied_cidrs,
+                denied_peers: config.denied_peers,
+            }),
+            relay_client: Toggle::from(relay_client.filter(|_| config.circuit_relays)),
+            relay_server: Toggle::from(
+                config
+                    .relay_server
+                    .map(|relay_config| relay::Behaviour::new(local_peer_id, relay_config)),
+            ),
+            dcutr: Toggle::from(
+                config
+                    .hole_punching
+                    .then(|| dcutr::Behaviour::new(local_peer_id)),
+            ),
+            autonat: Toggle::from(
+                config
+                    .autonat
+                    .then(|| autonat::Behaviour::new(local_peer_id, Default::default())),
+            ),
+            upnp: Toggle::from(config.upnp.then(upnp::tokio::Behaviour::default)),
+        })
+    }
+
+    /// Join the offers, control and status topics of `config`, `Splash::build` does this for its own
+    /// swarm
+    pub fn subscribe(
+        &mut self,
+        config: &BehaviourConfig,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        for topic in config.offer_topics.iter().chain(config.other_topics.iter()) {
+            self.gossipsub
+                .subscribe(&gossipsub::IdentTopic::new(topic))?;
+        }
+        Ok(())
+    }
+
+    /// For embedders driving the behaviour, e.g. to publish offers and validate received ones
+    pub fn gossipsub_mut(&mut self) -> &mut gossipsub::Behaviour {
+        &mut self.gossipsub
+    }
+
+    pub fn kademlia_mut(&mut self) -> &mut kad::Behaviour<kad::store::MemoryStore> {
+        &mut self.kademlia
+    }
+}
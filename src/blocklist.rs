@@ -0,0 +1,109 @@
+use crate::gate::{parse_deny_list, Cidr};
+use libp2p::{identity, PeerId};
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::sync::mpsc::Sender;
+
+pub(crate) const REFRESH_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+pub(crate) type Blocklist = (Vec<Cidr>, Vec<PeerId>);
+
+// Fetches a community blocklist periodically. The list at `url` has the deny file format and must be
+// signed by `signer`: `<url>.sig` holds the hex encoded ed25519 signature of the list as served
+pub(crate) struct BlocklistSubscription {
+    url: String,
+    signer: identity::PublicKey,
+    proxy: Option<SocketAddr>,
+    fetching: bool,
+    results: Sender<Result<Blocklist, String>>,
+}
+
+impl BlocklistSubscription {
+    // The signer's public key is embedded in its peer ID, which only holds for ed25519 peer IDs
+    pub(crate) fn new(
+        url: String,
+        signer: &PeerId,
+        proxy: Option<SocketAddr>,
+        results: Sender<Result<Blocklist, String>>,
+    ) -> Result<Self, String> {
+        let signer = identity::PublicKey::try_decode_protobuf(signer.as_ref().digest())
+            .map_err(|_| format!("Blocklist signer {} has no embedded public key", signer))?;
+
+        Ok(BlocklistSubscription {
+            url,
+            signer,
+            proxy,
+            fetching: false,
+            results,
+        })
+    }
+
+    pub(crate) fn start(&mut self) {
+        if self.fetching {
+            return;
+        }
+        self.fetching = true;
+
+        let url = self.url.clone();
+        let signer = self.signer.clone();
+        let proxy = self.proxy;
+        let results = self.results.clone();
+        tokio::spawn(async move {
+            let result = fetch(&url, &signer, proxy).await;
+            results.send(result).await.ok();
+        });
+    }
+
+    pub(crate) fn finished(&mut self) {
+        self.fetching = false;
+    }
+}
+
+async fn fetch(
+    url: &str,
+    signer: &identity::PublicKey,
+    proxy: Option<SocketAddr>,
+) -> Result<Blocklist, String> {
+    let mut client = reqwest::Client::builder().timeout(Duration::from_secs(30));
+    if let Some(proxy) = proxy {
+        client = client
+            .proxy(reqwest::Proxy::all(format!("socks5h://{}", proxy)).map_err(|e| e.to_string())?);
+    }
+    let client = client.build().map_err(|e| e.to_string())?;
+
+    let get = |url: String| {
+        let request = client.get(url);
+        async move {
+            request
+                .send()
+                .await
+                .and_then(|response| response.error_for_status())
+                .map_err(|e| e.to_string())?
+                .bytes()
+                .await
+                .map_err(|e| e.to_string())
+        }
+    };
+
+    let list = get(url.to_string()).await?;
+    let signature = get(format!("{}.sig", url)).await?;
+    let signature = decode_hex(String::from_utf8_lossy(&signature).trim())
+        .ok_or("Blocklist signature is not hex encoded")?;
+
+    if !signer.verify(&list, &signature) {
+        return Err("Blocklist signature doesn't match".to_string());
+    }
+
+    parse_deny_list(&String::from_utf8_lossy(&list))
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
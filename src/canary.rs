@@ -0,0 +1,107 @@
+use crate::SplashError;
+use libp2p::request_response::{self, OutboundRequestId, ProtocolSupport};
+use libp2p::{PeerId, StreamProtocol};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::sync::oneshot;
+use tokio::time::Instant;
+
+// A responder answers each peer at most once per interval, further canaries go unanswered
+const RESPONSE_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CanaryRequest {
+    pub(crate) nonce: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CanaryResponse {
+    pub(crate) nonce: String,
+}
+
+pub(crate) type Behaviour = request_response::json::Behaviour<CanaryRequest, CanaryResponse>;
+
+// Every node can send canaries, only responders advertise and answer the protocol
+pub(crate) fn behaviour(protocol_prefix: &str, responder: bool) -> Behaviour {
+    request_response::json::Behaviour::new(
+        [(
+            StreamProtocol::try_from_owned(format!("{}/canary/1", protocol_prefix))
+                .expect("protocol name is valid"),
+            if responder {
+                ProtocolSupport::Full
+            } else {
+                ProtocolSupport::Outbound
+            },
+        )],
+        request_response::Config::default().with_request_timeout(Duration::from_secs(10)),
+    )
+}
+
+struct PendingCanary {
+    nonce: String,
+    sent: Instant,
+    response: oneshot::Sender<Result<Duration, SplashError>>,
+}
+
+// Both sides of the canary protocol: our canaries waiting for their answer, and the peers we answered
+#[derive(Default)]
+pub(crate) struct Canaries {
+    pending: HashMap<OutboundRequestId, PendingCanary>,
+    answered: HashMap<PeerId, Instant>,
+}
+
+impl Canaries {
+    pub(crate) fn sent(
+        &mut self,
+        request_id: OutboundRequestId,
+        nonce: String,
+        response: oneshot::Sender<Result<Duration, SplashError>>,
+    ) {
+        let canary = PendingCanary {
+            nonce,
+            sent: Instant::now(),
+            response,
+        };
+        self.pending.insert(request_id, canary);
+    }
+
+    // Resolves the canary with its round trip time, if the answer carries the nonce we sent
+    pub(crate) fn answered(&mut self, request_id: OutboundRequestId, nonce: &str) {
+        if let Some(canary) = self.pending.remove(&request_id) {
+            let result = if canary.nonce == nonce {
+                Ok(canary.sent.elapsed())
+            } else {
+                Err(SplashError::CanaryFailed(
+                    "answer doesn't match the canary".to_string(),
+                ))
+            };
+            canary.response.send(result).ok();
+        }
+    }
+
+    pub(crate) fn failed(&mut self, request_id: OutboundRequestId, error: String) {
+        if let Some(canary) = self.pending.remove(&request_id) {
+            canary
+                .response
+                .send(Err(SplashError::CanaryFailed(error)))
+                .ok();
+        }
+    }
+
+    // Whether to answer a canary of this peer, at most once per RESPONSE_INTERVAL
+    pub(crate) fn may_answer(&mut self, peer_id: PeerId) -> bool {
+        let now = Instant::now();
+        match self.answered.get(&peer_id) {
+            Some(last) if now.duration_since(*last) < RESPONSE_INTERVAL => false,
+            _ => {
+                self.answered.insert(peer_id, now);
+                true
+            }
+        }
+    }
+
+    pub(crate) fn disconnected(&mut self, peer_id: &PeerId) {
+        self.answered.remove(peer_id);
+    }
+}
@@ -0,0 +1,43 @@
+// Optional features a node announces in its identify agent version, so peers and operators can tell
+// which nodes support them, e.g. "splash/0.2.0 caps=chunks,control,zstd (dexie; ops@dexie.space)"
+pub(crate) const RELAY_SERVER: &str = "relay-server";
+pub(crate) const CANARY_RESPONDER: &str = "canary";
+pub(crate) const COMPRESSION: &str = "zstd";
+pub(crate) const OFFERS_V2: &str = "offers-v2";
+pub(crate) const CHUNKS: &str = "chunks";
+pub(crate) const CONTROL: &str = "control";
+pub(crate) const SYNC: &str = "sync";
+
+const PREFIX: &str = "caps=";
+
+pub(crate) fn agent_version(operator: Option<&str>, capabilities: &[&str]) -> String {
+    let mut agent_version = format!("splash/{}", env!("CARGO_PKG_VERSION"));
+    if !capabilities.is_empty() {
+        agent_version.push_str(&format!(" {}{}", PREFIX, capabilities.join(",")));
+    }
+    if let Some(operator) = operator {
+        agent_version.push_str(&format!(" ({})", operator));
+    }
+    agent_version
+}
+
+// Capabilities of a peer's agent version, unknown ones included. Empty for other implementations and
+// Splash versions before capabilities were announced
+pub(crate) fn parse(agent_version: &str) -> Vec<String> {
+    let Some(rest) = agent_version.strip_prefix("splash/") else {
+        return Vec::new();
+    };
+
+    // The operator in parentheses comes last and may contain anything
+    let rest = rest.split(" (").next().unwrap_or_default();
+    rest.split(' ')
+        .find_map(|field| field.strip_prefix(PREFIX))
+        .map(|capabilities| {
+            capabilities
+                .split(',')
+                .filter(|capability| !capability.is_empty())
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default()
+}
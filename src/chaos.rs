@@ -0,0 +1,57 @@
+use libp2p::PeerId;
+use rand::seq::IteratorRandom;
+use rand::Rng;
+use std::time::Duration;
+use tokio::sync::mpsc::{self, Receiver};
+use tokio::time;
+
+/// Fault injection settings for resilience testing (requires the `testing` feature)
+#[derive(Clone, Debug, Default)]
+pub struct ChaosConfig {
+    /// Disconnect a random connected peer at this interval
+    pub disconnect_interval: Option<Duration>,
+    /// Hold every submitted offer for this long before publishing it
+    pub publish_delay: Option<Duration>,
+    /// Percentage (0-100) of incoming offers dropped before validation
+    pub drop_incoming_percent: u8,
+}
+
+impl ChaosConfig {
+    pub(crate) fn drop_incoming(&self) -> bool {
+        self.drop_incoming_percent > 0
+            && rand::thread_rng().gen_range(0..100) < self.drop_incoming_percent
+    }
+
+    pub(crate) fn disconnect_interval(&self) -> Option<time::Interval> {
+        self.disconnect_interval
+            .map(|period| time::interval_at(time::Instant::now() + period, period))
+    }
+
+    // Put a delay stage in front of the submission channel, the event loop stays untouched
+    pub(crate) fn delay_submissions<T: Send + 'static>(
+        &self,
+        mut receiver: Receiver<T>,
+    ) -> Receiver<T> {
+        let Some(delay) = self.publish_delay else {
+            return receiver;
+        };
+
+        let (delayed_sender, delayed_receiver) = mpsc::channel(100);
+
+        tokio::spawn(async move {
+            while let Some(submission) = receiver.recv().await {
+                let delayed_sender = delayed_sender.clone();
+                tokio::spawn(async move {
+                    time::sleep(delay).await;
+                    delayed_sender.send(submission).await.ok();
+                });
+            }
+        });
+
+        delayed_receiver
+    }
+}
+
+pub(crate) fn random_peer(peers: impl Iterator<Item = PeerId>) -> Option<PeerId> {
+    peers.choose(&mut rand::thread_rng())
+}
@@ -0,0 +1,123 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::time::Instant;
+
+// Offer bytes per chunk, leaving room for the envelope within the gossipsub transmit size
+pub(crate) const CHUNK_SIZE: usize = 256 * 1024;
+const REASSEMBLY_TIMEOUT: Duration = Duration::from_secs(60);
+// Partially received offers, the oldest is dropped once more arrive
+const MAX_PARTIAL_OFFERS: usize = 16;
+
+// One part of an offer too large for a single gossip message, linked to the others by the offer's hash
+#[derive(Serialize, Deserialize)]
+struct Chunk {
+    offer_id: String,
+    index: usize,
+    total: usize,
+    data: String,
+}
+
+// Splits an offer into serialized chunks of at most CHUNK_SIZE offer bytes, offers are ASCII
+pub(crate) fn split(offer: &str) -> Vec<Vec<u8>> {
+    let offer_id = offer_id(offer);
+    let parts: Vec<&[u8]> = offer.as_bytes().chunks(CHUNK_SIZE).collect();
+    let total = parts.len();
+
+    parts
+        .into_iter()
+        .enumerate()
+        .map(|(index, data)| {
+            let chunk = Chunk {
+                offer_id: offer_id.clone(),
+                index,
+                total,
+                data: String::from_utf8_lossy(data).into_owned(),
+            };
+            serde_json::to_vec(&chunk).expect("chunk serializes")
+        })
+        .collect()
+}
+
+struct Partial {
+    parts: Vec<Option<String>>,
+    started: Instant,
+}
+
+// Collects chunks until an offer is complete, incomplete offers are dropped after REASSEMBLY_TIMEOUT
+pub(crate) struct Reassembly {
+    max_offer_size: usize,
+    partial: HashMap<String, Partial>,
+}
+
+impl Reassembly {
+    pub(crate) fn new(max_offer_size: usize) -> Self {
+        Reassembly {
+            max_offer_size,
+            partial: HashMap::new(),
+        }
+    }
+
+    // The complete offer once its last missing chunk arrived, an error for malformed chunks
+    pub(crate) fn add(&mut self, message: &[u8]) -> Result<Option<String>, String> {
+        let chunk: Chunk = serde_json::from_slice(message).map_err(|e| e.to_string())?;
+        if chunk.total == 0
+            || chunk.index >= chunk.total
+            || chunk.total > self.max_offer_size.div_ceil(CHUNK_SIZE)
+            || chunk.data.len() > CHUNK_SIZE
+        {
+            return Err(format!(
+                "chunk {} of {} is out of bounds",
+                chunk.index, chunk.total
+            ));
+        }
+
+        self.partial
+            .retain(|_, partial| partial.started.elapsed() < REASSEMBLY_TIMEOUT);
+        if !self.partial.contains_key(&chunk.offer_id) && self.partial.len() >= MAX_PARTIAL_OFFERS {
+            let oldest = self
+                .partial
+                .iter()
+                .min_by_key(|(_, partial)| partial.started)
+                .map(|(offer_id, _)| offer_id.clone());
+            if let Some(oldest) = oldest {
+                self.partial.remove(&oldest);
+            }
+        }
+
+        let partial = self
+            .partial
+            .entry(chunk.offer_id.clone())
+            .or_insert_with(|| Partial {
+                parts: vec![None; chunk.total],
+                started: Instant::now(),
+            });
+        if partial.parts.len() != chunk.total {
+            return Err(format!("chunk total {} doesn't match", chunk.total));
+        }
+        partial.parts[chunk.index] = Some(chunk.data);
+
+        if partial.parts.iter().any(Option::is_none) {
+            return Ok(None);
+        }
+
+        let offer: String = self
+            .partial
+            .remove(&chunk.offer_id)
+            .into_iter()
+            .flat_map(|partial| partial.parts.into_iter().flatten())
+            .collect();
+        if offer_id(&offer) != chunk.offer_id {
+            return Err("reassembled offer doesn't match its hash".to_string());
+        }
+        Ok(Some(offer))
+    }
+}
+
+pub(crate) fn offer_id(offer: &str) -> String {
+    Sha256::digest(offer.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
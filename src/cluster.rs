@@ -0,0 +1,113 @@
+use hickory_resolver::TokioAsyncResolver;
+use libp2p::multiaddr::Protocol;
+use libp2p::{Multiaddr, PeerId};
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+use tokio::sync::mpsc::Sender;
+
+pub(crate) const DISCOVERY_INTERVAL: Duration = Duration::from_secs(30);
+
+// Finds the other replicas of a cluster via DNS, e.g. a Kubernetes headless Service. With a port the
+// service's A/AAAA records are used, without one its SRV records
+pub(crate) struct ClusterDiscovery {
+    service: String,
+    port: Option<u16>,
+    resolving: bool,
+    results: Sender<Result<Vec<Multiaddr>, String>>,
+    resolved: HashSet<Multiaddr>,
+    // Replicas we connected to, by the address we dialed
+    peers: HashMap<Multiaddr, PeerId>,
+}
+
+impl ClusterDiscovery {
+    pub(crate) fn new(
+        service: String,
+        port: Option<u16>,
+        results: Sender<Result<Vec<Multiaddr>, String>>,
+    ) -> Self {
+        ClusterDiscovery {
+            service,
+            port,
+            resolving: false,
+            results,
+            resolved: HashSet::new(),
+            peers: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn start(&mut self) {
+        if self.resolving {
+            return;
+        }
+        self.resolving = true;
+
+        let service = self.service.clone();
+        let port = self.port;
+        let results = self.results.clone();
+        tokio::spawn(async move {
+            let result = resolve_service(&service, port)
+                .await
+                .map_err(|e| e.to_string());
+            results.send(result).await.ok();
+        });
+    }
+
+    // Resolved addresses that aren't ours and not connected to yet, the service also returns our own
+    pub(crate) fn to_dial(
+        &mut self,
+        addresses: Vec<Multiaddr>,
+        listen_addresses: &HashSet<Multiaddr>,
+        is_connected: impl Fn(&PeerId) -> bool,
+    ) -> Vec<Multiaddr> {
+        self.resolving = false;
+        self.resolved = addresses.iter().cloned().collect();
+        self.peers
+            .retain(|address, _| self.resolved.contains(address));
+
+        addresses
+            .into_iter()
+            .filter(|address| !listen_addresses.contains(address))
+            .filter(|address| {
+                self.peers
+                    .get(address)
+                    .map_or(true, |peer_id| !is_connected(peer_id))
+            })
+            .collect()
+    }
+
+    pub(crate) fn failed(&mut self) {
+        self.resolving = false;
+    }
+
+    pub(crate) fn connected(&mut self, address: &Multiaddr, peer_id: PeerId) {
+        if self.resolved.contains(address) {
+            self.peers.insert(address.clone(), peer_id);
+        }
+    }
+}
+
+async fn resolve_service(
+    service: &str,
+    port: Option<u16>,
+) -> Result<Vec<Multiaddr>, hickory_resolver::error::ResolveError> {
+    let resolver = TokioAsyncResolver::tokio_from_system_conf()?;
+
+    let targets = match port {
+        Some(port) => vec![(service.to_string(), port)],
+        None => resolver
+            .srv_lookup(service)
+            .await?
+            .iter()
+            .map(|srv| (srv.target().to_utf8(), srv.port()))
+            .collect(),
+    };
+
+    let mut addresses = Vec::new();
+    for (host, port) in targets {
+        for ip in resolver.lookup_ip(host).await?.iter() {
+            addresses.push(Multiaddr::from(ip).with(Protocol::Tcp(port)));
+        }
+    }
+
+    Ok(addresses)
+}
@@ -0,0 +1,207 @@
+//! The wire format of offers on the plain offers topics, for implementations in other languages to
+//! depend on or port. Version 1 carries the bare bech32 offer as UTF-8, version 2 a protobuf envelope:
+//!
+//! ```proto
+//! message OfferEnvelope {
+//!   string offer = 1;
+//!   optional uint64 created_at = 2;
+//!   optional uint64 expires_at = 3;
+//!   optional uint64 fee = 4;
+//! }
+//! ```
+//!
+//! Unknown envelope fields are skipped when decoding, so metadata can be added without another topic
+//! version.
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use prost::Message;
+use serde::{Deserialize, Serialize};
+use std::io;
+
+/// Optional metadata published along an offer on the v2 offers topic. Offers arriving bare, on the v1
+/// topic, pushed or backfilled, come with empty metadata
+#[derive(
+    Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, BorshSerialize, BorshDeserialize,
+)]
+pub struct OfferMetadata {
+    /// Unix timestamp the offer was published at, filled in by the publishing node if unset
+    pub created_at: Option<u64>,
+    /// Unix timestamp after which the offer is probably no longer worth taking
+    pub expires_at: Option<u64>,
+    /// Suggested fee for taking the offer, in mojos
+    pub fee: Option<u64>,
+}
+
+/// A version 2 message: the offer and its metadata
+#[derive(
+    Clone, PartialEq, Eq, Message, Serialize, Deserialize, BorshSerialize, BorshDeserialize,
+)]
+pub struct OfferEnvelope {
+    #[prost(string, tag = "1")]
+    pub offer: String,
+    #[prost(uint64, optional, tag = "2")]
+    pub created_at: Option<u64>,
+    #[prost(uint64, optional, tag = "3")]
+    pub expires_at: Option<u64>,
+    #[prost(uint64, optional, tag = "4")]
+    pub fee: Option<u64>,
+}
+
+impl OfferEnvelope {
+    pub fn new(offer: String, metadata: OfferMetadata) -> Self {
+        OfferEnvelope {
+            offer,
+            created_at: metadata.created_at,
+            expires_at: metadata.expires_at,
+            fee: metadata.fee,
+        }
+    }
+
+    pub fn metadata(&self) -> OfferMetadata {
+        OfferMetadata {
+            created_at: self.created_at,
+            expires_at: self.expires_at,
+            fee: self.fee,
+        }
+    }
+}
+
+/// An offer as gossiped on a version of the plain offers topic
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, BorshSerialize, BorshDeserialize)]
+pub enum GossipOffer {
+    /// `/splash/offers/1`, the bare offer
+    V1(String),
+    /// `/splash/offers/2`, the offer in an envelope
+    V2(OfferEnvelope),
+}
+
+impl GossipOffer {
+    /// The topic version this message is published on
+    pub fn version(&self) -> u32 {
+        match self {
+            GossipOffer::V1(_) => 1,
+            GossipOffer::V2(_) => 2,
+        }
+    }
+
+    pub fn offer(&self) -> &str {
+        match self {
+            GossipOffer::V1(offer) => offer,
+            GossipOffer::V2(envelope) => &envelope.offer,
+        }
+    }
+
+    pub fn metadata(&self) -> OfferMetadata {
+        match self {
+            GossipOffer::V1(_) => OfferMetadata::default(),
+            GossipOffer::V2(envelope) => envelope.metadata(),
+        }
+    }
+
+    /// The gossip message data
+    pub fn encode(&self) -> Vec<u8> {
+        match self {
+            GossipOffer::V1(offer) => offer.as_bytes().to_vec(),
+            GossipOffer::V2(envelope) => envelope.encode_to_vec(),
+        }
+    }
+
+    /// Decodes the data of a gossip message received on the given topic version
+    pub fn decode(version: u32, data: &[u8]) -> io::Result<Self> {
+        match version {
+            1 => String::from_utf8(data.to_vec())
+                .map(GossipOffer::V1)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            2 => OfferEnvelope::decode(data)
+                .map(GossipOffer::V2)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unknown offers topic version: {}", version),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OFFER: &str = "offer1qqr83wcuu2rykcmqvpsxygqqemhmlaekcenaz02ma6hs5w600dhjlvfjn477nkwz369h88kll73h37fefnwk3qqnz8s0lle0";
+
+    fn messages() -> Vec<GossipOffer> {
+        vec![
+            GossipOffer::V1(OFFER.to_string()),
+            GossipOffer::V2(OfferEnvelope::new(
+                OFFER.to_string(),
+                OfferMetadata::default(),
+            )),
+            GossipOffer::V2(OfferEnvelope::new(
+                OFFER.to_string(),
+                OfferMetadata {
+                    created_at: Some(1_700_000_000),
+                    expires_at: Some(1_700_003_600),
+                    fee: Some(0),
+                },
+            )),
+        ]
+    }
+
+    #[test]
+    fn wire_round_trip() {
+        for message in messages() {
+            let data = message.encode();
+            assert_eq!(
+                GossipOffer::decode(message.version(), &data).unwrap(),
+                message
+            );
+        }
+    }
+
+    #[test]
+    fn v1_is_the_bare_offer() {
+        assert_eq!(
+            GossipOffer::V1(OFFER.to_string()).encode(),
+            OFFER.as_bytes()
+        );
+    }
+
+    #[test]
+    fn v2_keeps_zero_values() {
+        let message = &messages()[2];
+        let decoded = GossipOffer::decode(2, &message.encode()).unwrap();
+        assert_eq!(decoded.metadata().fee, Some(0));
+        assert_eq!(decoded.offer(), OFFER);
+    }
+
+    #[test]
+    fn v2_skips_unknown_fields() {
+        let mut data = messages()[2].encode();
+        // Field 15, varint 1
+        data.extend_from_slice(&[0x78, 0x01]);
+        assert_eq!(GossipOffer::decode(2, &data).unwrap(), messages()[2]);
+    }
+
+    #[test]
+    fn rejects_undecodable_data() {
+        assert!(GossipOffer::decode(1, &[0xff, 0xfe]).is_err());
+        assert!(GossipOffer::decode(2, OFFER.as_bytes()).is_err());
+        assert!(GossipOffer::decode(3, OFFER.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn serde_round_trip() {
+        for message in messages() {
+            let json = serde_json::to_string(&message).unwrap();
+            assert_eq!(serde_json::from_str::<GossipOffer>(&json).unwrap(), message);
+        }
+    }
+
+    #[test]
+    fn borsh_round_trip() {
+        for message in messages() {
+            let data = borsh::to_vec(&message).unwrap();
+            assert_eq!(GossipOffer::try_from_slice(&data).unwrap(), message);
+        }
+    }
+}
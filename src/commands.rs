@@ -0,0 +1,324 @@
+use crate::utils;
+use libp2p::autonat::NatStatus;
+use libp2p::multiaddr::Protocol;
+use libp2p::{identity, Multiaddr};
+use splash::{decode_offer, encode_offer, offer_from_bytes, Splash, SplashContext, SplashEvent};
+use std::collections::HashSet;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+use tokio::time::Instant;
+
+// Payload prefixes of the self test offers, canaries are answered with an echo carrying the same nonce
+const CANARY_PREFIX: &str = "splash-selftest:canary:";
+const ECHO_PREFIX: &str = "splash-selftest:echo:";
+
+// Sign a blocklist file, prints the hex signature to serve as <URL>.sig and the signer to stderr
+pub fn sign_blocklist(identity_file: &str, file: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let keypair = utils::load_keypair_from_file(identity_file)?;
+    let signature = keypair.sign(&std::fs::read(file)?)?;
+
+    eprintln!("Signer: {}", keypair.public().to_peer_id());
+    println!(
+        "{}",
+        signature
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>()
+    );
+    Ok(())
+}
+
+pub fn keygen(identity_file: &str, force: bool) -> Result<(), Box<dyn std::error::Error>> {
+    if !force && Path::new(identity_file).exists() {
+        return Err(format!(
+            "{} already exists, use --force to overwrite it",
+            identity_file
+        )
+        .into());
+    }
+
+    let keypair = identity::Keypair::generate_ed25519();
+    utils::save_keypair_to_file(&keypair, identity_file)?;
+
+    println!("{}", keypair.public().to_peer_id());
+    Ok(())
+}
+
+pub fn completions(shell: clap_complete::Shell, mut cli: clap::Command) {
+    clap_complete::generate(shell, &mut cli, "splash", &mut io::stdout());
+}
+
+pub async fn broadcast(
+    splash: Splash,
+    files: &[String],
+    timeout: Duration,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut offers = Vec::with_capacity(files.len());
+    for file in files {
+        offers.push((file.clone(), offer_from_bytes(&std::fs::read(file)?)?));
+    }
+
+    let node = start(splash).await?;
+    publish_all(&node, offers, Duration::ZERO, timeout).await
+}
+
+pub async fn replay(
+    splash: Splash,
+    file: &str,
+    interval: Duration,
+    timeout: Duration,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let offers: Vec<(String, String)> = std::fs::read_to_string(file)?
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| (format!("{}:{}", file, i + 1), line.trim().to_string()))
+        .collect();
+
+    let node = start(splash).await?;
+    publish_all(&node, offers, interval, timeout).await
+}
+
+// Build the node and discard its events, the subcommands only report on their offers
+async fn start(splash: Splash) -> Result<Splash, Box<dyn std::error::Error>> {
+    let SplashContext { node, mut events } = splash.build().await?;
+    tokio::spawn(async move { while events.recv().await.is_some() {} });
+    Ok(node)
+}
+
+// Publish the labeled offers one after another, each has until the timeout to reach a mesh peer
+async fn publish_all(
+    node: &Splash,
+    offers: Vec<(String, String)>,
+    interval: Duration,
+    timeout: Duration,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let total = offers.len();
+    let mut failed = 0;
+
+    for (i, (label, offer)) in offers.into_iter().enumerate() {
+        if i > 0 {
+            tokio::time::sleep(interval).await;
+        }
+
+        match node
+            .broadcast_offer_and_propagate_with_ttl(&offer, timeout)
+            .await
+        {
+            Ok(receipt) => println!(
+                "Broadcasted {} to {} mesh peers (message {})",
+                label, receipt.mesh_peers, receipt.message_id
+            ),
+            Err(e) => {
+                eprintln!("Error broadcasting {}: {}", label, e);
+                failed += 1;
+            }
+        }
+    }
+
+    if failed > 0 {
+        return Err(format!("{} of {} offers weren't broadcasted", failed, total).into());
+    }
+    Ok(())
+}
+
+// Watch a node for the timeout and report whether it joined the network
+pub async fn doctor(splash: Splash, timeout: Duration) -> Result<(), Box<dyn std::error::Error>> {
+    let SplashContext {
+        node: _node,
+        mut events,
+    } = splash.build().await?;
+
+    let mut peer_id = None;
+    let mut listen_addresses = Vec::new();
+    let mut external_addresses = Vec::new();
+    let mut bootstrap_degraded = None;
+    let mut peers = HashSet::new();
+    let mut mesh_peers = HashSet::new();
+    let mut nat_status = NatStatus::Unknown;
+    let mut handshake_timeouts = 0;
+
+    let deadline = tokio::time::sleep(timeout);
+    tokio::pin!(deadline);
+
+    loop {
+        let event = tokio::select! {
+            _ = &mut deadline => break,
+            event = events.recv() => match event {
+                Some(event) => event,
+                None => break,
+            },
+        };
+
+        match event {
+            SplashEvent::Initialized(id) => peer_id = Some(id),
+            SplashEvent::NewListenAddress(address) => listen_addresses.push(address),
+            SplashEvent::ExternalAddressConfirmed(address) | SplashEvent::PortMapped(address) => {
+                external_addresses.push(address)
+            }
+            SplashEvent::BootstrapDegraded { reason, .. } => bootstrap_degraded = Some(reason),
+            SplashEvent::PeerConnected(peer) => {
+                peers.insert(peer);
+            }
+            SplashEvent::MeshPeerAdded(peer) => {
+                mesh_peers.insert(peer);
+            }
+            SplashEvent::NatStatusChanged(status) => nat_status = status,
+            SplashEvent::HandshakeTimeout(_) => handshake_timeouts += 1,
+            _ => {}
+        }
+    }
+
+    if let Some(peer_id) = peer_id {
+        println!("Peer ID: {}", peer_id);
+    }
+    for address in &listen_addresses {
+        println!("Listening on: {}", address);
+    }
+    for address in &external_addresses {
+        println!("External address: {}", address);
+    }
+    match bootstrap_degraded {
+        Some(reason) => println!("Bootstrap: degraded, {}", reason),
+        None => println!("Bootstrap: ok"),
+    }
+    println!("NAT status: {:?}", nat_status);
+    println!("Peers connected: {}", peers.len());
+    println!("Mesh peers: {}", mesh_peers.len());
+    if handshake_timeouts > 0 {
+        println!("Handshake timeouts: {}", handshake_timeouts);
+    }
+
+    if peers.is_empty() {
+        return Err(format!("No peer connected within {}s", timeout.as_secs()).into());
+    }
+    if mesh_peers.is_empty() {
+        return Err("Connected, but no peer joined the offers mesh".into());
+    }
+    Ok(())
+}
+
+// Broadcast a canary through the reference node and wait for its echo, reports the round trip time
+pub async fn selftest(
+    splash: Splash,
+    reference: Multiaddr,
+    timeout: Duration,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let topic = splash.selftest_topic();
+    let SplashContext { node, mut events } = splash
+        .with_known_peers(vec![reference])
+        .with_topics(vec![topic])
+        .build()
+        .await?;
+
+    let nonce = format!("{:016x}", rand::random::<u64>());
+    let canary = encode_offer(format!("{}{}", CANARY_PREFIX, nonce).as_bytes())?;
+    let echo = encode_offer(format!("{}{}", ECHO_PREFIX, nonce).as_bytes())?;
+
+    let deadline = tokio::time::sleep(timeout);
+    tokio::pin!(deadline);
+
+    // The canary is only sent once the reference node joined the test topic's mesh
+    loop {
+        tokio::select! {
+            _ = &mut deadline => return Err("The reference node didn't join the self test mesh".into()),
+            event = events.recv() => match event {
+                Some(SplashEvent::MeshPeerAdded(peer)) => {
+                    println!("Reference node {} joined the mesh", peer);
+                    break;
+                }
+                Some(_) => {}
+                None => return Err("Node stopped".into()),
+            },
+        }
+    }
+
+    let sent = Instant::now();
+    node.broadcast_offer_and_wait(&canary).await?;
+    println!("Canary {} sent", nonce);
+
+    loop {
+        tokio::select! {
+            _ = &mut deadline => return Err(format!("No echo for canary {} within {}s", nonce, timeout.as_secs()).into()),
+            event = events.recv() => match event {
+                Some(SplashEvent::OfferReceived(offer, _)) if offer == echo => {
+                    println!("Echo received, round trip time {}ms", sent.elapsed().as_millis());
+                    return Ok(());
+                }
+                Some(_) => {}
+                None => return Err("Node stopped".into()),
+            },
+        }
+    }
+}
+
+// Send a canary to a node with a canary responder over the canary protocol, reports the round trip time
+pub async fn selftest_direct(
+    splash: Splash,
+    reference: Multiaddr,
+    timeout: Duration,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let Some(Protocol::P2p(reference_id)) = reference.iter().last() else {
+        return Err("The reference node address has to end with /p2p/<peer id>".into());
+    };
+
+    let SplashContext { node, mut events } =
+        splash.with_known_peers(vec![reference]).build().await?;
+
+    let deadline = tokio::time::sleep(timeout);
+    tokio::pin!(deadline);
+
+    loop {
+        tokio::select! {
+            _ = &mut deadline => return Err("Couldn't connect to the reference node".into()),
+            event = events.recv() => match event {
+                Some(SplashEvent::PeerConnected(peer)) if peer == reference_id => break,
+                Some(_) => {}
+                None => return Err("Node stopped".into()),
+            },
+        }
+    }
+    tokio::spawn(async move { while events.recv().await.is_some() {} });
+
+    let round_trip = tokio::time::timeout_at(deadline.deadline(), node.canary(reference_id))
+        .await
+        .map_err(|_| "No answer to the canary in time")??;
+    println!(
+        "Canary answered by {}, round trip time {}ms",
+        reference_id,
+        round_trip.as_millis()
+    );
+    Ok(())
+}
+
+// Run a reference node that answers the canaries of `selftest` on the self test topic
+pub async fn selftest_echo(splash: Splash) -> Result<(), Box<dyn std::error::Error>> {
+    let topic = splash.selftest_topic();
+    let SplashContext { node, mut events } = splash.with_topics(vec![topic]).build().await?;
+
+    while let Some(event) = events.recv().await {
+        let SplashEvent::OfferReceived(offer, _) = event else {
+            continue;
+        };
+
+        // Echoes from other reference nodes aren't answered, or two of them would echo forever
+        let Some(nonce) = decode_offer(&offer)
+            .ok()
+            .and_then(|payload| String::from_utf8(payload).ok())
+            .and_then(|payload| payload.strip_prefix(CANARY_PREFIX).map(str::to_string))
+        else {
+            continue;
+        };
+
+        match encode_offer(format!("{}{}", ECHO_PREFIX, nonce).as_bytes()) {
+            Ok(echo) => match node.broadcast_offer(&echo).await {
+                Ok(()) => println!("Echoed canary {}", nonce),
+                Err(e) => eprintln!("Error echoing canary {}: {}", nonce, e),
+            },
+            Err(e) => eprintln!("Error encoding echo for canary {}: {}", nonce, e),
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,26 @@
+use std::str::FromStr;
+
+/// Whether offers are gossiped zstd compressed. Compressed offers go to their own topic, so nodes
+/// without compression keep receiving the plain ones
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OfferCompression {
+    #[default]
+    Off,
+    /// Gossip on both topics, reaching nodes with and without compression
+    Both,
+    /// Only the compressed topic, for deployments where every node compresses
+    Only,
+}
+
+impl FromStr for OfferCompression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(OfferCompression::Off),
+            "both" => Ok(OfferCompression::Both),
+            "only" => Ok(OfferCompression::Only),
+            _ => Err(format!("Unknown offer compression: {}", s)),
+        }
+    }
+}
@@ -0,0 +1,163 @@
+use crate::transport::NegotiatedSecurity;
+use libp2p::core::ConnectedPoint;
+use libp2p::swarm::ConnectionId;
+use libp2p::{Multiaddr, PeerId, StreamProtocol};
+use rand::seq::IteratorRandom;
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, SystemTime};
+
+pub(crate) const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+// A connected peer subscribed to the offers topic
+pub(crate) struct Candidate {
+    pub(crate) peer_id: PeerId,
+    pub(crate) in_mesh: bool,
+    pub(crate) score: Option<f64>,
+}
+
+// Keeps the number of gossipsub peers between a minimum and a maximum
+pub(crate) struct ConnectionManager {
+    min_peers: Option<usize>,
+    max_peers: Option<usize>,
+    // Bootstrap peers and trusted relays are never pruned
+    protected: HashSet<PeerId>,
+}
+
+impl ConnectionManager {
+    pub(crate) fn new(
+        min_peers: Option<usize>,
+        max_peers: Option<usize>,
+        protected: impl IntoIterator<Item = PeerId>,
+    ) -> Self {
+        ConnectionManager {
+            min_peers,
+            max_peers,
+            protected: protected.into_iter().collect(),
+        }
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.min_peers.is_some() || self.max_peers.is_some()
+    }
+
+    // Random routing table peers to dial when below the minimum, the routing table also holds the
+    // address book restored from a state file
+    pub(crate) fn to_dial(
+        &self,
+        peers: usize,
+        routing_peers: Vec<PeerId>,
+        is_connected: impl Fn(&PeerId) -> bool,
+    ) -> Vec<PeerId> {
+        let Some(min_peers) = self.min_peers.filter(|min| peers < *min) else {
+            return Vec::new();
+        };
+
+        routing_peers
+            .into_iter()
+            .filter(|peer_id| !is_connected(peer_id))
+            .choose_multiple(&mut rand::thread_rng(), min_peers - peers)
+    }
+
+    // Peers to disconnect when above the maximum, those outside the mesh go first, then the lowest scoring
+    pub(crate) fn to_prune(&self, mut candidates: Vec<Candidate>) -> Vec<PeerId> {
+        let Some(max_peers) = self.max_peers.filter(|max| candidates.len() > *max) else {
+            return Vec::new();
+        };
+        let excess = candidates.len() - max_peers;
+
+        candidates.retain(|candidate| !self.protected.contains(&candidate.peer_id));
+        candidates.sort_by(|a, b| {
+            a.in_mesh.cmp(&b.in_mesh).then(
+                a.score
+                    .unwrap_or_default()
+                    .total_cmp(&b.score.unwrap_or_default()),
+            )
+        });
+
+        candidates
+            .into_iter()
+            .take(excess)
+            .map(|candidate| candidate.peer_id)
+            .collect()
+    }
+}
+
+/// An open connection, for troubleshooting interop with other implementations
+#[derive(Clone, Debug)]
+pub struct ConnectionInfo {
+    pub peer_id: PeerId,
+    pub remote_address: Multiaddr,
+    /// Whether we dialed the connection
+    pub outbound: bool,
+    pub established_at: SystemTime,
+    /// Negotiated security protocol, tracked per peer rather than per connection
+    pub security: Option<String>,
+    pub muxer: String,
+    /// Agent version and supported protocols as announced via identify
+    pub agent_version: Option<String>,
+    pub protocols: Vec<String>,
+}
+
+// Open connections and what peers told us about themselves via identify
+#[derive(Default)]
+pub(crate) struct ConnectionBook {
+    connections: HashMap<ConnectionId, (PeerId, ConnectedPoint, SystemTime)>,
+    identified: HashMap<PeerId, (String, Vec<StreamProtocol>)>,
+}
+
+impl ConnectionBook {
+    pub(crate) fn established(
+        &mut self,
+        connection_id: ConnectionId,
+        peer_id: PeerId,
+        endpoint: ConnectedPoint,
+    ) {
+        self.connections
+            .insert(connection_id, (peer_id, endpoint, SystemTime::now()));
+    }
+
+    pub(crate) fn closed(&mut self, connection_id: ConnectionId, peer_id: PeerId, remaining: u32) {
+        self.connections.remove(&connection_id);
+        if remaining == 0 {
+            self.identified.remove(&peer_id);
+        }
+    }
+
+    pub(crate) fn identified(
+        &mut self,
+        peer_id: PeerId,
+        agent_version: String,
+        protocols: Vec<StreamProtocol>,
+    ) {
+        self.identified.insert(peer_id, (agent_version, protocols));
+    }
+
+    pub(crate) fn snapshot(&self, negotiated: &NegotiatedSecurity) -> Vec<ConnectionInfo> {
+        let negotiated = negotiated
+            .lock()
+            .expect("negotiated security lock poisoned");
+
+        self.connections
+            .values()
+            .map(|(peer_id, endpoint, established_at)| {
+                let identified = self.identified.get(peer_id);
+                ConnectionInfo {
+                    peer_id: *peer_id,
+                    remote_address: endpoint.get_remote_address().clone(),
+                    outbound: endpoint.is_dialer(),
+                    established_at: *established_at,
+                    security: negotiated.get(peer_id).map(|info| info.to_string()),
+                    // Yamux is the only muxer we offer
+                    muxer: "/yamux/1.0.0".to_string(),
+                    agent_version: identified.map(|(agent_version, _)| agent_version.clone()),
+                    protocols: identified.map_or_else(Vec::new, |(_, protocols)| {
+                        protocols
+                            .iter()
+                            .map(|protocol| protocol.to_string())
+                            .collect()
+                    }),
+                }
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,93 @@
+use crate::{SplashError, SplashEvent};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// On-chain state of an offer, as observed by the node announcing it
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OfferStatus {
+    Taken,
+    Expired,
+}
+
+impl FromStr for OfferStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "taken" => Ok(OfferStatus::Taken),
+            "expired" => Ok(OfferStatus::Expired),
+            _ => Err(format!("Unknown offer status: {}", s)),
+        }
+    }
+}
+
+// Notices about offers gossiped separately from the offers themselves, cancellations on the control
+// topic and status changes on the status topic. They are unauthenticated, consumers should confirm
+// them on-chain before acting on them
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub(crate) enum ControlMessage {
+    OfferCancelled {
+        offer_id: String,
+    },
+    OfferStatus {
+        offer_id: String,
+        status: OfferStatus,
+    },
+}
+
+impl ControlMessage {
+    // A message is only valid on its own topic
+    pub(crate) fn parse(data: &[u8], status_topic: bool) -> Result<Self, String> {
+        let message: ControlMessage = serde_json::from_slice(data).map_err(|e| e.to_string())?;
+        if message.is_status() != status_topic {
+            return Err("message on the wrong topic".to_string());
+        }
+
+        if !is_offer_id(message.offer_id()) {
+            return Err(format!("invalid offer id: {}", message.offer_id()));
+        }
+        Ok(message)
+    }
+
+    fn offer_id(&self) -> &str {
+        match self {
+            ControlMessage::OfferCancelled { offer_id }
+            | ControlMessage::OfferStatus { offer_id, .. } => offer_id,
+        }
+    }
+
+    pub(crate) fn is_status(&self) -> bool {
+        matches!(self, ControlMessage::OfferStatus { .. })
+    }
+
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        serde_json::to_vec(self).expect("control message serializes")
+    }
+
+    pub(crate) fn into_event(self) -> SplashEvent {
+        match self {
+            ControlMessage::OfferCancelled { offer_id } => SplashEvent::OfferCancelled(offer_id),
+            ControlMessage::OfferStatus { offer_id, status } => {
+                SplashEvent::OfferStatusChanged { offer_id, status }
+            }
+        }
+    }
+}
+
+// Offers are identified by the lowercase hex SHA-256 of the offer string
+pub(crate) fn offer_id(offer_id: &str) -> Result<String, SplashError> {
+    let offer_id = offer_id.to_ascii_lowercase();
+    if !is_offer_id(&offer_id) {
+        return Err(SplashError::InvalidOfferId);
+    }
+    Ok(offer_id)
+}
+
+fn is_offer_id(offer_id: &str) -> bool {
+    offer_id.len() == 64
+        && offer_id
+            .chars()
+            .all(|c| c.is_ascii_digit() || ('a'..='f').contains(&c))
+}
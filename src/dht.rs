@@ -0,0 +1,110 @@
+use libp2p::kad::{
+    self,
+    store::{MemoryStore, MemoryStoreConfig, RecordStore},
+};
+use libp2p::PeerId;
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+use tokio::time::Instant;
+
+// Splash uses the DHT for peer routing and provider records of recent offers, other records are
+// whatever other peers put on us
+const MAX_RECORDS: usize = 1024;
+const MAX_RECORD_BYTES: usize = 16 * 1024;
+pub(crate) const MAX_PROVIDED_KEYS: usize = 256;
+const MAX_PROVIDERS_PER_KEY: usize = 20;
+
+pub(crate) const CLEANUP_INTERVAL: Duration = Duration::from_secs(10 * 60);
+// Routing table peers we haven't been connected to for this long are dropped
+const STALE_PEER_EXPIRY: Duration = Duration::from_secs(60 * 60);
+
+/// Current size of the Kademlia routing table and record store
+#[derive(Clone, Debug)]
+pub struct DhtStats {
+    pub routing_table_peers: usize,
+    pub records: usize,
+    pub provider_records: usize,
+}
+
+pub(crate) fn store(peer_id: PeerId) -> MemoryStore {
+    let config = MemoryStoreConfig {
+        max_records: MAX_RECORDS,
+        max_value_bytes: MAX_RECORD_BYTES,
+        max_provided_keys: MAX_PROVIDED_KEYS,
+        max_providers_per_key: MAX_PROVIDERS_PER_KEY,
+    };
+
+    MemoryStore::with_config(peer_id, config)
+}
+
+pub(crate) fn stats(kademlia: &mut kad::Behaviour<MemoryStore>) -> DhtStats {
+    let routing_table_peers = kademlia.kbuckets().map(|bucket| bucket.num_entries()).sum();
+    let store = kademlia.store_mut();
+
+    DhtStats {
+        routing_table_peers,
+        records: store.records().count(),
+        provider_records: store.provided().count(),
+    }
+}
+
+// Tracks when routing table peers were last connected, so unreachable ones can be expired
+pub(crate) struct StalePeers {
+    last_seen: HashMap<PeerId, Instant>,
+    // Bootstrap peers are never expired, they are our way back into the network
+    keep: HashSet<PeerId>,
+}
+
+impl StalePeers {
+    pub(crate) fn new(keep: impl IntoIterator<Item = PeerId>) -> Self {
+        StalePeers {
+            last_seen: HashMap::new(),
+            keep: keep.into_iter().collect(),
+        }
+    }
+
+    pub(crate) fn seen(&mut self, peer_id: PeerId) {
+        self.last_seen.insert(peer_id, Instant::now());
+    }
+
+    // Routing table peers that weren't connected within the expiry, the clock of peers we never
+    // connected to starts when they are first checked
+    pub(crate) fn expired(
+        &mut self,
+        routing_peers: Vec<PeerId>,
+        is_connected: impl Fn(&PeerId) -> bool,
+    ) -> Vec<PeerId> {
+        let now = Instant::now();
+        let current: HashSet<PeerId> = routing_peers.iter().copied().collect();
+        self.last_seen
+            .retain(|peer_id, _| current.contains(peer_id));
+
+        let mut expired = Vec::new();
+        for peer_id in routing_peers {
+            if self.keep.contains(&peer_id) || is_connected(&peer_id) {
+                self.last_seen.insert(peer_id, now);
+                continue;
+            }
+
+            let last_seen = *self.last_seen.entry(peer_id).or_insert(now);
+            if now.duration_since(last_seen) >= STALE_PEER_EXPIRY {
+                self.last_seen.remove(&peer_id);
+                expired.push(peer_id);
+            }
+        }
+
+        expired
+    }
+}
+
+pub(crate) fn routing_peers(kademlia: &mut kad::Behaviour<MemoryStore>) -> Vec<PeerId> {
+    kademlia
+        .kbuckets()
+        .flat_map(|bucket| {
+            bucket
+                .iter()
+                .map(|entry| *entry.node.key.preimage())
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
@@ -0,0 +1,113 @@
+use libp2p::multiaddr::Protocol;
+use libp2p::request_response::{self, OutboundRequestId, ProtocolSupport, ResponseChannel};
+use libp2p::swarm::ConnectionId;
+use libp2p::{Multiaddr, PeerId, StreamProtocol};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DialBackRequest {
+    pub(crate) address: Multiaddr,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DialBackResponse {
+    pub(crate) reachable: bool,
+}
+
+pub(crate) type Behaviour = request_response::json::Behaviour<DialBackRequest, DialBackResponse>;
+
+// Leaves room for the dial itself, which may run into the TCP connect timeout
+pub(crate) fn behaviour(protocol_prefix: &str) -> Behaviour {
+    request_response::json::Behaviour::new(
+        [(
+            StreamProtocol::try_from_owned(format!("{}/dialback/1", protocol_prefix))
+                .expect("protocol name is valid"),
+            ProtocolSupport::Full,
+        )],
+        request_response::Config::default().with_request_timeout(Duration::from_secs(30)),
+    )
+}
+
+// Both sides of the dial-back protocol: asking peers to dial our address candidates, and dialing for them
+#[derive(Default)]
+pub(crate) struct DialBack {
+    // Remote addresses of established connections, we only dial back the IP a request came from
+    connections: HashMap<ConnectionId, (PeerId, Multiaddr)>,
+    // Dials we do for peers, answered once the connection succeeds or fails
+    dialing: HashMap<ConnectionId, ResponseChannel<DialBackResponse>>,
+    candidates: HashSet<Multiaddr>,
+    requests: HashMap<OutboundRequestId, Multiaddr>,
+}
+
+impl DialBack {
+    pub(crate) fn connection_established(
+        &mut self,
+        connection_id: ConnectionId,
+        peer_id: PeerId,
+        remote: Multiaddr,
+    ) {
+        self.connections.insert(connection_id, (peer_id, remote));
+    }
+
+    pub(crate) fn connection_closed(&mut self, connection_id: ConnectionId) {
+        self.connections.remove(&connection_id);
+    }
+
+    // Each candidate is checked once, unless asking about it failed
+    pub(crate) fn is_new_candidate(&mut self, address: &Multiaddr) -> bool {
+        self.candidates.insert(address.clone())
+    }
+
+    // After a network change addresses may need to be confirmed again
+    pub(crate) fn forget_candidates(&mut self) {
+        self.candidates.clear();
+    }
+
+    pub(crate) fn requested(&mut self, request_id: OutboundRequestId, address: Multiaddr) {
+        self.requests.insert(request_id, address);
+    }
+
+    pub(crate) fn answered(&mut self, request_id: OutboundRequestId) -> Option<Multiaddr> {
+        self.requests.remove(&request_id)
+    }
+
+    pub(crate) fn failed(&mut self, request_id: OutboundRequestId) {
+        if let Some(address) = self.requests.remove(&request_id) {
+            self.candidates.remove(&address);
+        }
+    }
+
+    // Refuse to dial anything but the requesting peer's own IP, so we can't be used to scan others
+    pub(crate) fn may_dial(&self, peer_id: &PeerId, address: &Multiaddr) -> bool {
+        let ip = |addr: &Multiaddr| match addr.iter().next() {
+            Some(Protocol::Ip4(ip)) => Some(ip.to_string()),
+            Some(Protocol::Ip6(ip)) => Some(ip.to_string()),
+            _ => None,
+        };
+
+        let Some(requested) = ip(address) else {
+            return false;
+        };
+
+        self.connections
+            .values()
+            .any(|(peer, remote)| peer == peer_id && ip(remote).as_ref() == Some(&requested))
+    }
+
+    pub(crate) fn dialing(
+        &mut self,
+        connection_id: ConnectionId,
+        channel: ResponseChannel<DialBackResponse>,
+    ) {
+        self.dialing.insert(connection_id, channel);
+    }
+
+    pub(crate) fn dialed(
+        &mut self,
+        connection_id: ConnectionId,
+    ) -> Option<ResponseChannel<DialBackResponse>> {
+        self.dialing.remove(&connection_id)
+    }
+}
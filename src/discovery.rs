@@ -0,0 +1,44 @@
+use std::time::Duration;
+
+const MIN_DISCOVERY_INTERVAL: Duration = Duration::from_secs(10);
+const MAX_DISCOVERY_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+// Queries the DHT aggressively below the target peer count and backs off once the node is well connected
+pub(crate) struct AdaptiveDiscovery {
+    target_peers: usize,
+    interval: Duration,
+}
+
+impl AdaptiveDiscovery {
+    pub(crate) fn new(target_peers: usize) -> Self {
+        AdaptiveDiscovery {
+            target_peers,
+            interval: MIN_DISCOVERY_INTERVAL,
+        }
+    }
+
+    pub(crate) fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    // Called after each discovery query, returns the delay until the next one
+    pub(crate) fn next_interval(&mut self, peers: usize) -> Duration {
+        self.interval = if peers < self.target_peers {
+            MIN_DISCOVERY_INTERVAL
+        } else {
+            (self.interval * 2).min(MAX_DISCOVERY_INTERVAL)
+        };
+
+        self.interval
+    }
+
+    // Called when a peer is lost, returns a new delay if discovery should speed up right away
+    pub(crate) fn on_peer_lost(&mut self, peers: usize) -> Option<Duration> {
+        if peers >= self.target_peers || self.interval == MIN_DISCOVERY_INTERVAL {
+            return None;
+        }
+
+        self.interval = MIN_DISCOVERY_INTERVAL;
+        Some(self.interval)
+    }
+}
@@ -1,6 +1,22 @@
 use hickory_resolver::{error::ResolveError, TokioAsyncResolver};
 use libp2p::Multiaddr;
+use serde::Deserialize;
+use std::net::SocketAddr;
 use std::str::FromStr;
+use std::time::Duration;
+use tokio::sync::mpsc::Sender;
+use tokio::time::Instant;
+
+// DNS over HTTPS endpoint used when lookups have to go through a SOCKS5 proxy
+const DOH_ENDPOINT: &str = "https://cloudflare-dns.com/dns-query";
+
+// Long-running bootstrap nodes per network, used when the introducer lookup fails (captive portal,
+// DNS outage). Entries must end in /p2p/<peer id>
+const FALLBACK_PEERS: &[(&str, &[&str])] = &[("splash", &[]), ("splash-testnet", &[])];
+
+pub(crate) const REFRESH_INTERVAL: Duration = Duration::from_secs(60 * 60);
+// Lower bound between lookups triggered by losing all peers
+const MIN_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
 
 pub async fn resolve_peers_from_dns(network_name: String) -> Result<Vec<Multiaddr>, ResolveError> {
     let (config, mut opts) = hickory_resolver::system_conf::read_system_conf()?;
@@ -17,8 +33,7 @@ pub async fn resolve_peers_from_dns(network_name: String) -> Result<Vec<Multiadd
         .iter()
         .flat_map(|record| record.txt_data())
         .filter_map(|txt| std::str::from_utf8(txt).ok())
-        .map(|addr_str| addr_str.trim_start_matches("dnsaddr="))
-        .filter_map(|addr_str| Multiaddr::from_str(addr_str).ok())
+        .filter_map(parse_dnsaddr)
         .collect();
 
     if peers.is_empty() {
@@ -27,3 +42,137 @@ pub async fn resolve_peers_from_dns(network_name: String) -> Result<Vec<Multiadd
         Ok(peers)
     }
 }
+
+#[derive(Deserialize)]
+struct DohResponse {
+    #[serde(rename = "Answer", default)]
+    answer: Vec<DohAnswer>,
+}
+
+#[derive(Deserialize)]
+struct DohAnswer {
+    data: String,
+}
+
+/// Same lookup as `resolve_peers_from_dns`, but over HTTPS through the proxy, so no DNS traffic leaves directly
+pub async fn resolve_peers_via_proxy(
+    network_name: String,
+    proxy: SocketAddr,
+) -> Result<Vec<Multiaddr>, Box<dyn std::error::Error>> {
+    let client = reqwest::Client::builder()
+        .proxy(reqwest::Proxy::all(format!("socks5h://{}", proxy))?)
+        .build()?;
+
+    let response: DohResponse = client
+        .get(DOH_ENDPOINT)
+        .query(&[
+            ("name", format!("_dnsaddr.{}.dexie.space.", network_name)),
+            ("type", "TXT".to_string()),
+        ])
+        .header("Accept", "application/dns-json")
+        .send()
+        .await?
+        .json()
+        .await?;
+
+    // TXT data is quoted and split into chunks of at most 255 characters: "dnsaddr=/ip4/..." "..."
+    let peers: Vec<Multiaddr> = response
+        .answer
+        .iter()
+        .map(|answer| {
+            answer
+                .data
+                .split('"')
+                .skip(1)
+                .step_by(2)
+                .collect::<String>()
+        })
+        .filter_map(|txt| parse_dnsaddr(&txt))
+        .collect();
+
+    if peers.is_empty() {
+        Err("No peers found".into())
+    } else {
+        Ok(peers)
+    }
+}
+
+pub(crate) fn fallback_peers(network_name: &str) -> Vec<Multiaddr> {
+    FALLBACK_PEERS
+        .iter()
+        .filter(|(network, _)| *network == network_name)
+        .flat_map(|(_, peers)| peers.iter())
+        .filter_map(|addr| Multiaddr::from_str(addr).ok())
+        .collect()
+}
+
+// Resolves the introducers, through the proxy if one is configured
+pub(crate) async fn resolve_introducers(
+    network_name: String,
+    proxy: Option<SocketAddr>,
+) -> Result<Vec<Multiaddr>, String> {
+    match proxy {
+        Some(proxy) => resolve_peers_via_proxy(network_name, proxy)
+            .await
+            .map_err(|e| e.to_string()),
+        None => resolve_peers_from_dns(network_name)
+            .await
+            .map_err(|e| e.to_string()),
+    }
+}
+
+// Resolves the introducers again in the background, so long-running nodes pick up rotated infrastructure
+pub(crate) struct IntroducerRefresh {
+    network_name: String,
+    proxy: Option<SocketAddr>,
+    resolving: bool,
+    resolved_at: Instant,
+    results: Sender<Result<Vec<Multiaddr>, String>>,
+}
+
+impl IntroducerRefresh {
+    pub(crate) fn new(
+        network_name: String,
+        proxy: Option<SocketAddr>,
+        results: Sender<Result<Vec<Multiaddr>, String>>,
+    ) -> Self {
+        IntroducerRefresh {
+            network_name,
+            proxy,
+            resolving: false,
+            resolved_at: Instant::now(),
+            results,
+        }
+    }
+
+    pub(crate) fn start(&mut self) {
+        if self.resolving {
+            return;
+        }
+        self.resolving = true;
+        self.resolved_at = Instant::now();
+
+        let network_name = self.network_name.clone();
+        let proxy = self.proxy;
+        let results = self.results.clone();
+        tokio::spawn(async move {
+            let result = resolve_introducers(network_name, proxy).await;
+            results.send(result).await.ok();
+        });
+    }
+
+    // The node lost all its peers, the introducers it knows may be gone
+    pub(crate) fn on_peers_lost(&mut self) {
+        if self.resolved_at.elapsed() >= MIN_REFRESH_INTERVAL {
+            self.start();
+        }
+    }
+
+    pub(crate) fn finished(&mut self) {
+        self.resolving = false;
+    }
+}
+
+fn parse_dnsaddr(txt: &str) -> Option<Multiaddr> {
+    Multiaddr::from_str(txt.trim_start_matches("dnsaddr=")).ok()
+}
@@ -0,0 +1,36 @@
+use crate::SplashEvent;
+use tokio::sync::mpsc::{self, error::TrySendError};
+
+// Sends events without waiting for the consumer, so an embedder that stops polling its events can't
+// stall the swarm. Events that don't fit into the channel are dropped and counted, the count is reported
+// as `EventsDropped` once the consumer catches up
+pub(crate) struct EventSender {
+    tx: mpsc::Sender<SplashEvent>,
+    dropped: u64,
+}
+
+impl EventSender {
+    pub(crate) fn new(tx: mpsc::Sender<SplashEvent>) -> Self {
+        EventSender { tx, dropped: 0 }
+    }
+
+    pub(crate) fn send(&mut self, event: SplashEvent) {
+        if self.dropped > 0 {
+            match self.tx.try_send(SplashEvent::EventsDropped(self.dropped)) {
+                Ok(()) => self.dropped = 0,
+                Err(TrySendError::Full(_)) => {
+                    self.dropped += 1;
+                    return;
+                }
+                Err(TrySendError::Closed(_)) => return,
+            }
+        }
+
+        if let Err(TrySendError::Full(_)) = self.tx.try_send(event) {
+            if self.dropped == 0 {
+                log::warn!("Event consumer fell behind, dropping events");
+            }
+            self.dropped += 1;
+        }
+    }
+}
@@ -0,0 +1,57 @@
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+
+// Offers waiting for the reader, further ones are dropped instead of holding up the event loop
+const FIFO_BUFFER: usize = 1024;
+
+// Writes received offers to a named pipe, one per line. Opening the pipe waits for a reader, once the
+// reader goes away the next offer waits for a new one
+pub struct OfferFifo {
+    tx: mpsc::Sender<String>,
+}
+
+impl OfferFifo {
+    // The pipe has to exist, it's not created so a typo doesn't end up as a regular file
+    pub fn spawn(path: String) -> Result<Self, String> {
+        if !std::path::Path::new(&path).exists() {
+            return Err(format!(
+                "Offer FIFO {} doesn't exist, create it with mkfifo",
+                path
+            ));
+        }
+
+        let (tx, mut rx) = mpsc::channel::<String>(FIFO_BUFFER);
+
+        tokio::spawn(async move {
+            let mut pipe = None;
+            while let Some(line) = rx.recv().await {
+                if pipe.is_none() {
+                    match tokio::fs::OpenOptions::new().write(true).open(&path).await {
+                        Ok(file) => pipe = Some(file),
+                        Err(e) => {
+                            eprintln!("Error opening offer FIFO {}: {}", path, e);
+                            continue;
+                        }
+                    }
+                }
+
+                if let Some(file) = pipe.as_mut() {
+                    let written = async {
+                        file.write_all(line.as_bytes()).await?;
+                        file.flush().await
+                    };
+                    if let Err(e) = written.await {
+                        eprintln!("Offer FIFO reader went away: {}", e);
+                        pipe = None;
+                    }
+                }
+            }
+        });
+
+        Ok(OfferFifo { tx })
+    }
+
+    pub fn send(&self, offer: &str) {
+        self.tx.try_send(format!("{}\n", offer)).ok();
+    }
+}
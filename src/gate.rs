@@ -0,0 +1,224 @@
+use libp2p::core::{transport::PortUse, Endpoint};
+use libp2p::multiaddr::Protocol;
+use libp2p::swarm::{
+    dummy, ConnectionDenied, ConnectionId, FromSwarm, NetworkBehaviour, THandler, THandlerInEvent,
+    THandlerOutEvent, ToSwarm,
+};
+use libp2p::{Multiaddr, PeerId};
+use std::collections::HashSet;
+use std::convert::Infallible;
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::task::{Context, Poll};
+use thiserror::Error;
+
+/// An IP network like `203.0.113.0/24` or `2001:db8::/32`, a plain address only matches itself
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cidr {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        let (network, ip, bits) = match (self.network, ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                (u32::from(network) as u128, u32::from(*ip) as u128, 32)
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => (u128::from(network), u128::from(*ip), 128),
+            _ => return false,
+        };
+
+        self.prefix == 0 || (network ^ ip) >> (bits - self.prefix) == 0
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (network, prefix) = s.split_once('/').unwrap_or((s, ""));
+        let network: IpAddr = network
+            .parse()
+            .map_err(|e| format!("invalid CIDR {}: {}", s, e))?;
+        let max_prefix = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            "" => max_prefix,
+            prefix => prefix
+                .parse()
+                .ok()
+                .filter(|prefix| *prefix <= max_prefix)
+                .ok_or_else(|| format!("invalid CIDR {}: bad prefix length", s))?,
+        };
+
+        Ok(Cidr { network, prefix })
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix)
+    }
+}
+
+/// Parses a deny list, one CIDR or peer ID per line, `#` starts a comment
+pub fn parse_deny_list(text: &str) -> Result<(Vec<Cidr>, Vec<PeerId>), String> {
+    let mut cidrs = Vec::new();
+    let mut peers = Vec::new();
+
+    for line in text.lines() {
+        let entry = line.split('#').next().unwrap_or_default().trim();
+        if entry.is_empty() {
+            continue;
+        }
+
+        if let Ok(peer_id) = PeerId::from_str(entry) {
+            peers.push(peer_id);
+        } else {
+            cidrs.push(Cidr::from_str(entry)?);
+        }
+    }
+
+    Ok((cidrs, peers))
+}
+
+// Which hosts and peers may connect, an empty allowlist allows everyone not denied
+#[derive(Clone, Default)]
+pub(crate) struct Rules {
+    pub(crate) allowed_cidrs: Vec<Cidr>,
+    pub(crate) denied_cidrs: Vec<Cidr>,
+    pub(crate) denied_peers: HashSet<PeerId>,
+}
+
+#[derive(Debug, Error)]
+enum Denied {
+    #[error("Connections with {0} are not allowed")]
+    Ip(IpAddr),
+    #[error("Connections with peer {0} are not allowed")]
+    Peer(PeerId),
+}
+
+/// Rejects connections with denied IP ranges and peers in both directions, so public nodes can block
+/// abusive hosts without an external firewall
+pub struct ConnectionGate {
+    rules: Rules,
+    // From a blocklist subscription, replaced on every refresh
+    subscribed: Rules,
+}
+
+impl ConnectionGate {
+    pub(crate) fn new(rules: Rules) -> Self {
+        ConnectionGate {
+            rules,
+            subscribed: Rules::default(),
+        }
+    }
+
+    pub(crate) fn set_subscribed(&mut self, denied_cidrs: Vec<Cidr>, denied_peers: Vec<PeerId>) {
+        self.subscribed = Rules {
+            allowed_cidrs: Vec::new(),
+            denied_cidrs,
+            denied_peers: denied_peers.into_iter().collect(),
+        };
+    }
+
+    pub(crate) fn is_denied(&self, peer: &PeerId) -> bool {
+        self.check_peer(peer).is_err()
+    }
+
+    fn check_address(&self, addr: &Multiaddr) -> Result<(), ConnectionDenied> {
+        let ip: IpAddr = match addr.iter().next() {
+            Some(Protocol::Ip4(ip)) => ip.into(),
+            Some(Protocol::Ip6(ip)) => ip.into(),
+            _ => return Ok(()),
+        };
+
+        let in_any = |cidrs: &[Cidr]| cidrs.iter().any(|cidr| cidr.contains(&ip));
+        let allowed = self.rules.allowed_cidrs.is_empty() || in_any(&self.rules.allowed_cidrs);
+        if !allowed || in_any(&self.rules.denied_cidrs) || in_any(&self.subscribed.denied_cidrs) {
+            return Err(ConnectionDenied::new(Denied::Ip(ip)));
+        }
+
+        Ok(())
+    }
+
+    fn check_peer(&self, peer: &PeerId) -> Result<(), ConnectionDenied> {
+        if self.rules.denied_peers.contains(peer) || self.subscribed.denied_peers.contains(peer) {
+            return Err(ConnectionDenied::new(Denied::Peer(*peer)));
+        }
+
+        Ok(())
+    }
+}
+
+impl NetworkBehaviour for ConnectionGate {
+    type ConnectionHandler = dummy::ConnectionHandler;
+    type ToSwarm = Infallible;
+
+    fn handle_pending_inbound_connection(
+        &mut self,
+        _connection_id: ConnectionId,
+        _local_addr: &Multiaddr,
+        remote_addr: &Multiaddr,
+    ) -> Result<(), ConnectionDenied> {
+        self.check_address(remote_addr)
+    }
+
+    fn handle_established_inbound_connection(
+        &mut self,
+        _connection_id: ConnectionId,
+        peer: PeerId,
+        _local_addr: &Multiaddr,
+        _remote_addr: &Multiaddr,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        self.check_peer(&peer)?;
+        Ok(dummy::ConnectionHandler)
+    }
+
+    fn handle_pending_outbound_connection(
+        &mut self,
+        _connection_id: ConnectionId,
+        maybe_peer: Option<PeerId>,
+        _addresses: &[Multiaddr],
+        _effective_role: Endpoint,
+    ) -> Result<Vec<Multiaddr>, ConnectionDenied> {
+        if let Some(peer) = maybe_peer {
+            self.check_peer(&peer)?;
+        }
+
+        Ok(Vec::new())
+    }
+
+    // The address is only known for sure once connected, other behaviours add their own before dialing
+    fn handle_established_outbound_connection(
+        &mut self,
+        _connection_id: ConnectionId,
+        peer: PeerId,
+        addr: &Multiaddr,
+        _role_override: Endpoint,
+        _port_use: PortUse,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        self.check_peer(&peer)?;
+        self.check_address(addr)?;
+        Ok(dummy::ConnectionHandler)
+    }
+
+    fn on_swarm_event(&mut self, _event: FromSwarm) {}
+
+    fn on_connection_handler_event(
+        &mut self,
+        _peer_id: PeerId,
+        _connection_id: ConnectionId,
+        event: THandlerOutEvent<Self>,
+    ) {
+        match event {}
+    }
+
+    fn poll(
+        &mut self,
+        _cx: &mut Context<'_>,
+    ) -> Poll<ToSwarm<Self::ToSwarm, THandlerInEvent<Self>>> {
+        Poll::Pending
+    }
+}
@@ -0,0 +1,121 @@
+use crate::{
+    chunks, middleware, seen, seen_key, topic_offer_hash, topics, InboundOffer, OfferMetadata,
+    Splash, MAX_OFFER_SIZE,
+};
+use libp2p::gossipsub::{MessageAcceptance, TopicHash};
+use log::warn;
+use std::future::Future;
+use std::sync::Arc;
+
+/// What became of a message on an offers topic, see `InboundPipeline::process`
+pub enum Inbound {
+    /// An invalid message, or a chunk of an offer that's still incomplete
+    Dropped(MessageAcceptance),
+    /// A valid offer that was delivered before. Copies from another offers topic are still accepted, so
+    /// nodes only on that topic get it, re-broadcasts on the same topic are ignored
+    Duplicate(String, MessageAcceptance),
+    /// A new valid offer, accept the message and deliver the offer
+    New(String, OfferMetadata),
+    /// A new valid offer for the inbound middleware, the message is reported once `filter` is done
+    Filter(String, OfferMetadata),
+}
+
+impl Inbound {
+    /// The valid offer the message carried or completed
+    pub fn offer(&self) -> Option<&str> {
+        match self {
+            Inbound::Dropped(_) => None,
+            Inbound::Duplicate(offer, _) | Inbound::New(offer, _) | Inbound::Filter(offer, _) => {
+                Some(offer)
+            }
+        }
+    }
+}
+
+/// Decodes, validates and deduplicates the messages of the offers topics, and runs the inbound
+/// middleware. The node loop feeds it every offers topic message, embedders driving `SplashBehaviour`
+/// themselves can do the same with `Splash::inbound_pipeline`
+pub struct InboundPipeline {
+    topics: topics::OfferTopics,
+    reassembly: chunks::Reassembly,
+    // Remembers delivered offers beyond gossipsub's duplicate cache
+    pub(crate) seen: seen::SeenCache,
+    // The topics each offer was forwarded on, a copy on another offers topic is forwarded there too
+    pub(crate) forwarded: seen::SeenCache,
+    middleware: Arc<[middleware::InboundMiddleware]>,
+}
+
+impl InboundPipeline {
+    pub(crate) fn new(splash: &Splash) -> Result<Self, String> {
+        Ok(InboundPipeline {
+            topics: splash.offer_topics()?,
+            reassembly: chunks::Reassembly::new(MAX_OFFER_SIZE),
+            seen: seen::SeenCache::new(&splash.dedup),
+            forwarded: seen::SeenCache::new(&splash.dedup),
+            middleware: splash.inbound_middleware.clone().into(),
+        })
+    }
+
+    /// Whether new offers go through the inbound middleware before they are delivered
+    pub fn has_middleware(&self) -> bool {
+        !self.middleware.is_empty()
+    }
+
+    /// A message received on one of the offers topics. The chunk completing an offer is reported like
+    /// an unchunked offer, earlier chunks are forwarded if well-formed
+    pub fn process(&mut self, topic: &TopicHash, data: Vec<u8>) -> Inbound {
+        let (data, metadata) = if self.topics.is_chunk(topic) {
+            match self.reassembly.add(&data) {
+                Ok(Some(offer)) => (offer.into_bytes(), OfferMetadata::default()),
+                Ok(None) => return Inbound::Dropped(MessageAcceptance::Accept),
+                Err(e) => {
+                    warn!("Received invalid offer chunk: {}", e);
+                    return Inbound::Dropped(MessageAcceptance::Reject);
+                }
+            }
+        } else {
+            match self.topics.decode(topic, data) {
+                Ok(decoded) => decoded,
+                Err(e) => {
+                    warn!("Received undecodable offer: {}", e);
+                    return Inbound::Dropped(MessageAcceptance::Reject);
+                }
+            }
+        };
+
+        let offer = String::from_utf8_lossy(&data).into_owned();
+        if let Err(e) = Splash::validate_offer(&offer) {
+            warn!("Received invalid offer: {}", e);
+            return Inbound::Dropped(MessageAcceptance::Reject);
+        }
+
+        let duplicate = !self.seen.insert(seen_key(&data));
+        let forwarded = !self.forwarded.insert(topic_offer_hash(topic, &data));
+
+        if forwarded {
+            Inbound::Duplicate(offer, MessageAcceptance::Ignore)
+        } else if duplicate {
+            Inbound::Duplicate(offer, MessageAcceptance::Accept)
+        } else if self.has_middleware() {
+            Inbound::Filter(offer, metadata)
+        } else {
+            Inbound::New(offer, metadata)
+        }
+    }
+
+    /// Runs the inbound middleware chain, None if a middleware dropped the offer. It may take its time,
+    /// the node loop runs it off the loop
+    pub fn filter(
+        &self,
+        offer: InboundOffer,
+    ) -> impl Future<Output = Option<InboundOffer>> + Send + 'static {
+        let chain = self.middleware.clone();
+        async move { middleware::run_inbound(&chain, offer).await }
+    }
+
+    /// Forgets offers past the dedup TTL
+    pub fn prune(&mut self) {
+        self.seen.prune();
+        self.forwarded.prune();
+    }
+}
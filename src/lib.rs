@@ -1,18 +1,94 @@
-use futures::stream::StreamExt;
-use libp2p::gossipsub::MessageAcceptance;
-use libp2p::multiaddr::Protocol;
-use libp2p::{gossipsub, kad, noise, swarm::NetworkBehaviour, swarm::SwarmEvent, tcp, yamux};
-use libp2p::{identify, identity, Multiaddr, PeerId, StreamProtocol};
-use log::warn;
+use libp2p::{autonat, gossipsub, Multiaddr, PeerId};
+use sha2::{Digest, Sha256};
 use std::collections::hash_map::DefaultHasher;
 use std::hash::{Hash, Hasher};
 use std::time::Duration;
 use thiserror::Error;
-use tokio::sync::mpsc::{self, Receiver, Sender};
-use tokio::{io, select, time};
+use tokio::sync::oneshot;
+use tokio::time;
+mod addresses;
+mod alerts;
+mod api;
+mod auth;
+mod behaviour;
+mod blocklist;
+mod canary;
+mod capabilities;
+mod chaos;
+mod chunks;
+mod cluster;
+pub mod codec;
+mod compression;
+mod connections;
+mod control;
+mod dht;
+mod dialback;
+mod discovery;
 mod dns;
-
-const MAX_OFFER_SIZE: usize = 300 * 1024;
+mod events;
+mod gate;
+mod inbound;
+mod limits;
+mod listeners;
+mod mesh;
+mod middleware;
+mod node;
+mod offer;
+mod propagation;
+mod push;
+mod queue;
+mod reconnect;
+mod scoring;
+mod seen;
+#[cfg(feature = "simulation")]
+pub mod simulation;
+mod state;
+mod status;
+mod sync;
+mod throttle;
+mod topics;
+mod transport;
+
+pub use alerts::AlertThresholds;
+pub use api::{Splash, SplashContext};
+pub use behaviour::{BehaviourConfig, SplashBehaviour, SplashBehaviourEvent};
+#[cfg(feature = "testing")]
+pub use chaos::ChaosConfig;
+pub use codec::OfferMetadata;
+pub use compression::OfferCompression;
+pub use connections::ConnectionInfo;
+pub use control::OfferStatus;
+pub use dht::DhtStats;
+pub use gate::{parse_deny_list, Cidr};
+pub use inbound::{Inbound, InboundPipeline};
+pub use mesh::{MeshConfig, MeshRemovalReason};
+pub use middleware::{InboundOffer, OutboundOffer};
+pub use offer::{decode_offer, encode_offer, offer_from_bytes};
+pub use propagation::PropagationRecord;
+pub use scoring::PeerScoring;
+pub use seen::{DedupConfig, DedupStats};
+pub use state::{KnownPeer, NodeState};
+pub use status::StatusSummary;
+pub use transport::{AddressFamily, SecurityUpgrades};
+
+const DEFAULT_INBOUND_OFFER_RATE: f64 = 20.0;
+const DEFAULT_INBOUND_OFFER_BURST: u32 = 100;
+const RATE_LIMIT_REPORT_INTERVAL: Duration = Duration::from_secs(10);
+// Larger offers are split into chunks that fit into a gossip message
+const MAX_MESSAGE_SIZE: usize = 300 * 1024;
+const MAX_OFFER_SIZE: usize = 4 * 1024 * 1024;
+const DEFAULT_TARGET_PEERS: usize = 20;
+const INTRODUCER_TARGET_PEERS: usize = 100;
+const PENDING_RETRY_INTERVAL: Duration = Duration::from_secs(10);
+const RAW_MESSAGE_BUFFER: usize = 1024;
+const EVENT_BUFFER: usize = 100;
+const DEFAULT_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(20);
+const DEFAULT_DIAL_TIMEOUT: Duration = Duration::from_secs(10);
+const DEFAULT_DIAL_CONCURRENCY_FACTOR: u8 = 8;
+const DEFAULT_MAX_NEGOTIATING_INBOUND_STREAMS: usize = 128;
+const BOOTSTRAP_RETRY_INTERVAL: Duration = Duration::from_secs(30);
+// Mesh peers on the offers topic from which published offers reach the network
+const DEFAULT_MESH_READY_PEERS: usize = 1;
 
 #[derive(Error, Debug)]
 pub enum SplashError {
@@ -22,296 +98,213 @@ pub enum SplashError {
     InvalidOfferFormat,
     #[error("Failed to send offer to network")]
     SendError,
+    #[error("Node is not running")]
+    NotRunning,
+    #[error("Failed to read offer file: {0}")]
+    OfferFile(#[from] std::io::Error),
+    #[error("Failed to publish offer: {0}")]
+    PublishFailed(String),
+    #[error("Offer was published but not to any mesh peer")]
+    NotPropagated,
+    #[error("Offer expired before it could be published")]
+    Expired,
+    #[error("Failed to persist submission: {0}")]
+    QueueError(std::io::Error),
+    #[error("Offer rejected: {0}")]
+    Rejected(String),
+    #[error("Publish rate limit exceeded, too many offers are waiting")]
+    RateLimited,
+    #[error("Canary failed: {0}")]
+    CanaryFailed(String),
+    #[error("Invalid offer id: expected the hex SHA-256 of the offer")]
+    InvalidOfferId,
+    #[error("Failed to fetch offer: {0}")]
+    FetchFailed(String),
+    #[error("No peers to publish to yet, the offer is retried in the background")]
+    Retrying,
 }
 
 pub enum SplashEvent {
     Initialized(PeerId),
+    /// The DNS introducer couldn't be resolved, bootstrapping from fallback and restored peers instead
+    BootstrapDegraded {
+        reason: String,
+        fallback_peers: usize,
+        restored_peers: usize,
+    },
     PeerConnected(PeerId),
     PeerDisconnected(PeerId),
-    OfferReceived(String),
+    OfferReceived(String, OfferMetadata),
     NewListenAddress(Multiaddr),
+    /// An address we advertise to peers, configured, taken from a listen address or confirmed by a peer
+    ExternalAddressConfirmed(Multiaddr),
+    /// A listener closed, e.g. after its interface went down, it is restarted with backoff
+    ListenerClosed {
+        address: Multiaddr,
+        retry_in: Duration,
+    },
+    ListenerRestored(Multiaddr),
+    /// The interface of a listen address went away, external addresses are confirmed again
+    ListenAddressExpired(Multiaddr),
+    /// The connection to a known peer dropped or redialing it failed, it is dialed again with backoff
+    ReconnectScheduled {
+        peer_id: PeerId,
+        attempt: u32,
+        retry_in: Duration,
+    },
+    ReconnectGaveUp(PeerId),
     OfferBroadcasted(String),
     OfferBroadcastFailed(gossipsub::PublishError),
+    /// A submitted offer's TTL passed before it could be published, it is dropped
+    OfferExpired(String),
+    PeerAuthenticated(PeerId),
+    PeerAuthenticationFailed(PeerId),
+    MeshPeerAdded(PeerId),
+    /// The offers topic reached the configured number of mesh peers, emitted once with the mesh size
+    MeshReady(usize),
+    /// Score is only available once peer scoring is enabled, backoff durations are internal to gossipsub
+    MeshPeerRemoved {
+        peer_id: PeerId,
+        reason: MeshRemovalReason,
+        score: Option<f64>,
+    },
+    DedupCacheStats(DedupStats),
+    DhtStats(DhtStats),
+    PeerIdentified {
+        peer_id: PeerId,
+        agent_version: String,
+        /// Optional features the peer announced, e.g. "relay-server" or "zstd"
+        capabilities: Vec<String>,
+    },
+    /// AutoNAT confirmed or revoked our reachability, only confirmed addresses are advertised
+    NatStatusChanged(autonat::NatStatus),
+    /// The router forwards this external address to one of our listen addresses via UPnP
+    PortMapped(Multiaddr),
+    PortMappingExpired(Multiaddr),
+    /// A peer dialed one of our address candidates on request, confirmed addresses are advertised
+    AddressConfirmed {
+        address: Multiaddr,
+        peer_id: PeerId,
+    },
+    AddressUnreachable {
+        address: Multiaddr,
+        peer_id: PeerId,
+    },
+    /// The event loop stopped after `Splash::shutdown`
+    ShutdownComplete(ShutdownSummary),
+    /// An inbound connection didn't finish the security and muxer handshake in time
+    HandshakeTimeout(Multiaddr),
+    /// Alerts per the configured `AlertThresholds`, raised once until the condition clears
+    PeerCountLow {
+        peers: usize,
+        min_peers: usize,
+    },
+    PeerCountRecovered(usize),
+    /// No offer was received for this long
+    OfferFlowStalled(Duration),
+    /// Emitted every status interval, see `Splash::with_status_interval`
+    Status(StatusSummary),
+    /// The subscribed blocklist was fetched and verified, connected peers on it are disconnected
+    BlocklistUpdated {
+        cidrs: usize,
+        peers: usize,
+    },
+    /// The previous blocklist stays in effect
+    BlocklistRefreshFailed(String),
+    /// Offers from this peer beyond the inbound rate limit were dropped, reported every 10 seconds
+    OffersRateLimited {
+        peer_id: PeerId,
+        dropped: u64,
+    },
+    /// The consumer fell more than 100 events behind and this many were dropped instead of stalling the node
+    EventsDropped(u64),
+    /// A peer announced that the offer with this id (hex SHA-256 of the offer) was cancelled. Notices
+    /// aren't authenticated, confirm them on-chain before acting on them
+    OfferCancelled(String),
+    /// A peer announced a change of the offer's on-chain state, unauthenticated like cancellations
+    OfferStatusChanged {
+        offer_id: String,
+        status: OfferStatus,
+    },
+    /// A valid offer arrived on this gossip topic, duplicates of already received offers included, e.g.
+    /// from another topic version. Tells how much traffic each version carries during a migration
+    OfferArrived {
+        topic: String,
+        duplicate: bool,
+    },
 }
 
-pub struct Splash {
-    pub listen_addresses: Vec<Multiaddr>,
-    pub known_peers: Vec<Multiaddr>,
-    pub keys: identity::Keypair,
-    network_name: String,
-    submission: Sender<Vec<u8>>,
-    submission_receiver: Option<Receiver<Vec<u8>>>,
+/// Outcome of a successful gossipsub publish
+#[derive(Clone, Debug)]
+pub struct PublishReceipt {
+    pub message_id: gossipsub::MessageId,
+    /// Number of mesh peers for the offers topic at the time of publishing
+    pub mesh_peers: usize,
 }
 
-pub struct SplashContext {
-    pub node: Splash,
-    pub events: mpsc::Receiver<SplashEvent>,
+/// A gossip message as received, before any authentication, deduplication or validation
+#[derive(Clone, Debug)]
+pub struct RawMessage {
+    pub message_id: gossipsub::MessageId,
+    /// The peer that forwarded the message to us
+    pub propagation_source: PeerId,
+    /// Signing author, nodes sign with a throwaway key so this only tells nodes apart
+    pub source: Option<PeerId>,
+    pub sequence_number: Option<u64>,
+    pub data: Vec<u8>,
+    pub received_at: std::time::SystemTime,
 }
 
-impl Clone for Splash {
-    fn clone(&self) -> Self {
-        Splash {
-            listen_addresses: self.listen_addresses.clone(),
-            known_peers: self.known_peers.clone(),
-            keys: self.keys.clone(),
-            network_name: self.network_name.clone(),
-            submission: self.submission.clone(),
-            submission_receiver: None,
-        }
-    }
+/// What was left undelivered when the node shut down
+#[derive(Clone, Debug, Default)]
+pub struct ShutdownSummary {
+    /// Submitted offers that were never published
+    pub unpublished_offers: usize,
+    /// Offers pushed to trusted relays without an answer
+    pub unacknowledged_pushes: usize,
+    /// Offers left in the submission queue file for the next start
+    pub queued_offers: usize,
 }
 
-#[derive(NetworkBehaviour)]
-struct SplashBehaviour {
-    gossipsub: gossipsub::Behaviour,
-    kademlia: kad::Behaviour<kad::store::MemoryStore>,
-    identify: identify::Behaviour,
+// An offer on its way to the event loop, optionally waiting for the publish outcome
+struct Submission {
+    offer: Vec<u8>,
+    metadata: OfferMetadata,
+    expires_at: Option<time::Instant>,
+    receipt: Option<oneshot::Sender<Result<PublishReceipt, SplashError>>>,
 }
 
-impl Splash {
-    pub fn new() -> Splash {
-        let (submission_sender, submission_receiver) = tokio::sync::mpsc::channel::<Vec<u8>>(100);
-
-        Splash {
-            known_peers: Vec::new(),
-            listen_addresses: Vec::new(),
-            keys: identity::Keypair::generate_ed25519(),
-            network_name: "splash".to_string(),
-            submission: submission_sender,
-            submission_receiver: Some(submission_receiver),
-        }
-    }
-
-    pub fn validate_offer(offer: &str) -> Result<(), SplashError> {
-        if offer.len() > MAX_OFFER_SIZE {
-            return Err(SplashError::OfferTooLarge(MAX_OFFER_SIZE));
-        }
-
-        if !offer.starts_with("offer1") || bech32::decode(offer).is_err() {
-            return Err(SplashError::InvalidOfferFormat);
-        }
-
-        // TODO: more validations?
-
-        Ok(())
-    }
-
-    pub async fn broadcast_offer(&self, offer: &str) -> Result<(), SplashError> {
-        Splash::validate_offer(offer)?;
-
-        self.submission
-            .send(offer.as_bytes().to_vec())
-            .await
-            .map_err(|_| SplashError::SendError)?;
-
-        Ok(())
-    }
-
-    pub fn with_listen_addresses(mut self, listen_addresses: Vec<Multiaddr>) -> Self {
-        self.listen_addresses = listen_addresses;
-        self
-    }
-
-    pub fn with_known_peers(mut self, known_peers: Vec<Multiaddr>) -> Self {
-        self.known_peers = known_peers;
-        self
-    }
-
-    pub fn with_keys(mut self, keys: identity::Keypair) -> Self {
-        self.keys = keys;
-        self
-    }
-
-    pub fn with_testnet(mut self) -> Self {
-        self.network_name = "splash-testnet".to_string();
-        self
-    }
-
-    pub async fn build(mut self) -> Result<SplashContext, Box<dyn std::error::Error>> {
-        let (event_tx, event_rx) = mpsc::channel(100);
-
-        // Check if known_peers is empty and resolve from DNS if necessary
-        if self.known_peers.is_empty() {
-            self.known_peers = dns::resolve_peers_from_dns(self.network_name.clone())
-                .await
-                .map_err(|e| format!("Failed to resolve peers from DNS: {}", e))?;
-        }
-
-        let mut swarm = libp2p::SwarmBuilder::with_existing_identity(self.keys.clone())
-            .with_tokio()
-            .with_tcp(
-                tcp::Config::default(),
-                noise::Config::new,
-                yamux::Config::default,
-            )?
-            .with_behaviour(|key| {
-                // We can take the hash of message and use it as an ID.
-                let unique_offer_fn = |message: &gossipsub::Message| {
-                    let mut s = DefaultHasher::new();
-                    message.data.hash(&mut s);
-                    gossipsub::MessageId::from(s.finish().to_string())
-                };
-
-                // Set a custom gossipsub configuration
-                let gossipsub_config = gossipsub::ConfigBuilder::default()
-                    .heartbeat_interval(Duration::from_secs(5)) // This is set to aid debugging by not cluttering the log space
-                    .message_id_fn(unique_offer_fn) // No duplicate offers will be propagated.
-                    .max_transmit_size(MAX_OFFER_SIZE)
-                    .validate_messages()
-                    .validation_mode(gossipsub::ValidationMode::Permissive)
-                    .build()
-                    .map_err(|msg| io::Error::new(io::ErrorKind::Other, msg))?; // Temporary hack because `build` does not return a proper `std::error::Error`.
-
-                // Generate a dummy keypair for signing gossipsub messages
-                // TODO: use gossipsub::MessageAuthenticity::RandomAuthor and disable signing for even more privacy (once enough nodes are updated)
-                let dummy_key = identity::Keypair::generate_ed25519();
-
-                // build a gossipsub network behaviour
-                let gossipsub = gossipsub::Behaviour::new(
-                    gossipsub::MessageAuthenticity::Signed(dummy_key),
-                    gossipsub_config,
-                )?;
-
-                // Create a Kademlia behaviour.
-                let mut cfg = kad::Config::new(
-                    StreamProtocol::try_from_owned(format!("/{}/kad/1", self.network_name))
-                        .expect("protocol name is valid"),
-                );
-
-                cfg.set_query_timeout(Duration::from_secs(60));
-                let store = kad::store::MemoryStore::new(key.public().to_peer_id());
-
-                let mut kademlia =
-                    kad::Behaviour::with_config(key.public().to_peer_id(), store, cfg);
-
-                for addr in self.known_peers.iter() {
-                    let Some(Protocol::P2p(peer_id)) = addr.iter().last() else {
-                        return Err("Expect peer multiaddr to contain peer ID.".into());
-                    };
-                    kademlia.add_address(&peer_id, addr.clone());
-                }
-
-                kademlia.bootstrap().unwrap();
-
-                let identify = identify::Behaviour::new(
-                    identify::Config::new(
-                        format!("/{}/id/1", self.network_name),
-                        key.public().clone(),
-                    )
-                    .with_agent_version(format!("splash/{}", env!("CARGO_PKG_VERSION"))),
-                );
-
-                Ok(SplashBehaviour {
-                    gossipsub,
-                    kademlia,
-                    identify,
-                })
-            })?
-            .with_swarm_config(|c| c.with_idle_connection_timeout(Duration::from_secs(60)))
-            .build();
-
-        if !self.listen_addresses.is_empty() {
-            for addr in self.listen_addresses.iter() {
-                swarm.listen_on(addr.clone())?;
-            }
-        } else {
-            // Fallback to default addresses if no listen addresses are provided
-            swarm.listen_on("/ip4/0.0.0.0/tcp/0".parse()?)?;
-            swarm.listen_on("/ip6/::/tcp/0".parse()?)?;
-        }
-
-        // Create a Gossipsub topic
-        let topic = gossipsub::IdentTopic::new(format!("/{}/offers/1", self.network_name));
-
-        // subscribes to our topic
-        swarm.behaviour_mut().gossipsub.subscribe(&topic)?;
-
-        let mut peer_discovery_interval = time::interval(time::Duration::from_secs(10));
-
-        // Take submission_receiver early to avoid partial move error
-        let mut submission_receiver = self
-            .submission_receiver
-            .take()
-            .ok_or("Submission receiver already consumed")?;
-
-        event_tx
-            .send(SplashEvent::Initialized(self.keys.public().to_peer_id()))
-            .await
-            .ok();
-
-        // Main event loop
-        tokio::spawn(async move {
-            loop {
-                select! {
-                    Some(offer) = submission_receiver.recv() => {
-
-                        if let Err(e) = swarm.behaviour_mut().gossipsub.publish(topic.clone(), offer.clone()) {
-                            event_tx.send(SplashEvent::OfferBroadcastFailed(e)).await.ok();
-                        }
-
-                        event_tx.send(SplashEvent::OfferBroadcasted(String::from_utf8_lossy(&offer).to_string())).await.ok();
-                    },
-                    _ = peer_discovery_interval.tick() => {
-                        swarm.behaviour_mut().kademlia.get_closest_peers(PeerId::random());
-                    },
-                    event = swarm.select_next_some() => match event {
-                        SwarmEvent::ConnectionEstablished { peer_id, .. } => {
-                            event_tx.send(SplashEvent::PeerConnected(peer_id)).await.ok();
-                        },
-                        SwarmEvent::ConnectionClosed { peer_id, .. } => {
-                            event_tx.send(SplashEvent::PeerDisconnected(peer_id)).await.ok();
-                        },
-                        SwarmEvent::Behaviour(SplashBehaviourEvent::Gossipsub(gossipsub::Event::Message {
-                            propagation_source,
-                            message_id,
-                            message,
-                        })) => {
-                            let msg_str = String::from_utf8_lossy(&message.data).into_owned();
-
-                            match Splash::validate_offer(&msg_str) {
-                                Ok(_) => {
-                                    event_tx.send(SplashEvent::OfferReceived(msg_str)).await.ok();
-                                    swarm.behaviour_mut().gossipsub.report_message_validation_result(&message_id, &propagation_source, MessageAcceptance::Accept).ok();
-                                }
-                                Err(e) => {
-                                    warn!("Received invalid offer: {}", e);
-                                    swarm.behaviour_mut().gossipsub.report_message_validation_result(&message_id, &propagation_source, MessageAcceptance::Reject).ok();
-                                }
-                            }
-                        },
-                        SwarmEvent::Behaviour(SplashBehaviourEvent::Identify(identify::Event::Received { info: identify::Info { observed_addr, listen_addrs, .. }, peer_id, connection_id: _ })) => {
-                            for addr in listen_addrs {
-                                // If the node is advertising a non-global address, ignore it
-                                // TODO: also filter out ipv6 private addresses when rust API is finalized
-                                let is_non_global = addr.iter().any(|p| match p {
-                                    Protocol::Ip4(addr) => addr.is_loopback() || addr.is_private(),
-                                    Protocol::Ip6(addr) => addr.is_loopback(),
-                                    _ => false,
-                                });
+// Requests from a `Splash` handle to the running event loop
+enum Command {
+    ExportState(oneshot::Sender<NodeState>),
+    Connections(oneshot::Sender<Vec<ConnectionInfo>>),
+    Propagation(oneshot::Sender<Vec<PropagationRecord>>),
+    Canary(PeerId, oneshot::Sender<Result<Duration, SplashError>>),
+    FetchOffer(String, oneshot::Sender<Result<String, SplashError>>),
+    WaitForMesh(oneshot::Sender<usize>),
+    PublishControl(
+        control::ControlMessage,
+        oneshot::Sender<Result<(), SplashError>>,
+    ),
+    Shutdown(Duration, oneshot::Sender<ShutdownSummary>),
+}
 
-                                if is_non_global {
-                                    continue;
-                                }
+fn offer_hash(data: &[u8]) -> u64 {
+    let mut s = DefaultHasher::new();
+    data.hash(&mut s);
+    s.finish()
+}
 
-                                swarm.behaviour_mut().kademlia.add_address(&peer_id, addr);
-                            }
-                            // Mark the address observed for us by the external peer as confirmed.
-                            // TODO: We shouldn't trust this, instead we should confirm our own address manually or using
-                            // `libp2p-autonat`.
-                            swarm.add_external_address(observed_addr);
-                        },
-                        SwarmEvent::NewListenAddr { address, .. } => {
-                            event_tx.send(SplashEvent::NewListenAddress(address)).await.ok();
-                        },
-                        _ => {}
-                    }
-                }
-            }
-        });
+// Key of a delivered offer in the seen cache, the first 8 bytes of its SHA-256 so snapshots stay valid
+// across builds, unlike `offer_hash`
+fn seen_key(data: &[u8]) -> u64 {
+    let digest = Sha256::digest(data);
+    u64::from_be_bytes(digest[..8].try_into().expect("SHA-256 is 32 bytes"))
+}
 
-        Ok(SplashContext {
-            node: self,
-            events: event_rx,
-        })
-    }
+fn topic_offer_hash(topic: &gossipsub::TopicHash, data: &[u8]) -> u64 {
+    let mut s = DefaultHasher::new();
+    topic.hash(&mut s);
+    data.hash(&mut s);
+    s.finish()
 }
@@ -0,0 +1,133 @@
+use libp2p::core::{transport::PortUse, Endpoint};
+use libp2p::multiaddr::Protocol;
+use libp2p::swarm::{
+    dummy, ConnectionClosed, ConnectionDenied, ConnectionId, FromSwarm, NetworkBehaviour, THandler,
+    THandlerInEvent, THandlerOutEvent, ToSwarm,
+};
+use libp2p::{Multiaddr, PeerId};
+use std::collections::{HashMap, HashSet};
+use std::convert::Infallible;
+use std::net::IpAddr;
+use std::task::{Context, Poll};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+#[error("Too many connections from {0}")]
+struct IpLimitExceeded(IpAddr);
+
+/// libp2p's connection limits count per peer, but one host can bring any number of peer ids
+pub struct IpLimits {
+    max_per_ip: Option<usize>,
+    established: HashMap<IpAddr, HashSet<ConnectionId>>,
+}
+
+impl IpLimits {
+    pub(crate) fn new(max_per_ip: Option<usize>) -> Self {
+        IpLimits {
+            max_per_ip,
+            established: HashMap::new(),
+        }
+    }
+
+    // Only inbound connections are denied, the ones we dial are our own choice
+    fn check(&self, remote: &Multiaddr) -> Result<(), ConnectionDenied> {
+        let (Some(max), Some(ip)) = (self.max_per_ip, ip(remote)) else {
+            return Ok(());
+        };
+
+        if self.established.get(&ip).map_or(0, HashSet::len) >= max {
+            return Err(ConnectionDenied::new(IpLimitExceeded(ip)));
+        }
+
+        Ok(())
+    }
+
+    fn add(&mut self, connection_id: ConnectionId, remote: &Multiaddr) {
+        if let Some(ip) = ip(remote) {
+            self.established
+                .entry(ip)
+                .or_default()
+                .insert(connection_id);
+        }
+    }
+}
+
+fn ip(addr: &Multiaddr) -> Option<IpAddr> {
+    match addr.iter().next() {
+        Some(Protocol::Ip4(ip)) => Some(ip.into()),
+        Some(Protocol::Ip6(ip)) => Some(ip.into()),
+        _ => None,
+    }
+}
+
+impl NetworkBehaviour for IpLimits {
+    type ConnectionHandler = dummy::ConnectionHandler;
+    type ToSwarm = Infallible;
+
+    fn handle_pending_inbound_connection(
+        &mut self,
+        _connection_id: ConnectionId,
+        _local_addr: &Multiaddr,
+        remote_addr: &Multiaddr,
+    ) -> Result<(), ConnectionDenied> {
+        self.check(remote_addr)
+    }
+
+    fn handle_established_inbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        _peer: PeerId,
+        _local_addr: &Multiaddr,
+        remote_addr: &Multiaddr,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        self.check(remote_addr)?;
+        self.add(connection_id, remote_addr);
+        Ok(dummy::ConnectionHandler)
+    }
+
+    fn handle_established_outbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        _peer: PeerId,
+        addr: &Multiaddr,
+        _role_override: Endpoint,
+        _port_use: PortUse,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        self.add(connection_id, addr);
+        Ok(dummy::ConnectionHandler)
+    }
+
+    fn on_swarm_event(&mut self, event: FromSwarm) {
+        if let FromSwarm::ConnectionClosed(ConnectionClosed {
+            connection_id,
+            endpoint,
+            ..
+        }) = event
+        {
+            if let Some(ip) = ip(endpoint.get_remote_address()) {
+                if let Some(connections) = self.established.get_mut(&ip) {
+                    connections.remove(&connection_id);
+                    if connections.is_empty() {
+                        self.established.remove(&ip);
+                    }
+                }
+            }
+        }
+    }
+
+    fn on_connection_handler_event(
+        &mut self,
+        _peer_id: PeerId,
+        _connection_id: ConnectionId,
+        event: THandlerOutEvent<Self>,
+    ) {
+        match event {}
+    }
+
+    fn poll(
+        &mut self,
+        _cx: &mut Context<'_>,
+    ) -> Poll<ToSwarm<Self::ToSwarm, THandlerInEvent<Self>>> {
+        Poll::Pending
+    }
+}
@@ -0,0 +1,83 @@
+use libp2p::core::transport::ListenerId;
+use libp2p::Multiaddr;
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::time::Instant;
+
+const MIN_RELISTEN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_RELISTEN_BACKOFF: Duration = Duration::from_secs(5 * 60);
+pub(crate) const RELISTEN_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Clone)]
+pub(crate) struct Listener {
+    pub(crate) address: Multiaddr,
+    pub(crate) internal: bool,
+    backoff: Duration,
+}
+
+// Remembers the address each listener was started with, so listeners that close (interface down,
+// address conflict after a sleep) can be restarted with backoff
+#[derive(Default)]
+pub(crate) struct Listeners {
+    active: HashMap<ListenerId, Listener>,
+    retrying: Vec<(Instant, Listener)>,
+}
+
+impl Listeners {
+    pub(crate) fn started(&mut self, listener_id: ListenerId, address: Multiaddr, internal: bool) {
+        self.active.insert(
+            listener_id,
+            Listener {
+                address,
+                internal,
+                backoff: MIN_RELISTEN_BACKOFF,
+            },
+        );
+    }
+
+    // Schedules a restart of a closed listener, returns its address and the delay
+    pub(crate) fn closed(&mut self, listener_id: ListenerId) -> Option<(Multiaddr, Duration)> {
+        let listener = self.active.remove(&listener_id)?;
+        let retry_in = listener.backoff;
+        let address = listener.address.clone();
+        self.retrying.push((Instant::now() + retry_in, listener));
+
+        Some((address, retry_in))
+    }
+
+    pub(crate) fn is_retrying(&self) -> bool {
+        !self.retrying.is_empty()
+    }
+
+    // Network interfaces changed, a listener that couldn't bind before may succeed now
+    pub(crate) fn retry_now(&mut self) {
+        let now = Instant::now();
+        for (at, listener) in self.retrying.iter_mut() {
+            *at = now;
+            listener.backoff = MIN_RELISTEN_BACKOFF;
+        }
+    }
+
+    // Listeners whose backoff elapsed, to be restarted now
+    pub(crate) fn due(&mut self) -> Vec<Listener> {
+        let now = Instant::now();
+        let (due, waiting): (Vec<_>, Vec<_>) =
+            self.retrying.drain(..).partition(|(at, _)| *at <= now);
+        self.retrying = waiting;
+
+        due.into_iter().map(|(_, listener)| listener).collect()
+    }
+
+    pub(crate) fn restarted(&mut self, listener_id: ListenerId, listener: Listener) {
+        self.started(listener_id, listener.address, listener.internal);
+    }
+
+    // Restarting failed too, try again after twice the delay
+    pub(crate) fn failed(&mut self, mut listener: Listener) -> Duration {
+        listener.backoff = (listener.backoff * 2).min(MAX_RELISTEN_BACKOFF);
+        let retry_in = listener.backoff;
+        self.retrying.push((Instant::now() + retry_in, listener));
+
+        retry_in
+    }
+}
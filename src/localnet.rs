@@ -0,0 +1,140 @@
+use libp2p::identity;
+use libp2p::multiaddr::Protocol;
+use libp2p::Multiaddr;
+use serde_json::json;
+use splash::{Splash, SplashContext, SplashEvent};
+use std::net::{Ipv4Addr, SocketAddr};
+use warp::http::StatusCode;
+use warp::Filter;
+
+struct LocalNode {
+    node: Splash,
+    address: Multiaddr,
+}
+
+// Start `count` interconnected nodes on localhost, each node bootstraps from its successor
+pub async fn run(
+    count: usize,
+    base_port: u16,
+    control_address: Option<SocketAddr>,
+    configure: impl Fn(Splash) -> Result<Splash, Box<dyn std::error::Error>>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if count < 2 {
+        return Err("A localnet needs at least 2 nodes".into());
+    }
+
+    let keys: Vec<identity::Keypair> = (0..count)
+        .map(|_| identity::Keypair::generate_ed25519())
+        .collect();
+
+    let addresses: Vec<Multiaddr> = (0..count)
+        .map(|i| {
+            Multiaddr::empty()
+                .with(Protocol::Ip4(Ipv4Addr::LOCALHOST))
+                .with(Protocol::Tcp(base_port + i as u16))
+        })
+        .collect();
+
+    let mut nodes = Vec::with_capacity(count);
+
+    for i in 0..count {
+        let next = (i + 1) % count;
+        let known_peer = addresses[next]
+            .clone()
+            .with(Protocol::P2p(keys[next].public().to_peer_id()));
+
+        let splash = configure(Splash::new())?
+            .with_keys(keys[i].clone())
+            .with_listen_addresses(vec![addresses[i].clone()])
+            .with_known_peers(vec![known_peer])
+            .with_private_addresses()
+            .with_upnp(false);
+
+        let SplashContext { node, mut events } = splash.build().await?;
+
+        tokio::spawn(async move {
+            while let Some(event) = events.recv().await {
+                match event {
+                    SplashEvent::PeerConnected(peer_id) => {
+                        println!("[node {}] Connected to peer: {}", i, peer_id)
+                    }
+                    SplashEvent::PeerDisconnected(peer_id) => {
+                        println!("[node {}] Disconnected from peer: {}", i, peer_id)
+                    }
+                    SplashEvent::OfferReceived(offer, _) => {
+                        println!("[node {}] Received Offer: {}", i, offer)
+                    }
+                    SplashEvent::OfferBroadcasted(offer) => {
+                        println!("[node {}] Broadcasted Offer: {}", i, offer)
+                    }
+                    SplashEvent::OfferBroadcastFailed(err) => {
+                        println!("[node {}] Broadcasting Offer failed: {}", i, err)
+                    }
+                    _ => {}
+                }
+            }
+        });
+
+        let address = addresses[i]
+            .clone()
+            .with(Protocol::P2p(keys[i].public().to_peer_id()));
+
+        println!("[node {}] {}", i, address);
+
+        nodes.push(LocalNode { node, address });
+    }
+
+    match control_address {
+        Some(control_address) => serve_control_api(nodes, control_address).await,
+        None => std::future::pending().await,
+    }
+
+    Ok(())
+}
+
+// GET /nodes lists all nodes, POST /nodes/<index>/offers broadcasts {"offer":"offer1..."} from a node
+async fn serve_control_api(nodes: Vec<LocalNode>, address: SocketAddr) {
+    let node_list: Vec<_> = nodes
+        .iter()
+        .enumerate()
+        .map(|(index, local)| json!({"index": index, "address": local.address.to_string()}))
+        .collect();
+
+    let list_route = warp::get()
+        .and(warp::path!("nodes"))
+        .map(move || warp::reply::json(&node_list));
+
+    let nodes: Vec<Splash> = nodes.into_iter().map(|local| local.node).collect();
+
+    let offer_route = warp::post()
+        .and(warp::path!("nodes" / usize / "offers"))
+        .and(warp::body::json())
+        .and_then(move |index: usize, offer: serde_json::Value| {
+            let node = nodes.get(index).cloned();
+            async move {
+                let Some(node) = node else {
+                    return Ok::<_, warp::Rejection>(warp::reply::with_status(
+                        warp::reply::json(&json!({"success": false, "error": "Unknown node"})),
+                        StatusCode::NOT_FOUND,
+                    ));
+                };
+
+                let response = match offer.get("offer").and_then(|v| v.as_str()) {
+                    Some(offer_str) => match node.broadcast_offer(offer_str).await {
+                        Ok(_) => json!({"success": true}),
+                        Err(e) => json!({"success": false, "error": e.to_string()}),
+                    },
+                    None => json!({"success": false, "error": "Invalid offer format"}),
+                };
+
+                Ok(warp::reply::with_status(
+                    warp::reply::json(&response),
+                    StatusCode::OK,
+                ))
+            }
+        });
+
+    println!("Control API listening on: {}", address);
+
+    warp::serve(list_route.or(offer_route)).run(address).await;
+}
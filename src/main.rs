@@ -1,18 +1,54 @@
-use clap::Parser;
+use clap::{Args, CommandFactory, Parser, Subcommand};
 use env_logger;
-use libp2p::identity;
-use libp2p::Multiaddr;
+use libp2p::autonat::NatStatus;
+use libp2p::{gossipsub, identity};
+use libp2p::{Multiaddr, PeerId};
+use serde::Deserialize;
 use serde_json::json;
-use splash::{Splash, SplashContext, SplashEvent};
+#[cfg(feature = "testing")]
+use splash::ChaosConfig;
+use splash::{
+    offer_from_bytes, AddressFamily, AlertThresholds, Cidr, DedupConfig, OfferCompression,
+    OfferStatus, PeerScoring, PublishReceipt, SecurityUpgrades, Splash, SplashContext, SplashError,
+    SplashEvent,
+};
+use std::collections::HashMap;
 use std::net::SocketAddr;
+use std::num::NonZeroU8;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, UNIX_EPOCH};
 use warp::http::StatusCode;
+use warp::hyper::body::Bytes;
 use warp::Filter;
+mod commands;
+mod fifo;
+mod localnet;
 mod metrics;
 mod utils;
 
+// Serialized SpendBundles are larger than their compressed offer1 encoding
+const MAX_OFFER_FILE_SIZE: u64 = 4 * 1024 * 1024;
+
 #[derive(Parser, Debug)]
-#[clap(name = "Splash!", version = env!("CARGO_PKG_VERSION"))]
+#[clap(
+    name = "Splash!",
+    version = env!("CARGO_PKG_VERSION"),
+    args_conflicts_with_subcommands = true
+)]
 struct Opt {
+    #[clap(flatten)]
+    node: NodeOpt,
+
+    #[clap(flatten)]
+    run: RunOpt,
+
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+// Options of the node, shared by run and the subcommands that join the network
+#[derive(Args, Debug)]
+struct NodeOpt {
     #[clap(
         long,
         short,
@@ -21,6 +57,33 @@ struct Opt {
     )]
     known_peer: Vec<Multiaddr>,
 
+    #[clap(
+        long,
+        value_name = "MULTIADDR",
+        help = "Bootstrap from this peer if the DNS introducer can't be resolved, use multiple times for multiple peers"
+    )]
+    fallback_peer: Vec<Multiaddr>,
+
+    #[clap(
+        long,
+        help = "Start even if no bootstrap peer can be reached, keep bootstrapping in the background"
+    )]
+    lazy_bootstrap: bool,
+
+    #[clap(
+        long,
+        value_name = "NAME[:PORT]",
+        value_parser = parse_cluster_service,
+        help = "Peer with the other replicas of a cluster, found via DNS like a Kubernetes headless Service. With a port the A/AAAA records of the name are dialed, without one its SRV records are used"
+    )]
+    cluster_dns: Option<(String, Option<u16>)>,
+
+    #[clap(
+        long,
+        help = "Tune the node for bootstrap duty and print the _dnsaddr TXT records to publish for it"
+    )]
+    introducer: bool,
+
     #[clap(
         long,
         short,
@@ -29,6 +92,385 @@ struct Opt {
     )]
     listen_address: Vec<Multiaddr>,
 
+    #[clap(
+        long,
+        value_name = "MULTIADDR",
+        help = "Set a listen address for local tooling that is never advertised to the network, use multiple times for multiple addresses"
+    )]
+    internal_listen_address: Vec<Multiaddr>,
+
+    #[clap(
+        long,
+        value_name = "MULTIADDR",
+        help = "Advertise this address instead of addresses observed by peers, use multiple times for multiple addresses"
+    )]
+    external_address: Vec<Multiaddr>,
+
+    #[clap(
+        long,
+        value_name = "LISTEN=EXTERNAL",
+        value_parser = parse_advertise_mapping,
+        help = "Advertise a listen address as the address it is forwarded from, e.g. /ip4/0.0.0.0/tcp/4001=/ip4/203.0.113.5/tcp/14001, use multiple times for multiple addresses"
+    )]
+    advertise_map: Vec<(Multiaddr, Multiaddr)>,
+
+    #[clap(
+        long,
+        value_name = "PEM_FILE",
+        requires = "websocket_tls_key",
+        help = "TLS certificate chain for /wss listen addresses"
+    )]
+    websocket_tls_cert: Option<String>,
+
+    #[clap(
+        long,
+        value_name = "PEM_FILE",
+        requires = "websocket_tls_cert",
+        help = "TLS private key for /wss listen addresses"
+    )]
+    websocket_tls_key: Option<String>,
+
+    #[clap(
+        long,
+        value_name = "MULTIADDR",
+        help = "Be reachable through this circuit relay when behind NAT, with direct connection upgrade via hole punching, use multiple times for multiple relays"
+    )]
+    circuit_relay: Vec<Multiaddr>,
+
+    #[clap(
+        long,
+        visible_alias = "relay-server",
+        help = "Relay connections for peers behind NAT, only useful on publicly reachable nodes"
+    )]
+    circuit_relay_server: bool,
+
+    #[clap(
+        long,
+        help = "Answer canaries of nodes running selftest --direct, each peer at most once every 10 seconds"
+    )]
+    canary_responder: bool,
+
+    #[clap(
+        long,
+        value_name = "MINUTES",
+        help = "Keep the offers of the last MINUTES and answer the backfill requests of starting nodes with them"
+    )]
+    sync_window: Option<u64>,
+
+    #[clap(
+        long,
+        value_name = "MINUTES",
+        help = "On startup, ask up to 3 peers running --sync-window for the offers of the last MINUTES"
+    )]
+    backfill: Option<u64>,
+
+    #[clap(
+        long,
+        value_name = "RESERVATIONS",
+        help = "Number of peers the relay server holds a slot for [default: 128]"
+    )]
+    relay_max_reservations: Option<usize>,
+
+    #[clap(
+        long,
+        value_name = "CIRCUITS",
+        help = "Number of connections the relay server relays at a time [default: 16]"
+    )]
+    relay_max_circuits: Option<usize>,
+
+    #[clap(
+        long,
+        value_name = "SECONDS",
+        help = "Close relayed connections after this time [default: 120]"
+    )]
+    relay_max_circuit_duration: Option<u64>,
+
+    #[clap(
+        long,
+        value_name = "BYTES",
+        help = "Close relayed connections after transferring this many bytes in each direction [default: 131072]"
+    )]
+    relay_max_circuit_bytes: Option<u64>,
+
+    #[clap(
+        long,
+        value_name = "HOST:PORT",
+        help = "Dial all peers and the DNS introducer lookup through this SOCKS5 proxy"
+    )]
+    proxy: Option<SocketAddr>,
+
+    #[clap(
+        long,
+        value_name = "HOST:PORT",
+        conflicts_with = "proxy",
+        help = "Privacy mode: dial through this Tor SOCKS port and never reveal our IP address"
+    )]
+    tor_socks: Option<SocketAddr>,
+
+    #[clap(
+        long,
+        value_name = "MULTIADDR",
+        requires = "tor_socks",
+        help = "Onion service forwarding to our listen address, advertised instead of it, e.g. /onion3/<address>:11511"
+    )]
+    onion_address: Option<Multiaddr>,
+
+    #[clap(
+        long,
+        help = "Don't ask the router to forward the listen port via UPnP"
+    )]
+    no_upnp: bool,
+
+    #[clap(
+        long,
+        help = "Don't score gossipsub peers, peers flooding invalid offers are then never ignored"
+    )]
+    no_peer_scoring: bool,
+
+    #[clap(
+        long,
+        value_name = "SCORE",
+        allow_negative_numbers = true,
+        conflicts_with = "no_peer_scoring",
+        help = "Ignore all messages of peers scoring below this, must be negative [default: -80]"
+    )]
+    graylist_threshold: Option<f64>,
+
+    #[clap(
+        long,
+        conflicts_with = "ipv6_only",
+        help = "Only listen on and dial IPv4 addresses"
+    )]
+    ipv4_only: bool,
+
+    #[clap(long, help = "Only listen on and dial IPv6 addresses")]
+    ipv6_only: bool,
+
+    #[clap(
+        long,
+        value_name = "noise|tls|both",
+        default_value = "both",
+        help = "Security protocols offered on connections, Noise is preferred when both are offered"
+    )]
+    security: SecurityUpgrades,
+
+    #[clap(
+        long,
+        value_name = "off|both|only",
+        default_value = "off",
+        help = "Gossip offers zstd compressed on a separate topic, both keeps the plain topic for nodes without compression"
+    )]
+    offer_compression: OfferCompression,
+
+    #[clap(
+        long,
+        value_name = "VERSION",
+        value_parser = clap::value_parser!(u32).range(1..=2),
+        help = "Publish offers on this version of the offers topic, use multiple times to publish on several during a migration. Defaults to version 1, offers are received on all versions"
+    )]
+    publish_topic_version: Vec<u32>,
+
+    #[clap(
+        long,
+        value_name = "TOPIC",
+        conflicts_with = "publish_topic_version",
+        help = "Gossip offers on this topic instead of the offers topic, use multiple times for multiple topics. Only nodes on the same topics exchange offers"
+    )]
+    topic: Vec<String>,
+
+    #[clap(
+        long,
+        value_name = "SECONDS",
+        default_value_t = 20,
+        help = "Drop connections that don't complete the security and muxer handshake in time"
+    )]
+    handshake_timeout: u64,
+
+    #[clap(
+        long,
+        value_name = "SECONDS",
+        default_value_t = 10,
+        help = "Give up on connecting to a peer address after this time"
+    )]
+    dial_timeout: u64,
+
+    #[clap(
+        long,
+        value_name = "COUNT",
+        default_value = "8",
+        help = "Addresses of a single peer that are dialed at the same time"
+    )]
+    dial_concurrency: NonZeroU8,
+
+    #[clap(
+        long,
+        value_name = "COUNT",
+        help = "Maximum dials in progress at the same time"
+    )]
+    max_pending_dials: Option<u32>,
+
+    #[clap(
+        long,
+        value_name = "KIB_PER_SEC",
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "Limit the upload rate of each connection"
+    )]
+    max_upload_rate: Option<u64>,
+
+    #[clap(
+        long,
+        value_name = "OFFERS_PER_SEC",
+        help = "Publish at most this many submitted offers per second, excess submissions wait and are refused once 100 are waiting"
+    )]
+    publish_rate: Option<f64>,
+
+    #[clap(
+        long,
+        value_name = "COUNT",
+        default_value_t = 10,
+        requires = "publish_rate",
+        help = "Offers that may be published at once before the publish rate applies"
+    )]
+    publish_burst: u32,
+
+    #[clap(
+        long,
+        value_name = "OFFERS_PER_SEC",
+        default_value_t = 20.0,
+        help = "Accept at most this many gossiped offers per second from each peer, excess offers are dropped"
+    )]
+    inbound_offer_rate: f64,
+
+    #[clap(
+        long,
+        value_name = "COUNT",
+        default_value_t = 100,
+        help = "Offers a peer may send at once before the inbound offer rate applies"
+    )]
+    inbound_offer_burst: u32,
+
+    #[clap(
+        long,
+        value_name = "COUNT",
+        help = "Track first-seen time, duplicates and delivering peers of the last COUNT received offers, exported on the metrics API"
+    )]
+    propagation_analytics: Option<usize>,
+
+    #[clap(
+        long,
+        value_name = "KIB_PER_SEC",
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "Limit the download rate of each connection"
+    )]
+    max_download_rate: Option<u64>,
+
+    #[clap(
+        long,
+        value_name = "COUNT",
+        default_value_t = 128,
+        help = "Maximum inbound substreams per connection that are still negotiating their protocol"
+    )]
+    max_negotiating_streams: usize,
+
+    #[clap(long, value_name = "COUNT", help = "Maximum inbound connections")]
+    max_inbound_connections: Option<u32>,
+
+    #[clap(long, value_name = "COUNT", help = "Maximum outbound connections")]
+    max_outbound_connections: Option<u32>,
+
+    #[clap(
+        long,
+        value_name = "COUNT",
+        help = "Maximum connections to a single peer"
+    )]
+    max_connections_per_peer: Option<u32>,
+
+    #[clap(
+        long,
+        value_name = "COUNT",
+        help = "Maximum connections with a single IP address, only inbound connections are denied"
+    )]
+    max_connections_per_ip: Option<usize>,
+
+    #[clap(
+        long,
+        value_name = "CIDR",
+        help = "Only connect with hosts in this network, use multiple times for multiple networks"
+    )]
+    allow_cidr: Vec<Cidr>,
+
+    #[clap(
+        long,
+        value_name = "CIDR",
+        help = "Never connect with hosts in this network, use multiple times for multiple networks"
+    )]
+    deny_cidr: Vec<Cidr>,
+
+    #[clap(
+        long,
+        value_name = "PEER_ID",
+        help = "Never connect with this peer, use multiple times for multiple peers"
+    )]
+    deny_peer: Vec<PeerId>,
+
+    #[clap(
+        long,
+        value_name = "FILE",
+        help = "Never connect with the networks and peers listed in this file, one CIDR or peer ID per line"
+    )]
+    deny_file: Option<String>,
+
+    #[clap(
+        long,
+        value_name = "URL",
+        requires = "blocklist_signer",
+        help = "Subscribe to a signed blocklist in the deny file format, refreshed hourly"
+    )]
+    blocklist_url: Option<String>,
+
+    #[clap(
+        long,
+        value_name = "PEER_ID",
+        requires = "blocklist_url",
+        help = "Ed25519 peer ID whose key signed the blocklist, the signature is fetched from <URL>.sig"
+    )]
+    blocklist_signer: Option<PeerId>,
+
+    #[clap(
+        long,
+        value_name = "COUNT",
+        help = "Raise a peer_count_low event when fewer peers are connected"
+    )]
+    alert_min_peers: Option<usize>,
+
+    #[clap(
+        long,
+        value_name = "SECONDS",
+        help = "Raise an offer_flow_stalled event when no offer was received for this long"
+    )]
+    alert_offer_stall: Option<u64>,
+
+    #[clap(
+        long,
+        value_name = "SECONDS",
+        help = "Print a status line with peers, mesh peers, offers per minute, pending submissions and dedup cache size this often"
+    )]
+    status_interval: Option<u64>,
+
+    #[clap(
+        long,
+        value_name = "COUNT",
+        help = "Mesh peers on the offers topic from which the node reports the mesh as ready [default: 1]"
+    )]
+    mesh_ready_peers: Option<usize>,
+
+    #[clap(
+        long,
+        value_name = "SECONDS",
+        help = "Wait up to this long for the mesh to be ready before starting the offer submission API"
+    )]
+    wait_for_mesh: Option<u64>,
+
     #[clap(
         long,
         short,
@@ -36,15 +478,175 @@ struct Opt {
     )]
     identity_file: Option<String>,
 
+    #[clap(
+        long,
+        value_name = "STATE_FILE",
+        help = "Restore identity, address book and dedup state from this file and keep it updated"
+    )]
+    state_file: Option<String>,
+
     #[clap(long, short, help = "Use Testnet")]
     testnet: bool,
 
+    #[clap(
+        long,
+        value_name = "NAMESPACE",
+        help = "Prefix all topic and protocol names, e.g. acme for /acme/splash/offers/1, to run an isolated network"
+    )]
+    topic_namespace: Option<String>,
+
+    #[clap(
+        long,
+        value_name = "TOKEN",
+        help = "Only accept gossip from peers proving possession of this shared token, others are disconnected"
+    )]
+    auth_token: Option<String>,
+
+    #[clap(
+        long,
+        value_name = "MIB",
+        help = "Memory budget for duplicate suppression, larger budgets remember offers longer"
+    )]
+    dedup_memory_budget: Option<usize>,
+
+    #[clap(
+        long,
+        value_name = "FILE",
+        help = "Gossipsub mesh parameters as JSON (mesh_n, mesh_n_low, mesh_n_high, mesh_outbound_min, heartbeat_interval_ms, history_length, history_gossip)"
+    )]
+    mesh_config: Option<String>,
+
+    #[clap(
+        long,
+        value_name = "COUNT",
+        help = "Peer count below which new peers are actively discovered [default: 20]"
+    )]
+    target_peers: Option<usize>,
+
+    #[clap(
+        long,
+        value_name = "COUNT",
+        help = "Dial known peers while fewer gossipsub peers are connected"
+    )]
+    min_peers: Option<usize>,
+
+    #[clap(
+        long,
+        value_name = "COUNT",
+        help = "Disconnect the worst peers while more gossipsub peers are connected"
+    )]
+    max_peers: Option<usize>,
+
+    #[clap(
+        long,
+        value_name = "QUEUE_FILE",
+        help = "Persist accepted but unpublished offers to this file and retry them, also after a restart"
+    )]
+    submission_queue_file: Option<String>,
+
+    #[clap(
+        long,
+        value_name = "SECONDS",
+        help = "Retry offers that couldn't be published for lack of peers for up to this long, in memory unless --submission-queue-file is given"
+    )]
+    publish_retry: Option<u64>,
+
+    #[clap(
+        long,
+        value_name = "MULTIADDR",
+        help = "Additionally push submitted offers directly to this peer, use multiple times for multiple relays"
+    )]
+    trusted_relay: Vec<Multiaddr>,
+
+    #[clap(
+        long,
+        value_name = "NAME",
+        help = "Operator name announced to other peers via identify"
+    )]
+    operator_name: Option<String>,
+
+    #[clap(
+        long,
+        value_name = "URI",
+        requires = "operator_name",
+        help = "Operator contact (e.g. mailto: or https: URI) announced with the operator name"
+    )]
+    operator_contact: Option<String>,
+
+    #[clap(
+        long,
+        value_name = "DSN",
+        help = "Report panics and fatal errors to Sentry, offer contents are never sent"
+    )]
+    sentry_dsn: Option<String>,
+
+    #[cfg(feature = "testing")]
+    #[clap(
+        long,
+        value_name = "SECONDS",
+        help = "Testing: disconnect a random peer at this interval"
+    )]
+    chaos_disconnect_interval: Option<u64>,
+
+    #[cfg(feature = "testing")]
+    #[clap(
+        long,
+        value_name = "MILLISECONDS",
+        help = "Testing: delay every submitted offer before publishing"
+    )]
+    chaos_publish_delay: Option<u64>,
+
+    #[cfg(feature = "testing")]
+    #[clap(
+        long,
+        value_name = "PERCENT",
+        default_value_t = 0,
+        value_parser = clap::value_parser!(u8).range(0..=100),
+        help = "Testing: drop this percentage of incoming offers"
+    )]
+    chaos_drop_incoming: u8,
+}
+
+// Options only a running node uses, for its consumers and operators
+#[derive(Args, Debug)]
+struct RunOpt {
+    #[clap(
+        long,
+        value_name = "SECONDS",
+        default_value_t = 5,
+        help = "On shutdown, time given to queued offers, relay pushes and hook requests to complete"
+    )]
+    shutdown_drain: u64,
+
     #[clap(
         long,
         help = "HTTP endpoint where incoming offers are posted to, sends JSON body {\"offer\":\"offer1...\"}"
     )]
     offer_hook: Option<String>,
 
+    #[clap(
+        long,
+        value_enum,
+        value_name = "VERSION",
+        default_value = "1",
+        help = "Offer hook payload version, 2 adds \"id\" (SHA-256 of the offer) and \"received_at\""
+    )]
+    offer_hook_schema: utils::HookSchema,
+
+    #[clap(
+        long,
+        value_name = "PATH",
+        help = "Write incoming offers to this named pipe (FIFO), one per line. Create it with mkfifo, offers are dropped while no reader keeps up"
+    )]
+    offer_fifo: Option<String>,
+
+    #[clap(
+        long,
+        value_name = "URL",
+        help = "HTTP endpoint for node events (peer_connected, peer_disconnected, nat_status, peer_count_low, peer_count_recovered, offer_flow_stalled, offer_cancelled, offer_status), use \"url=URL,events=peer_connected|...\" to pick events, use multiple times for multiple hooks"
+    )]
+    event_hook: Vec<utils::EventHook>,
+
     #[clap(
         long,
         help = "Start a HTTP API for offer submission, expects JSON body {\"offer\":\"offer1...\"}",
@@ -52,81 +654,686 @@ struct Opt {
     )]
     listen_offer_submission: Option<String>,
 
-    #[clap(long, help = "Start a HTTP API for metrics", value_name = "HOST:PORT")]
-    listen_metrics: Option<String>,
-}
+    #[clap(
+        long,
+        help = "Start a HTTP API for metrics (JSON, Prometheus format on /metrics, connected peers on /peers, connections on /debug/connections, propagation of received offers as CSV on /analytics/propagation.csv)",
+        value_name = "HOST:PORT"
+    )]
+    listen_metrics: Option<String>,
+
+    #[clap(
+        long,
+        value_name = "URL",
+        help = "Periodically push metrics to this Prometheus pushgateway, for nodes that can't be scraped"
+    )]
+    metrics_pushgateway: Option<String>,
+
+    #[clap(
+        long,
+        value_name = "JOB",
+        default_value = "splash",
+        help = "Job label of pushed metrics"
+    )]
+    metrics_push_job: String,
+
+    #[clap(
+        long,
+        value_name = "INSTANCE",
+        help = "Instance label of pushed metrics [default: peer ID]"
+    )]
+    metrics_push_instance: Option<String>,
+
+    #[clap(
+        long,
+        value_name = "SECONDS",
+        default_value_t = 60,
+        help = "Interval between metrics pushes"
+    )]
+    metrics_push_interval: u64,
+}
+
+// Node options are given after the subcommand, like its own options
+#[derive(Subcommand, Debug)]
+#[allow(clippy::large_enum_variant)]
+enum Command {
+    /// Run a node, the default without a subcommand
+    Run {
+        #[clap(flatten)]
+        node: NodeOpt,
+
+        #[clap(flatten)]
+        run: RunOpt,
+    },
+    /// Join the network, broadcast offer files and exit once each reached a mesh peer
+    Broadcast {
+        #[clap(flatten)]
+        node: NodeOpt,
+
+        #[clap(
+            value_name = "FILE",
+            required = true,
+            help = "Offer file, either a bech32 offer or a serialized SpendBundle"
+        )]
+        files: Vec<String>,
+
+        #[clap(
+            long,
+            value_name = "SECONDS",
+            default_value_t = 60,
+            help = "Give up on an offer that didn't reach a mesh peer in time"
+        )]
+        timeout: u64,
+    },
+    /// Generate a new identity for --identity-file and print its peer ID
+    Keygen {
+        #[clap(long, short, help = "File to write the identity to")]
+        identity_file: String,
+
+        #[clap(long, help = "Overwrite an existing identity file")]
+        force: bool,
+    },
+    /// Start a node with the given options and report whether it can join the network
+    Doctor {
+        #[clap(flatten)]
+        node: NodeOpt,
+
+        #[clap(
+            long,
+            value_name = "SECONDS",
+            default_value_t = 30,
+            help = "How long to watch the node before reporting"
+        )]
+        timeout: u64,
+    },
+    /// Broadcast recorded offers again, one offer per line of the file
+    Replay {
+        #[clap(flatten)]
+        node: NodeOpt,
+
+        #[clap(value_name = "FILE", help = "Offers to replay, one per line")]
+        file: String,
+
+        #[clap(
+            long,
+            value_name = "MILLISECONDS",
+            default_value_t = 1000,
+            help = "Pause between two offers"
+        )]
+        interval: u64,
+
+        #[clap(
+            long,
+            value_name = "SECONDS",
+            default_value_t = 60,
+            help = "Give up on an offer that didn't reach a mesh peer in time"
+        )]
+        timeout: u64,
+    },
+    /// Check that this node can send and receive offers through a reference node
+    Selftest {
+        #[clap(flatten)]
+        node: NodeOpt,
+
+        #[clap(
+            long,
+            value_name = "MULTIADDR",
+            help = "Reference node running selftest-echo, or any node with --canary-responder for --direct"
+        )]
+        peer: Multiaddr,
+
+        #[clap(
+            long,
+            help = "Exchange the canary directly with the peer instead of echoing it through gossip"
+        )]
+        direct: bool,
+
+        #[clap(
+            long,
+            value_name = "SECONDS",
+            default_value_t = 30,
+            help = "Give up if the canary isn't echoed in time"
+        )]
+        timeout: u64,
+    },
+    /// Run a reference node that echoes the canaries of selftest
+    SelftestEcho {
+        #[clap(flatten)]
+        node: NodeOpt,
+    },
+    /// Print a shell completion script
+    Completions {
+        #[clap(value_name = "SHELL", help = "Shell to generate the script for")]
+        shell: clap_complete::Shell,
+    },
+    /// Run several interconnected nodes on localhost for development
+    Localnet {
+        #[clap(flatten)]
+        node: NodeOpt,
+
+        #[clap(long, default_value_t = 3, help = "Number of nodes to start")]
+        nodes: usize,
+
+        #[clap(
+            long,
+            default_value_t = 11600,
+            help = "Listen port of the first node, following nodes use the next ports"
+        )]
+        base_port: u16,
+
+        #[clap(
+            long,
+            value_name = "HOST:PORT",
+            help = "Start a HTTP API to list nodes (GET /nodes) and submit offers (POST /nodes/<index>/offers)"
+        )]
+        listen_control: Option<String>,
+    },
+    /// Sign a blocklist with the --identity-file key, prints the signature to serve as <URL>.sig
+    SignBlocklist {
+        #[clap(long, short, help = "Identity whose key signs the blocklist")]
+        identity_file: String,
+
+        #[clap(
+            value_name = "FILE",
+            help = "Blocklist in the deny file format, as it will be served"
+        )]
+        file: String,
+    },
+}
+
+#[derive(Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum WaitMode {
+    // Respond as soon as the offer is queued for broadcasting
+    #[default]
+    None,
+    // Respond after the gossipsub publish call
+    Published,
+    // Respond after the offer was handed to at least one mesh peer
+    Propagated,
+}
+
+#[derive(Deserialize)]
+struct SubmitQuery {
+    #[serde(default)]
+    wait: WaitMode,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    env_logger::init();
+
+    let opt = Opt::parse();
+
+    // Panics are reported by the guard's panic integration, fatal errors when run returns
+    let _sentry = opt.node().and_then(|node| {
+        let guard = sentry::init((
+            node.sentry_dsn.as_deref()?,
+            sentry::ClientOptions {
+                release: sentry::release_name!(),
+                ..Default::default()
+            },
+        ));
+        sentry::configure_scope(|scope| {
+            scope.set_tag("network", if node.testnet { "testnet" } else { "mainnet" });
+        });
+        Some(guard)
+    });
+
+    let result = run(opt).await;
+    if let Err(e) = result.as_ref() {
+        sentry::capture_error(e.as_ref());
+    }
+
+    result
+}
+
+impl Opt {
+    // The node options, given with the subcommand if there is one
+    fn node(&self) -> Option<&NodeOpt> {
+        match &self.command {
+            None => Some(&self.node),
+            Some(
+                Command::Run { node, .. }
+                | Command::Broadcast { node, .. }
+                | Command::Doctor { node, .. }
+                | Command::Replay { node, .. }
+                | Command::Selftest { node, .. }
+                | Command::SelftestEcho { node }
+                | Command::Localnet { node, .. },
+            ) => Some(node),
+            Some(
+                Command::Keygen { .. }
+                | Command::SignBlocklist { .. }
+                | Command::Completions { .. },
+            ) => None,
+        }
+    }
+}
+
+async fn run(opt: Opt) -> Result<(), Box<dyn std::error::Error>> {
+    let (opt, run_opt) = match opt.command {
+        None => (opt.node, opt.run),
+        Some(Command::Run { node, run }) => (node, run),
+        Some(Command::Localnet {
+            node: opt,
+            nodes,
+            base_port,
+            listen_control,
+        }) => {
+            print_welcome(&opt);
+
+            let control_address: Option<SocketAddr> = listen_control
+                .as_ref()
+                .map(|addr| addr.parse())
+                .transpose()?;
+
+            return localnet::run(nodes, base_port, control_address, |splash| {
+                configure_network(splash, &opt)
+            })
+            .await;
+        }
+        Some(Command::SignBlocklist {
+            identity_file,
+            file,
+        }) => {
+            return commands::sign_blocklist(&identity_file, &file);
+        }
+        Some(Command::Keygen {
+            identity_file,
+            force,
+        }) => {
+            return commands::keygen(&identity_file, force);
+        }
+        Some(Command::Broadcast {
+            node: opt,
+            files,
+            timeout,
+        }) => {
+            print_welcome(&opt);
+            return commands::broadcast(
+                configure_node(&opt)?,
+                &files,
+                Duration::from_secs(timeout),
+            )
+            .await;
+        }
+        Some(Command::Replay {
+            node: opt,
+            file,
+            interval,
+            timeout,
+        }) => {
+            print_welcome(&opt);
+            return commands::replay(
+                configure_node(&opt)?,
+                &file,
+                Duration::from_millis(interval),
+                Duration::from_secs(timeout),
+            )
+            .await;
+        }
+        Some(Command::Doctor { node: opt, timeout }) => {
+            print_welcome(&opt);
+            return commands::doctor(configure_node(&opt)?, Duration::from_secs(timeout)).await;
+        }
+        Some(Command::Selftest {
+            node: opt,
+            peer,
+            direct,
+            timeout,
+        }) => {
+            print_welcome(&opt);
+            let timeout = Duration::from_secs(timeout);
+            if direct {
+                return commands::selftest_direct(configure_node(&opt)?, peer, timeout).await;
+            }
+            return commands::selftest(configure_node(&opt)?, peer, timeout).await;
+        }
+        Some(Command::SelftestEcho { node: opt }) => {
+            print_welcome(&opt);
+            return commands::selftest_echo(configure_node(&opt)?).await;
+        }
+        Some(Command::Completions { shell }) => {
+            commands::completions(shell, Opt::command());
+            return Ok(());
+        }
+    };
+
+    // Introducers serve bootstrap traffic, not offer consumers
+    if opt.introducer && run_opt.offer_hook.is_some() {
+        return Err("--introducer can't be used with --offer-hook".into());
+    }
+
+    print_welcome(&opt);
+
+    let splash = configure_node(&opt)?;
+
+    let SplashContext { node, mut events } = splash.build().await?;
+
+    let metrics = metrics::Metrics::new(node.network_name(), &node.offers_topic());
+
+    // Agent versions and capabilities of connected peers, served on /peers of the metrics API
+    let peer_agents: Arc<Mutex<HashMap<PeerId, (String, Vec<String>)>>> =
+        Arc::new(Mutex::new(HashMap::new()));
+
+    // Hook deliveries in flight, waited for on shutdown
+    let hook_tasks = utils::HookTasks::default();
+
+    let offer_fifo = run_opt
+        .offer_fifo
+        .clone()
+        .map(fifo::OfferFifo::spawn)
+        .transpose()?;
+
+    // Keep the snapshot updated, only if --state-file is specified
+    if let Some(state_file) = opt.state_file.clone() {
+        let node = node.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(5 * 60));
+            interval.tick().await;
+
+            loop {
+                interval.tick().await;
+                save_state(&node, &state_file).await;
+            }
+        });
+    }
+
+    // On ctrl-c write a final snapshot, then give in-flight offers and hooks the drain period
+    let shutdown_task = {
+        let node = node.clone();
+        let state_file = opt.state_file.clone();
+        let hook_tasks = hook_tasks.clone();
+        let drain = Duration::from_secs(run_opt.shutdown_drain);
+        tokio::spawn(async move {
+            tokio::signal::ctrl_c().await.ok();
+            println!("Shutting down, draining for up to {}s", drain.as_secs());
+
+            if let Some(state_file) = state_file {
+                save_state(&node, &state_file).await;
+            }
+
+            let deadline = tokio::time::Instant::now() + drain;
+            match node.shutdown(drain).await {
+                Ok(summary) => println!(
+                    "Undelivered at shutdown: {} offers not published, {} relay pushes unanswered, {} offers queued for the next start",
+                    summary.unpublished_offers, summary.unacknowledged_pushes, summary.queued_offers
+                ),
+                Err(e) => eprintln!("Error shutting down: {}", e),
+            }
+
+            let unfinished_hooks = hook_tasks.drain(deadline).await;
+            if unfinished_hooks > 0 {
+                println!(
+                    "Undelivered at shutdown: {} hook requests",
+                    unfinished_hooks
+                );
+            }
+        })
+    };
+    let mut shutting_down = false;
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    env_logger::init();
+    // Start a local webserver for offer submission, only if --listen-offer-submission is specified
+    if let Some(offer_submission_addr_str) = run_opt.listen_offer_submission {
+        // Offer files can be uploaded as multipart (field "file") or as the raw request body
+        let file_node = node.clone();
+        let file_route = warp::post()
+            .and(warp::path!("submit" / "file"))
+            .and(warp::body::content_length_limit(MAX_OFFER_FILE_SIZE))
+            .and(
+                warp::multipart::form()
+                    .max_length(MAX_OFFER_FILE_SIZE)
+                    .and_then(utils::read_multipart_file)
+                    .or(warp::body::bytes().map(|body: Bytes| body.to_vec()))
+                    .unify(),
+            )
+            .and_then(move |file: Vec<u8>| {
+                let node = file_node.clone();
+                async move {
+                    let result = match offer_from_bytes(&file) {
+                        Ok(offer) => node.broadcast_offer(&offer).await.map(|_| offer),
+                        Err(e) => Err(e),
+                    };
 
-    let opt = Opt::parse();
+                    let response = match result {
+                        Ok(offer) => json!({"success": true, "offer": offer}),
+                        Err(e) => json!({"success": false, "error": e.to_string()}),
+                    };
 
-    println!("Welcome to Splash! v{}", env!("CARGO_PKG_VERSION"));
+                    Ok::<_, warp::Rejection>(warp::reply::with_status(
+                        warp::reply::json(&response),
+                        StatusCode::OK,
+                    ))
+                }
+            });
 
-    let mut splash = Splash::new()
-        .with_listen_addresses(opt.listen_address)
-        .with_known_peers(opt.known_peer);
+        // Cancellation notices, {"offer_id":"<sha256 hex of the offer>"}
+        let cancel_node = node.clone();
+        let cancel_route = warp::post()
+            .and(warp::path!("cancel"))
+            .and(warp::body::json())
+            .and_then(move |body: serde_json::Value| {
+                let node = cancel_node.clone();
+                async move {
+                    let response = match body.get("offer_id").and_then(|v| v.as_str()) {
+                        Some(offer_id) => match node.broadcast_cancellation(offer_id).await {
+                            Ok(()) => json!({"success": true}),
+                            Err(e) => json!({"success": false, "error": e.to_string()}),
+                        },
+                        None => json!({"success": false, "error": "Missing offer_id"}),
+                    };
 
-    // Load or generate peer identity (keypair), only if --identity-file is specified
-    if let Some(keypair) = opt.identity_file.as_ref().map(|file_path| {
-        utils::load_keypair_from_file(file_path).unwrap_or_else(|_| {
-            let keypair = identity::Keypair::generate_ed25519();
-            utils::save_keypair_to_file(&keypair, file_path).ok();
-            keypair
-        })
-    }) {
-        splash = splash.with_keys(keypair);
-    }
+                    Ok::<_, warp::Rejection>(warp::reply::with_status(
+                        warp::reply::json(&response),
+                        StatusCode::OK,
+                    ))
+                }
+            });
 
-    if opt.testnet {
-        println!("Using Testnet");
-        splash = splash.with_testnet();
-    }
+        // Status notices, {"offer_id":"<sha256 hex of the offer>","status":"taken"|"expired"}
+        let status_node = node.clone();
+        let status_route = warp::post()
+            .and(warp::path!("status"))
+            .and(warp::body::json())
+            .and_then(move |body: serde_json::Value| {
+                let node = status_node.clone();
+                async move {
+                    let offer_id = body.get("offer_id").and_then(|v| v.as_str());
+                    let status = body.get("status").and_then(|v| v.as_str());
+                    let response = match (offer_id, status) {
+                        (Some(offer_id), Some(status)) => match status.parse::<OfferStatus>() {
+                            Ok(status) => match node.submit_offer_status(offer_id, status).await {
+                                Ok(()) => json!({"success": true}),
+                                Err(e) => json!({"success": false, "error": e.to_string()}),
+                            },
+                            Err(e) => json!({"success": false, "error": e}),
+                        },
+                        (None, _) => json!({"success": false, "error": "Missing offer_id"}),
+                        (_, None) => json!({"success": false, "error": "Missing status"}),
+                    };
 
-    let SplashContext { node, mut events } = splash.build().await?;
+                    Ok::<_, warp::Rejection>(warp::reply::with_status(
+                        warp::reply::json(&response),
+                        StatusCode::OK,
+                    ))
+                }
+            });
 
-    let metrics = metrics::Metrics::new();
+        let offer_route = warp::post()
+            .and(warp::query::<SubmitQuery>())
+            .and(warp::body::json())
+            .and_then(move |query: SubmitQuery, offer: serde_json::Value| {
+                let node = node.clone();
+                async move {
+                    let ttl = offer
+                        .get("ttl_secs")
+                        .and_then(|v| v.as_u64())
+                        .map(Duration::from_secs);
+                    let response =
+                        if let Some(offer_str) = offer.get("offer").and_then(|v| v.as_str()) {
+                            let result = submit_offer(&node, offer_str, query.wait, ttl).await;
 
-    // Start a local webserver for offer submission, only if --listen-offer-submission is specified
-    if let Some(offer_submission_addr_str) = opt.listen_offer_submission {
-        let offer_route =
-            warp::post()
-                .and(warp::body::json())
-                .and_then(move |offer: serde_json::Value| {
-                    let node = node.clone();
-                    async move {
-                        let response =
-                            if let Some(offer_str) = offer.get("offer").and_then(|v| v.as_str()) {
-                                match node.broadcast_offer(offer_str).await {
-                                    Ok(_) => warp::reply::json(&json!({"success": true})),
-                                    Err(e) => warp::reply::json(&json!({
-                                        "success": false,
-                                        "error": e.to_string(),
-                                    })),
-                                }
-                            } else {
-                                warp::reply::json(&json!({
+                            match result {
+                                Ok(None) => json!({"success": true}),
+                                Ok(Some(receipt)) => json!({
+                                    "success": true,
+                                    "message_id": receipt.message_id.to_string(),
+                                    "mesh_peers": receipt.mesh_peers,
+                                }),
+                                Err(e) => json!({
                                     "success": false,
-                                    "error": "Invalid offer format",
-                                }))
-                            };
+                                    "error": e.to_string(),
+                                }),
+                            }
+                        } else {
+                            json!({
+                                "success": false,
+                                "error": "Invalid offer format",
+                            })
+                        };
 
-                        Ok::<_, warp::Rejection>(warp::reply::with_status(response, StatusCode::OK))
-                    }
-                });
+                    Ok::<_, warp::Rejection>(warp::reply::with_status(
+                        warp::reply::json(&response),
+                        StatusCode::OK,
+                    ))
+                }
+            });
 
         let submission_addr: SocketAddr = offer_submission_addr_str.parse()?;
 
         tokio::spawn(async move {
-            warp::serve(offer_route).run(submission_addr).await;
+            warp::serve(
+                file_route
+                    .or(cancel_route)
+                    .or(status_route)
+                    .or(offer_route)
+                    .with(utils::access_log("submission")),
+            )
+            .run(submission_addr)
+            .await;
+        });
+    }
+
+    // Push metrics to a pushgateway, only if --metrics-pushgateway is specified
+    if let Some(gateway) = run_opt.metrics_pushgateway.clone() {
+        let metrics = metrics.clone();
+        let job = run_opt.metrics_push_job.clone();
+        let instance = run_opt
+            .metrics_push_instance
+            .clone()
+            .unwrap_or_else(|| node.keys.public().to_peer_id().to_string());
+        let mut interval =
+            tokio::time::interval(Duration::from_secs(run_opt.metrics_push_interval.max(1)));
+        tokio::spawn(async move {
+            loop {
+                interval.tick().await;
+                if let Err(e) =
+                    utils::push_metrics(&gateway, &job, &instance, metrics.to_prometheus()).await
+                {
+                    eprintln!("Error pushing metrics: {}", e);
+                }
+            }
         });
     }
 
     // Start a local webserver for splash metrics, only if --listen-metrics is specified
-    if let Some(listen_metrics_str) = opt.listen_metrics {
+    if let Some(listen_metrics_str) = run_opt.listen_metrics {
         let metrics_address: SocketAddr = listen_metrics_str.parse()?;
 
+        let prometheus_metrics = metrics.clone();
+        let prometheus_route = warp::get()
+            .and(warp::path("metrics"))
+            .and(warp::path::end())
+            .map(move || {
+                warp::reply::with_header(
+                    prometheus_metrics.to_prometheus(),
+                    "Content-Type",
+                    "text/plain; version=0.0.4",
+                )
+            });
+
+        let peers_book = peer_agents.clone();
+        let peers_route = warp::get()
+            .and(warp::path("peers"))
+            .and(warp::path::end())
+            .map(move || {
+                let peers: Vec<_> = peers_book
+                    .lock()
+                    .expect("peer book lock poisoned")
+                    .iter()
+                    .map(|(peer_id, (agent_version, capabilities))| {
+                        json!({
+                            "peer_id": peer_id.to_string(),
+                            "agent_version": agent_version,
+                            "capabilities": capabilities,
+                        })
+                    })
+                    .collect();
+                warp::reply::json(&peers)
+            });
+
+        // Negotiated protocols per connection, to troubleshoot interop with other implementations
+        let connections_node = node.clone();
+        let connections_route = warp::get()
+            .and(warp::path!("debug" / "connections"))
+            .and_then(move || {
+                let node = connections_node.clone();
+                async move {
+                    let connections: Vec<_> = node
+                        .connections()
+                        .await
+                        .unwrap_or_default()
+                        .into_iter()
+                        .map(|connection| {
+                            json!({
+                                "peer_id": connection.peer_id.to_string(),
+                                "remote_address": connection.remote_address.to_string(),
+                                "direction": if connection.outbound { "outbound" } else { "inbound" },
+                                "established_at": connection
+                                    .established_at
+                                    .duration_since(UNIX_EPOCH)
+                                    .map_or(0, |since| since.as_secs()),
+                                "security": connection.security,
+                                "muxer": connection.muxer,
+                                "agent_version": connection.agent_version,
+                                "protocols": connection.protocols,
+                            })
+                        })
+                        .collect();
+                    Ok::<_, warp::Rejection>(warp::reply::json(&connections))
+                }
+            });
+
+        // One row per received offer, for studying propagation across nodes
+        let propagation_node = node.clone();
+        let propagation_route = warp::get()
+            .and(warp::path!("analytics" / "propagation.csv"))
+            .and_then(move || {
+                let node = propagation_node.clone();
+                async move {
+                    let mut csv =
+                        String::from("offer_sha256,first_seen_ms,first_peer,duplicates,peers\n");
+                    for record in node.propagation().await.unwrap_or_default() {
+                        csv.push_str(&format!(
+                            "{},{},{},{},{}\n",
+                            record.offer_sha256,
+                            record
+                                .first_seen
+                                .duration_since(UNIX_EPOCH)
+                                .map_or(0, |since| since.as_millis()),
+                            record.first_peer,
+                            record.duplicates,
+                            record.peers
+                        ));
+                    }
+                    Ok::<_, warp::Rejection>(warp::reply::with_header(
+                        csv,
+                        "Content-Type",
+                        "text/csv",
+                    ))
+                }
+            });
+
         let metrics = metrics.clone();
         let metrics_route = warp::get().map(move || {
             let metrics_data = metrics.get_metrics();
@@ -134,7 +1341,16 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         });
 
         tokio::spawn(async move {
-            warp::serve(metrics_route).run(metrics_address).await;
+            warp::serve(
+                prometheus_route
+                    .or(peers_route)
+                    .or(connections_route)
+                    .or(propagation_route)
+                    .or(metrics_route)
+                    .with(utils::access_log("metrics")),
+            )
+            .run(metrics_address)
+            .await;
         });
     }
 
@@ -142,17 +1358,84 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     while let Some(event) = events.recv().await {
         match event {
             SplashEvent::Initialized(peer_id) => println!("Our Peer ID: {}", peer_id),
+            SplashEvent::BootstrapDegraded {
+                reason,
+                fallback_peers,
+                restored_peers,
+            } => {
+                println!(
+                    "Failed to resolve peers from DNS ({}), bootstrapping from {} fallback and {} restored peers",
+                    reason, fallback_peers, restored_peers
+                )
+            }
 
             SplashEvent::NewListenAddress(address) => println!("Listening on: {}", address),
+            SplashEvent::ExternalAddressConfirmed(address) => {
+                println!("Advertising external address: {}", address);
+                if opt.introducer {
+                    println!(
+                        "Publish to serve as introducer: {}",
+                        utils::dnsaddr_record(
+                            node.network_name(),
+                            &address,
+                            &node.keys.public().to_peer_id()
+                        )
+                    );
+                }
+            }
+
+            SplashEvent::ListenerClosed { address, retry_in } => println!(
+                "Stopped listening on: {}, retrying in {}s",
+                address,
+                retry_in.as_secs()
+            ),
+
+            SplashEvent::ListenAddressExpired(address) => {
+                println!("No longer listening on: {}", address)
+            }
+
+            SplashEvent::ListenerRestored(address) => {
+                println!("Listening again on: {}", address)
+            }
+            SplashEvent::ReconnectScheduled {
+                peer_id,
+                attempt,
+                retry_in,
+            } => {
+                println!(
+                    "Reconnecting to known peer {} in {:?} (attempt {})",
+                    peer_id, retry_in, attempt
+                )
+            }
+            SplashEvent::ReconnectGaveUp(peer_id) => {
+                println!("Gave up reconnecting to known peer: {}", peer_id)
+            }
 
             SplashEvent::PeerConnected(peer_id) => {
                 let peers = metrics.increment_peers();
                 println!("Connected to peer: {} (peers: {})", peer_id, peers);
+                utils::notify_event_hooks(
+                    &hook_tasks,
+                    &run_opt.event_hook,
+                    "peer_connected",
+                    json!({"peer_id": peer_id.to_string(), "peers": peers}),
+                );
             }
 
             SplashEvent::PeerDisconnected(peer_id) => {
+                let mut peers = peer_agents.lock().expect("peer book lock poisoned");
+                peers.remove(&peer_id);
+                metrics.set_peer_capabilities(peers.values().map(|(_, capabilities)| capabilities));
+                drop(peers);
+
                 let peers = metrics.decrement_peers();
                 println!("Disconnected from peer: {} (peers: {})", peer_id, peers);
+                utils::notify_event_hooks(
+                    &hook_tasks,
+                    &run_opt.event_hook,
+                    "peer_disconnected",
+                    json!({"peer_id": peer_id.to_string(), "peers": peers}),
+                );
             }
 
             SplashEvent::OfferBroadcasted(offer) => {
@@ -161,17 +1444,210 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             }
 
             SplashEvent::OfferBroadcastFailed(err) => {
-                println!("Broadcasting Offer failed: {}", err)
+                println!("Broadcasting Offer failed: {}", err);
+                metrics.increment_offers_failed(match err {
+                    gossipsub::PublishError::Duplicate => "duplicate",
+                    gossipsub::PublishError::InsufficientPeers => "insufficient_peers",
+                    gossipsub::PublishError::MessageTooLarge => "message_too_large",
+                    _ => "other",
+                });
+            }
+
+            SplashEvent::OfferExpired(offer) => {
+                println!("Offer expired before it could be broadcasted: {}", offer);
+                metrics.increment_offers_failed("expired");
+            }
+
+            SplashEvent::PeerAuthenticated(peer_id) => {
+                println!("Peer authenticated: {}", peer_id)
             }
 
-            SplashEvent::OfferReceived(offer) => {
+            SplashEvent::PeerAuthenticationFailed(peer_id) => {
+                println!("Peer failed to authenticate: {}", peer_id)
+            }
+
+            SplashEvent::MeshPeerAdded(peer_id) => println!("Mesh peer added: {}", peer_id),
+
+            SplashEvent::MeshReady(mesh_peers) => println!("Mesh ready with {} peers", mesh_peers),
+
+            SplashEvent::MeshPeerRemoved {
+                peer_id,
+                reason,
+                score,
+            } => match score {
+                Some(score) => println!(
+                    "Mesh peer removed: {} ({:?}, score: {:.2})",
+                    peer_id, reason, score
+                ),
+                None => println!("Mesh peer removed: {} ({:?})", peer_id, reason),
+            },
+
+            SplashEvent::PeerIdentified {
+                peer_id,
+                agent_version,
+                capabilities,
+            } => {
+                let mut peers = peer_agents.lock().expect("peer book lock poisoned");
+                peers.insert(peer_id, (agent_version, capabilities));
+                metrics.set_peer_capabilities(peers.values().map(|(_, capabilities)| capabilities));
+            }
+
+            SplashEvent::NatStatusChanged(status) => {
+                let payload = match status {
+                    NatStatus::Public(address) => {
+                        println!("Node is publicly reachable at {}", address);
+                        json!({"status": "public", "address": address.to_string()})
+                    }
+                    NatStatus::Private => {
+                        println!("Node is behind NAT and not publicly reachable");
+                        json!({"status": "private"})
+                    }
+                    NatStatus::Unknown => json!({"status": "unknown"}),
+                };
+                utils::notify_event_hooks(&hook_tasks, &run_opt.event_hook, "nat_status", payload);
+            }
+
+            SplashEvent::PortMapped(address) => {
+                println!("Router forwards external address {} via UPnP", address);
+            }
+
+            SplashEvent::PortMappingExpired(address) => {
+                println!("UPnP port mapping for {} expired", address);
+            }
+
+            SplashEvent::AddressConfirmed { address, peer_id } => {
+                println!("Peer {} confirmed we are reachable at {}", peer_id, address);
+            }
+
+            SplashEvent::AddressUnreachable { address, peer_id } => {
+                println!("Peer {} could not reach us at {}", peer_id, address);
+            }
+
+            SplashEvent::ShutdownComplete(_) => {
+                shutting_down = true;
+                break;
+            }
+
+            SplashEvent::HandshakeTimeout(remote) => {
+                metrics.increment_handshake_timeouts(&remote);
+            }
+
+            SplashEvent::BlocklistUpdated { cidrs, peers } => {
+                println!("Blocklist updated: {} networks, {} peers", cidrs, peers);
+            }
+
+            SplashEvent::BlocklistRefreshFailed(reason) => {
+                eprintln!("Failed to refresh blocklist: {}", reason);
+            }
+
+            SplashEvent::OffersRateLimited { peer_id, dropped } => {
+                println!(
+                    "Dropped {} offers from {} exceeding the inbound rate limit",
+                    dropped, peer_id
+                );
+                metrics.add_offers_rate_limited(dropped);
+            }
+            SplashEvent::EventsDropped(dropped) => {
+                println!(
+                    "Dropped {} node events, event handling fell behind",
+                    dropped
+                );
+            }
+            SplashEvent::OfferArrived { topic, duplicate } => {
+                metrics.increment_offers_arrived(topic, duplicate);
+            }
+
+            SplashEvent::PeerCountLow { peers, min_peers } => {
+                println!(
+                    "Only {} peers connected, expected at least {}",
+                    peers, min_peers
+                );
+                utils::notify_event_hooks(
+                    &hook_tasks,
+                    &run_opt.event_hook,
+                    "peer_count_low",
+                    json!({"peers": peers, "min_peers": min_peers}),
+                );
+            }
+
+            SplashEvent::PeerCountRecovered(peers) => {
+                println!("Peer count recovered (peers: {})", peers);
+                utils::notify_event_hooks(
+                    &hook_tasks,
+                    &run_opt.event_hook,
+                    "peer_count_recovered",
+                    json!({"peers": peers}),
+                );
+            }
+
+            SplashEvent::Status(status) => {
+                println!(
+                    "Status: {} peers, {} mesh peers, {:.1} offers/min, {} pending submissions, {} dedup entries",
+                    status.peers,
+                    status.mesh_peers,
+                    status.offers_per_minute,
+                    status.pending_submissions,
+                    status.dedup_entries
+                );
+            }
+
+            SplashEvent::OfferFlowStalled(since) => {
+                println!("No offer received for {:?}", since);
+                utils::notify_event_hooks(
+                    &hook_tasks,
+                    &run_opt.event_hook,
+                    "offer_flow_stalled",
+                    json!({"seconds": since.as_secs()}),
+                );
+            }
+
+            SplashEvent::OfferCancelled(offer_id) => {
+                println!("Offer cancelled: {}", offer_id);
+                utils::notify_event_hooks(
+                    &hook_tasks,
+                    &run_opt.event_hook,
+                    "offer_cancelled",
+                    json!({"offer_id": offer_id}),
+                );
+            }
+
+            SplashEvent::OfferStatusChanged { offer_id, status } => {
+                println!("Offer {:?}: {}", status, offer_id);
+                utils::notify_event_hooks(
+                    &hook_tasks,
+                    &run_opt.event_hook,
+                    "offer_status",
+                    json!({"offer_id": offer_id, "status": status}),
+                );
+            }
+
+            SplashEvent::DedupCacheStats(stats) => {
+                metrics.set_seen_cache_usage(stats.entries, stats.approx_bytes);
+            }
+
+            SplashEvent::DhtStats(stats) => {
+                metrics.set_dht_usage(
+                    stats.routing_table_peers,
+                    stats.records,
+                    stats.provider_records,
+                );
+            }
+
+            SplashEvent::OfferReceived(offer, _) => {
                 println!("Received Offer: {}", offer);
                 metrics.increment_offers_received();
 
-                if let Some(ref endpoint_url) = opt.offer_hook {
+                if let Some(fifo) = offer_fifo.as_ref() {
+                    fifo.send(&offer);
+                }
+
+                if let Some(ref endpoint_url) = run_opt.offer_hook {
                     let endpoint_url_clone = endpoint_url.clone();
-                    tokio::spawn(async move {
-                        if let Err(e) = utils::offer_post_hook(&endpoint_url_clone, &offer).await {
+                    let schema = run_opt.offer_hook_schema;
+                    hook_tasks.spawn(async move {
+                        if let Err(e) =
+                            utils::offer_post_hook(&endpoint_url_clone, &offer, schema).await
+                        {
                             eprintln!("Error posting to offer hook: {}", e);
                         }
                     });
@@ -180,5 +1656,344 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
     }
 
+    // The shutdown task still waits for hooks, then run returns and destructors flush as usual
+    if shutting_down {
+        shutdown_task.await.ok();
+    }
+
     Ok(())
 }
+
+fn print_welcome(opt: &NodeOpt) {
+    println!("Welcome to Splash! v{}", env!("CARGO_PKG_VERSION"));
+
+    if opt.testnet {
+        println!("Using Testnet");
+    }
+}
+
+// The node as configured by the options, shared by run and the subcommands that join the network
+fn configure_node(opt: &NodeOpt) -> Result<Splash, Box<dyn std::error::Error>> {
+    let mut splash = Splash::new()
+        .with_listen_addresses(opt.listen_address.clone())
+        .with_internal_listen_addresses(opt.internal_listen_address.clone())
+        .with_external_addresses(opt.external_address.clone())
+        .with_advertise_map(opt.advertise_map.clone())
+        .with_known_peers(opt.known_peer.clone())
+        .with_fallback_peers(opt.fallback_peer.clone());
+
+    // Load or generate peer identity (keypair), only if --identity-file is specified
+    if let Some(keypair) = opt.identity_file.as_ref().map(|file_path| {
+        utils::load_keypair_from_file(file_path).unwrap_or_else(|_| {
+            let keypair = identity::Keypair::generate_ed25519();
+            utils::save_keypair_to_file(&keypair, file_path).ok();
+            keypair
+        })
+    }) {
+        splash = splash.with_keys(keypair);
+    }
+
+    // Restore a snapshot, only if --state-file is specified and exists, it takes precedence over --identity-file
+    if let Some(state) = opt
+        .state_file
+        .as_ref()
+        .and_then(|file_path| utils::load_state_from_file(file_path).ok())
+    {
+        println!("Restoring state ({} known peers)", state.peers.len());
+        splash = splash.with_state(state)?;
+    }
+
+    if let Some(name) = opt.operator_name.as_ref() {
+        splash = splash.with_operator(name, opt.operator_contact.as_deref());
+    }
+
+    if !opt.circuit_relay.is_empty() {
+        splash = splash.with_circuit_relays(opt.circuit_relay.clone());
+    }
+
+    if opt.circuit_relay_server {
+        splash = splash.with_circuit_relay_server();
+    }
+
+    if opt.canary_responder {
+        splash = splash.with_canary_responder();
+    }
+
+    if let Some(minutes) = opt.sync_window {
+        splash = splash.with_sync_responder(Duration::from_secs(minutes * 60));
+    }
+
+    if let Some(minutes) = opt.backfill {
+        splash = splash.with_backfill(Duration::from_secs(minutes * 60));
+    }
+
+    if let Some(max_reservations) = opt.relay_max_reservations {
+        splash = splash.with_relay_max_reservations(max_reservations);
+    }
+
+    if let Some(max_circuits) = opt.relay_max_circuits {
+        splash = splash.with_relay_max_circuits(max_circuits);
+    }
+
+    if let Some(max_circuit_duration) = opt.relay_max_circuit_duration {
+        splash = splash.with_relay_max_circuit_duration(Duration::from_secs(max_circuit_duration));
+    }
+
+    if let Some(max_circuit_bytes) = opt.relay_max_circuit_bytes {
+        splash = splash.with_relay_max_circuit_bytes(max_circuit_bytes);
+    }
+
+    if let Some(proxy) = opt.proxy {
+        splash = splash.with_proxy(proxy);
+    }
+
+    if let Some(tor_socks) = opt.tor_socks {
+        splash = splash.with_tor(tor_socks, opt.onion_address.clone());
+    }
+
+    if opt.introducer {
+        splash = splash.with_introducer();
+    }
+
+    if let Some((service, port)) = opt.cluster_dns.clone() {
+        splash = splash.with_cluster_discovery(service, port);
+    }
+
+    if opt.lazy_bootstrap {
+        splash = splash.with_lazy_bootstrap();
+    }
+
+    if opt.no_peer_scoring {
+        splash = splash.without_peer_scoring();
+    } else if let Some(graylist_threshold) = opt.graylist_threshold {
+        splash = splash.with_peer_scoring(PeerScoring {
+            graylist_threshold,
+            ..PeerScoring::default()
+        });
+    }
+
+    if opt.no_upnp {
+        splash = splash.with_upnp(false);
+    }
+
+    if !opt.trusted_relay.is_empty() {
+        splash = splash.with_trusted_relays(opt.trusted_relay.clone());
+    }
+
+    let (mut deny_cidrs, mut deny_peers) = (opt.deny_cidr.clone(), opt.deny_peer.clone());
+    if let Some(deny_file) = opt.deny_file.as_ref() {
+        let (cidrs, peers) = utils::load_deny_list(deny_file)?;
+        deny_cidrs.extend(cidrs);
+        deny_peers.extend(peers);
+    }
+    splash = splash
+        .with_allowed_cidrs(opt.allow_cidr.clone())
+        .with_denied_cidrs(deny_cidrs)
+        .with_denied_peers(deny_peers)
+        .with_alert_thresholds(AlertThresholds {
+            min_peers: opt.alert_min_peers,
+            offer_stall: opt.alert_offer_stall.map(Duration::from_secs),
+        });
+
+    if let Some(interval) = opt.status_interval {
+        splash = splash.with_status_interval(Duration::from_secs(interval.max(1)));
+    }
+
+    if let Some(peers) = opt.mesh_ready_peers {
+        splash = splash.with_mesh_ready_peers(peers);
+    }
+
+    if let Some(timeout) = opt.wait_for_mesh {
+        splash = splash.with_wait_for_mesh(Duration::from_secs(timeout));
+    }
+
+    if let (Some(url), Some(signer)) = (opt.blocklist_url.as_ref(), opt.blocklist_signer) {
+        splash = splash.with_blocklist_subscription(url.clone(), signer);
+    }
+
+    if let Some(queue_file) = opt.submission_queue_file.as_ref() {
+        splash = splash.with_submission_queue(queue_file)?;
+    }
+
+    if let Some(max_age) = opt.publish_retry {
+        splash = splash.with_publish_retry(Duration::from_secs(max_age));
+    }
+
+    if let (Some(cert_file), Some(key_file)) = (
+        opt.websocket_tls_cert.as_ref(),
+        opt.websocket_tls_key.as_ref(),
+    ) {
+        splash = splash.with_websocket_tls(cert_file, key_file)?;
+    }
+
+    configure_network(splash, opt)
+}
+
+// Broadcast an offer from the submission API, returns the receipt if the wait mode asks for it
+async fn submit_offer(
+    node: &Splash,
+    offer: &str,
+    wait: WaitMode,
+    ttl: Option<Duration>,
+) -> Result<Option<PublishReceipt>, SplashError> {
+    match (wait, ttl) {
+        (WaitMode::None, None) => node.broadcast_offer(offer).await.map(|_| None),
+        (WaitMode::None, Some(ttl)) => node
+            .broadcast_offer_with_ttl(offer, ttl)
+            .await
+            .map(|_| None),
+        (WaitMode::Published, None) => node.broadcast_offer_and_wait(offer).await.map(Some),
+        (WaitMode::Published, Some(ttl)) => node
+            .broadcast_offer_and_wait_with_ttl(offer, ttl)
+            .await
+            .map(Some),
+        (WaitMode::Propagated, None) => node.broadcast_offer_and_propagate(offer).await.map(Some),
+        (WaitMode::Propagated, Some(ttl)) => node
+            .broadcast_offer_and_propagate_with_ttl(offer, ttl)
+            .await
+            .map(Some),
+    }
+}
+
+async fn save_state(node: &Splash, state_file: &str) {
+    match node.export_state().await {
+        Ok(state) => {
+            if let Err(e) = utils::save_state_to_file(&state, state_file) {
+                eprintln!("Error saving state: {}", e);
+            }
+        }
+        Err(e) => eprintln!("Error exporting state: {}", e),
+    }
+}
+
+// A LISTEN=EXTERNAL pair of --advertise-map
+fn parse_advertise_mapping(mapping: &str) -> Result<(Multiaddr, Multiaddr), String> {
+    let (listen, external) = mapping.split_once('=').ok_or("expected LISTEN=EXTERNAL")?;
+    let listen = listen
+        .parse()
+        .map_err(|e| format!("invalid listen address: {}", e))?;
+    let external = external
+        .parse()
+        .map_err(|e| format!("invalid external address: {}", e))?;
+
+    Ok((listen, external))
+}
+
+// NAME[:PORT] of --cluster-dns
+fn parse_cluster_service(service: &str) -> Result<(String, Option<u16>), String> {
+    match service.rsplit_once(':') {
+        Some((name, port)) => {
+            let port = port.parse().map_err(|e| format!("invalid port: {}", e))?;
+            Ok((name.to_string(), Some(port)))
+        }
+        None => Ok((service.to_string(), None)),
+    }
+}
+
+// Network level options shared by every node started from this process
+fn configure_network(
+    mut splash: Splash,
+    opt: &NodeOpt,
+) -> Result<Splash, Box<dyn std::error::Error>> {
+    if opt.testnet {
+        splash = splash.with_testnet();
+    }
+
+    if let Some(namespace) = opt.topic_namespace.as_ref() {
+        splash = splash.with_topic_namespace(namespace);
+    }
+
+    if let Some(token) = opt.auth_token.as_ref() {
+        splash = splash.with_auth_token(token.clone());
+    }
+
+    if let Some(target_peers) = opt.target_peers {
+        splash = splash.with_target_peers(target_peers);
+    }
+
+    if let Some(min_peers) = opt.min_peers {
+        splash = splash.with_min_peers(min_peers);
+    }
+
+    if let Some(max_peers) = opt.max_peers {
+        splash = splash.with_max_peers(max_peers);
+    }
+
+    if let Some(budget) = opt.dedup_memory_budget {
+        splash = splash.with_dedup(DedupConfig::from_memory_budget(budget * 1024 * 1024));
+    }
+
+    if let Some(mesh_config) = opt.mesh_config.as_ref() {
+        splash = splash.with_mesh_config(utils::load_mesh_config(mesh_config)?);
+    }
+
+    if opt.ipv4_only {
+        splash = splash.with_address_family(AddressFamily::Ipv4);
+    } else if opt.ipv6_only {
+        splash = splash.with_address_family(AddressFamily::Ipv6);
+    }
+
+    splash = splash
+        .with_security(opt.security)
+        .with_offer_compression(opt.offer_compression)
+        .with_handshake_timeout(Duration::from_secs(opt.handshake_timeout))
+        .with_dial_timeout(Duration::from_secs(opt.dial_timeout))
+        .with_dial_concurrency_factor(opt.dial_concurrency)
+        .with_max_negotiating_inbound_streams(opt.max_negotiating_streams);
+
+    if !opt.publish_topic_version.is_empty() {
+        splash = splash.with_publish_topic_versions(opt.publish_topic_version.clone());
+    }
+
+    if !opt.topic.is_empty() {
+        splash = splash.with_topics(opt.topic.clone());
+    }
+
+    if let Some(rate) = opt.publish_rate {
+        splash = splash.with_publish_rate_limit(rate, opt.publish_burst);
+    }
+    splash = splash.with_inbound_rate_limit(opt.inbound_offer_rate, opt.inbound_offer_burst);
+
+    if let Some(capacity) = opt.propagation_analytics {
+        splash = splash.with_propagation_analytics(capacity);
+    }
+
+    if let Some(rate) = opt.max_upload_rate {
+        splash = splash.with_max_upload_rate(rate * 1024);
+    }
+
+    if let Some(rate) = opt.max_download_rate {
+        splash = splash.with_max_download_rate(rate * 1024);
+    }
+
+    if let Some(max) = opt.max_pending_dials {
+        splash = splash.with_max_pending_dials(max);
+    }
+
+    if let Some(max) = opt.max_inbound_connections {
+        splash = splash.with_max_inbound_connections(max);
+    }
+
+    if let Some(max) = opt.max_outbound_connections {
+        splash = splash.with_max_outbound_connections(max);
+    }
+
+    if let Some(max) = opt.max_connections_per_peer {
+        splash = splash.with_max_connections_per_peer(max);
+    }
+
+    if let Some(max) = opt.max_connections_per_ip {
+        splash = splash.with_max_connections_per_ip(max);
+    }
+
+    #[cfg(feature = "testing")]
+    {
+        splash = splash.with_chaos(ChaosConfig {
+            disconnect_interval: opt.chaos_disconnect_interval.map(Duration::from_secs),
+            publish_delay: opt.chaos_publish_delay.map(Duration::from_millis),
+            drop_incoming_percent: opt.chaos_drop_incoming,
+        });
+    }
+
+    Ok(splash)
+}
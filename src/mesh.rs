@@ -0,0 +1,69 @@
+use libp2p::PeerId;
+use std::collections::HashSet;
+use std::time::Duration;
+
+/// Gossipsub mesh shape for the offers topic, wider meshes deliver more reliably at the cost of
+/// more duplicate traffic. Defaults are the gossipsub defaults, with a 5 second heartbeat
+#[derive(Clone, Debug)]
+pub struct MeshConfig {
+    /// Target number of mesh peers (D)
+    pub mesh_n: usize,
+    /// Below this many mesh peers more are grafted (D_lo)
+    pub mesh_n_low: usize,
+    /// Above this many mesh peers some are pruned (D_hi)
+    pub mesh_n_high: usize,
+    /// Minimum number of outbound mesh peers (D_out), must be below `mesh_n_low` and at most half of `mesh_n`
+    pub mesh_outbound_min: usize,
+    pub heartbeat_interval: Duration,
+    /// Heartbeats a message is kept in the message cache
+    pub history_length: usize,
+    /// Heartbeats of the message cache that are gossiped about, at most `history_length`
+    pub history_gossip: usize,
+}
+
+impl Default for MeshConfig {
+    fn default() -> Self {
+        MeshConfig {
+            mesh_n: 6,
+            mesh_n_low: 5,
+            mesh_n_high: 12,
+            mesh_outbound_min: 2,
+            heartbeat_interval: Duration::from_secs(5),
+            history_length: 5,
+            history_gossip: 3,
+        }
+    }
+}
+
+/// Why a peer left our mesh for the offers topic
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MeshRemovalReason {
+    /// The connection to the peer was closed
+    Disconnected,
+    /// The peer unsubscribed from the offers topic
+    Unsubscribed,
+    /// Still connected and subscribed, so the peer was pruned (by either side, e.g. due to scoring or mesh size)
+    Pruned,
+}
+
+// Remembers the mesh of the last heartbeat to report joins and departures
+#[derive(Default)]
+pub(crate) struct MeshTracker {
+    peers: HashSet<PeerId>,
+}
+
+impl MeshTracker {
+    pub(crate) fn update(
+        &mut self,
+        current: impl Iterator<Item = PeerId>,
+    ) -> (Vec<PeerId>, Vec<PeerId>) {
+        let current: HashSet<PeerId> = current.collect();
+
+        let added = current.difference(&self.peers).copied().collect();
+        let removed = self.peers.difference(&current).copied().collect();
+
+        self.peers = current;
+
+        (added, removed)
+    }
+}
@@ -1,26 +1,65 @@
+use libp2p::multiaddr::Protocol;
+use libp2p::Multiaddr;
 use serde::Serialize;
-use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::Arc;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Bounds the label cardinality of per-prefix metrics, further prefixes are counted as "other"
+const MAX_ADDRESS_PREFIXES: usize = 1024;
 
 #[derive(Clone, Debug)]
 pub struct Metrics {
+    // Labels of every series, so nodes on different networks share a dashboard
+    network: Arc<str>,
+    topic: Arc<str>,
     peers: Arc<AtomicUsize>,
     offers_broadcasted: Arc<AtomicUsize>,
     offers_received: Arc<AtomicUsize>,
     total_connections: Arc<AtomicUsize>,
+    last_offer_received: Arc<AtomicU64>,
+    last_peer_connected: Arc<AtomicU64>,
+    seen_cache_entries: Arc<AtomicUsize>,
+    seen_cache_bytes: Arc<AtomicUsize>,
+    dht_routing_table_peers: Arc<AtomicUsize>,
+    dht_records: Arc<AtomicUsize>,
+    dht_provider_records: Arc<AtomicUsize>,
+    handshake_timeouts: Arc<Mutex<BTreeMap<String, u64>>>,
+    offers_failed: Arc<Mutex<BTreeMap<&'static str, u64>>>,
+    offers_rate_limited: Arc<AtomicU64>,
+    offers_arrived: Arc<Mutex<BTreeMap<String, TopicOffers>>>,
+    peer_capabilities: Arc<Mutex<BTreeMap<String, usize>>>,
 }
 
 impl Metrics {
-    pub fn new() -> Self {
+    pub fn new(network: &str, topic: &str) -> Self {
         Self {
+            network: network.into(),
+            topic: topic.into(),
             peers: Arc::new(AtomicUsize::new(0)),
             offers_broadcasted: Arc::new(AtomicUsize::new(0)),
             offers_received: Arc::new(AtomicUsize::new(0)),
             total_connections: Arc::new(AtomicUsize::new(0)),
+            last_offer_received: Arc::new(AtomicU64::new(0)),
+            last_peer_connected: Arc::new(AtomicU64::new(0)),
+            seen_cache_entries: Arc::new(AtomicUsize::new(0)),
+            seen_cache_bytes: Arc::new(AtomicUsize::new(0)),
+            dht_routing_table_peers: Arc::new(AtomicUsize::new(0)),
+            dht_records: Arc::new(AtomicUsize::new(0)),
+            dht_provider_records: Arc::new(AtomicUsize::new(0)),
+            handshake_timeouts: Arc::new(Mutex::new(BTreeMap::new())),
+            offers_failed: Arc::new(Mutex::new(BTreeMap::new())),
+            offers_rate_limited: Arc::new(AtomicU64::new(0)),
+            offers_arrived: Arc::new(Mutex::new(BTreeMap::new())),
+            peer_capabilities: Arc::new(Mutex::new(BTreeMap::new())),
         }
     }
 
     pub fn increment_peers(&self) -> usize {
+        self.last_peer_connected
+            .store(unix_timestamp(), Ordering::SeqCst);
         self.peers.fetch_add(1, Ordering::SeqCst) + 1
     }
 
@@ -29,6 +68,8 @@ impl Metrics {
     }
 
     pub fn increment_offers_received(&self) {
+        self.last_offer_received
+            .store(unix_timestamp(), Ordering::SeqCst);
         self.offers_received.fetch_add(1, Ordering::SeqCst);
     }
 
@@ -36,14 +77,293 @@ impl Metrics {
         self.offers_broadcasted.fetch_add(1, Ordering::SeqCst);
     }
 
+    pub fn increment_offers_failed(&self, reason: &'static str) {
+        *self
+            .offers_failed
+            .lock()
+            .expect("metrics lock poisoned")
+            .entry(reason)
+            .or_default() += 1;
+    }
+
+    pub fn add_offers_rate_limited(&self, dropped: u64) {
+        self.offers_rate_limited
+            .fetch_add(dropped, Ordering::SeqCst);
+    }
+
+    pub fn increment_offers_arrived(&self, topic: String, duplicate: bool) {
+        let mut arrived = self.offers_arrived.lock().expect("metrics lock poisoned");
+        let offers = arrived.entry(topic).or_default();
+        if duplicate {
+            offers.duplicates += 1;
+        } else {
+            offers.first += 1;
+        }
+    }
+
+    // Count the connected peers announcing each capability, from their capability lists
+    pub fn set_peer_capabilities<'a>(&self, peers: impl Iterator<Item = &'a Vec<String>>) {
+        let mut counts = BTreeMap::new();
+        for capability in peers.flatten() {
+            *counts.entry(capability.clone()).or_default() += 1;
+        }
+        *self
+            .peer_capabilities
+            .lock()
+            .expect("metrics lock poisoned") = counts;
+    }
+
+    pub fn set_seen_cache_usage(&self, entries: usize, bytes: usize) {
+        self.seen_cache_entries.store(entries, Ordering::SeqCst);
+        self.seen_cache_bytes.store(bytes, Ordering::SeqCst);
+    }
+
+    pub fn set_dht_usage(
+        &self,
+        routing_table_peers: usize,
+        records: usize,
+        provider_records: usize,
+    ) {
+        self.dht_routing_table_peers
+            .store(routing_table_peers, Ordering::SeqCst);
+        self.dht_records.store(records, Ordering::SeqCst);
+        self.dht_provider_records
+            .store(provider_records, Ordering::SeqCst);
+    }
+
+    pub fn increment_handshake_timeouts(&self, remote: &Multiaddr) {
+        let mut timeouts = self
+            .handshake_timeouts
+            .lock()
+            .expect("metrics lock poisoned");
+
+        let mut prefix = address_prefix(remote);
+        if !timeouts.contains_key(&prefix) && timeouts.len() >= MAX_ADDRESS_PREFIXES {
+            prefix = "other".to_string();
+        }
+
+        *timeouts.entry(prefix).or_default() += 1;
+    }
+
     pub fn get_metrics(&self) -> MetricsData {
         MetricsData {
             peers: self.peers.load(Ordering::SeqCst),
             offers_broadcasted: self.offers_broadcasted.load(Ordering::SeqCst),
             offers_received: self.offers_received.load(Ordering::SeqCst),
             total_connections: self.total_connections.load(Ordering::SeqCst),
+            last_offer_received_timestamp_seconds: self.last_offer_received.load(Ordering::SeqCst),
+            last_peer_connected_timestamp_seconds: self.last_peer_connected.load(Ordering::SeqCst),
+            seen_cache_entries: self.seen_cache_entries.load(Ordering::SeqCst),
+            seen_cache_bytes: self.seen_cache_bytes.load(Ordering::SeqCst),
+            dht_routing_table_peers: self.dht_routing_table_peers.load(Ordering::SeqCst),
+            dht_records: self.dht_records.load(Ordering::SeqCst),
+            dht_provider_records: self.dht_provider_records.load(Ordering::SeqCst),
+            handshake_timeouts: self
+                .handshake_timeouts
+                .lock()
+                .expect("metrics lock poisoned")
+                .clone(),
+            offers_failed: self
+                .offers_failed
+                .lock()
+                .expect("metrics lock poisoned")
+                .iter()
+                .map(|(reason, count)| (reason.to_string(), *count))
+                .collect(),
+            offers_rate_limited: self.offers_rate_limited.load(Ordering::SeqCst),
+            offers_arrived: self
+                .offers_arrived
+                .lock()
+                .expect("metrics lock poisoned")
+                .clone(),
+            peer_capabilities: self
+                .peer_capabilities
+                .lock()
+                .expect("metrics lock poisoned")
+                .clone(),
         }
     }
+
+    // Render the metrics in the Prometheus text exposition format
+    pub fn to_prometheus(&self) -> String {
+        let data = self.get_metrics();
+        let mut out = String::new();
+
+        let network = format!("network=\"{}\"", self.network);
+        let topic = format!("{},topic=\"{}\"", network, self.topic);
+
+        let mut metric = |name: &str, kind: &str, help: &str, series: &[(String, u64)]| {
+            writeln!(out, "# HELP {} {}", name, help).ok();
+            writeln!(out, "# TYPE {} {}", name, kind).ok();
+            for (labels, value) in series {
+                writeln!(out, "{}{{{}}} {}", name, labels, value).ok();
+            }
+        };
+
+        metric(
+            "splash_peers",
+            "gauge",
+            "Number of currently connected peers",
+            &[(network.clone(), data.peers as u64)],
+        );
+        metric(
+            "splash_offers_total",
+            "counter",
+            "Number of offers broadcasted by this node (outbound) and received from the network (inbound)",
+            &[
+                (
+                    format!("{},direction=\"outbound\"", topic),
+                    data.offers_broadcasted as u64,
+                ),
+                (
+                    format!("{},direction=\"inbound\"", topic),
+                    data.offers_received as u64,
+                ),
+            ],
+        );
+        metric(
+            "splash_offers_failed_total",
+            "counter",
+            "Number of offers that could not be broadcasted, by reason",
+            &data
+                .offers_failed
+                .iter()
+                .map(|(reason, count)| {
+                    (
+                        format!("{},direction=\"outbound\",reason=\"{}\"", topic, reason),
+                        *count,
+                    )
+                })
+                .collect::<Vec<_>>(),
+        );
+        metric(
+            "splash_offers_rate_limited_total",
+            "counter",
+            "Number of received offers dropped because their peer exceeded the inbound rate limit",
+            &[(
+                format!("{},direction=\"inbound\"", topic),
+                data.offers_rate_limited,
+            )],
+        );
+        metric(
+            "splash_offers_arrived_total",
+            "counter",
+            "Number of valid offers received per offers topic, duplicates were already received, e.g. on another topic version",
+            &data
+                .offers_arrived
+                .iter()
+                .flat_map(|(arrival_topic, offers)| {
+                    [("false", offers.first), ("true", offers.duplicates)].map(
+                        |(duplicate, count)| {
+                            (
+                                format!(
+                                    "{},topic=\"{}\",duplicate=\"{}\"",
+                                    network, arrival_topic, duplicate
+                                ),
+                                count,
+                            )
+                        },
+                    )
+                })
+                .collect::<Vec<_>>(),
+        );
+        metric(
+            "splash_last_offer_received_timestamp_seconds",
+            "gauge",
+            "Unix timestamp of the last received offer, 0 if none yet",
+            &[(topic.clone(), data.last_offer_received_timestamp_seconds)],
+        );
+        metric(
+            "splash_last_peer_connected_timestamp_seconds",
+            "gauge",
+            "Unix timestamp of the last peer connection, 0 if none yet",
+            &[(network.clone(), data.last_peer_connected_timestamp_seconds)],
+        );
+        metric(
+            "splash_seen_cache_entries",
+            "gauge",
+            "Number of offers remembered for duplicate suppression",
+            &[(topic.clone(), data.seen_cache_entries as u64)],
+        );
+        metric(
+            "splash_seen_cache_bytes",
+            "gauge",
+            "Approximate memory used by the seen-offer cache",
+            &[(topic.clone(), data.seen_cache_bytes as u64)],
+        );
+        metric(
+            "splash_peer_capabilities",
+            "gauge",
+            "Number of connected peers announcing a capability",
+            &data
+                .peer_capabilities
+                .iter()
+                .map(|(capability, peers)| {
+                    (
+                        format!("{},capability=\"{}\"", network, capability),
+                        *peers as u64,
+                    )
+                })
+                .collect::<Vec<_>>(),
+        );
+        metric(
+            "splash_dht_routing_table_peers",
+            "gauge",
+            "Number of peers in the Kademlia routing table",
+            &[(network.clone(), data.dht_routing_table_peers as u64)],
+        );
+        metric(
+            "splash_dht_records",
+            "gauge",
+            "Number of records in the Kademlia store",
+            &[(network.clone(), data.dht_records as u64)],
+        );
+        metric(
+            "splash_dht_provider_records",
+            "gauge",
+            "Number of provider records published by this node",
+            &[(network.clone(), data.dht_provider_records as u64)],
+        );
+        metric(
+            "splash_handshake_timeouts_total",
+            "counter",
+            "Inbound connections that timed out during the handshake, by remote address prefix",
+            &data
+                .handshake_timeouts
+                .iter()
+                .map(|(prefix, count)| {
+                    (
+                        format!("{},direction=\"inbound\",prefix=\"{}\"", network, prefix),
+                        *count,
+                    )
+                })
+                .collect::<Vec<_>>(),
+        );
+
+        out
+    }
+}
+
+// Group remote addresses by network, /24 for IPv4 and /48 for IPv6
+fn address_prefix(addr: &Multiaddr) -> String {
+    match addr.iter().next() {
+        Some(Protocol::Ip4(ip)) => {
+            let [a, b, c, _] = ip.octets();
+            format!("{}.{}.{}.0/24", a, b, c)
+        }
+        Some(Protocol::Ip6(ip)) => {
+            let segments = ip.segments();
+            format!("{:x}:{:x}:{:x}::/48", segments[0], segments[1], segments[2])
+        }
+        _ => "other".to_string(),
+    }
+}
+
+fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
 }
 
 #[derive(Serialize)]
@@ -52,4 +372,23 @@ pub struct MetricsData {
     pub offers_broadcasted: usize,
     pub offers_received: usize,
     pub total_connections: usize,
+    pub last_offer_received_timestamp_seconds: u64,
+    pub last_peer_connected_timestamp_seconds: u64,
+    pub seen_cache_entries: usize,
+    pub seen_cache_bytes: usize,
+    pub dht_routing_table_peers: usize,
+    pub dht_records: usize,
+    pub dht_provider_records: usize,
+    pub handshake_timeouts: BTreeMap<String, u64>,
+    pub offers_failed: BTreeMap<String, u64>,
+    pub offers_rate_limited: u64,
+    pub offers_arrived: BTreeMap<String, TopicOffers>,
+    pub peer_capabilities: BTreeMap<String, usize>,
+}
+
+// Offers received on one offers topic, first arrivals and duplicates of offers already received
+#[derive(Serialize, Clone, Copy, Default)]
+pub struct TopicOffers {
+    pub first: u64,
+    pub duplicates: u64,
 }
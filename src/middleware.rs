@@ -0,0 +1,92 @@
+use crate::OfferMetadata;
+use futures::future::{BoxFuture, FutureExt};
+use libp2p::gossipsub::MessageId;
+use libp2p::PeerId;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+/// A received offer passed along the inbound middleware chain
+#[derive(Clone, Debug)]
+pub struct InboundOffer {
+    pub offer: String,
+    /// Metadata of enveloped offers, empty for bare ones
+    pub metadata: OfferMetadata,
+    /// The peer that delivered the offer to us, not necessarily its author
+    pub propagation_source: PeerId,
+    /// Notes left by earlier middleware for later ones, e.g. parsed fields or a reputation score
+    pub annotations: BTreeMap<String, String>,
+}
+
+/// An offer submitted through this node, passed along the outbound middleware chain
+#[derive(Clone, Debug)]
+pub struct OutboundOffer {
+    pub offer: String,
+    /// Notes left by earlier middleware for later ones, e.g. the tag of the submitting application
+    pub annotations: BTreeMap<String, String>,
+}
+
+pub(crate) type InboundMiddleware =
+    Arc<dyn Fn(InboundOffer) -> BoxFuture<'static, Option<InboundOffer>> + Send + Sync>;
+
+pub(crate) fn inbound<F, Fut>(middleware: F) -> InboundMiddleware
+where
+    F: Fn(InboundOffer) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Option<InboundOffer>> + Send + 'static,
+{
+    Arc::new(move |offer| middleware(offer).boxed())
+}
+
+pub(crate) type OutboundMiddleware =
+    Arc<dyn Fn(OutboundOffer) -> BoxFuture<'static, Result<OutboundOffer, String>> + Send + Sync>;
+
+pub(crate) fn outbound<F, Fut>(middleware: F) -> OutboundMiddleware
+where
+    F: Fn(OutboundOffer) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<OutboundOffer, String>> + Send + 'static,
+{
+    Arc::new(move |offer| middleware(offer).boxed())
+}
+
+// Runs the chain in registration order, the first rejection is returned to the submitter
+pub(crate) async fn run_outbound(
+    chain: &[OutboundMiddleware],
+    mut offer: OutboundOffer,
+) -> Result<OutboundOffer, String> {
+    for middleware in chain {
+        offer = middleware(offer).await?;
+    }
+
+    Ok(offer)
+}
+
+// Outcome of the chain, with the gossip message to validate if the offer came from the mesh
+pub(crate) struct Filtered {
+    pub(crate) gossip: Option<(MessageId, PeerId)>,
+    pub(crate) offer: Option<InboundOffer>,
+}
+
+// Middleware may take its time, so the chain runs off the event loop and reports back
+pub(crate) fn spawn_inbound(
+    filtered: impl Future<Output = Option<InboundOffer>> + Send + 'static,
+    gossip: Option<(MessageId, PeerId)>,
+    results: mpsc::Sender<Filtered>,
+) {
+    tokio::spawn(async move {
+        let offer = filtered.await;
+        results.send(Filtered { gossip, offer }).await.ok();
+    });
+}
+
+// Runs the chain in registration order, stopping at the first middleware that drops the offer
+pub(crate) async fn run_inbound(
+    chain: &[InboundMiddleware],
+    mut offer: InboundOffer,
+) -> Option<InboundOffer> {
+    for middleware in chain {
+        offer = middleware(offer).await?;
+    }
+
+    Some(offer)
+}
@@ -0,0 +1,1214 @@
+use crate::behaviour::{SplashBehaviour, SplashBehaviourEvent};
+use crate::{
+    addresses, alerts, auth, blocklist, canary, capabilities, chaos, cluster, connections, control,
+    dht, dialback, discovery, dns, events, listeners, mesh, middleware, propagation, push, queue,
+    reconnect, seen_key, state, status, sync, throttle, topics, transport, AddressFamily, Command,
+    Inbound, InboundOffer, MeshRemovalReason, NodeState, OfferMetadata, PublishReceipt, RawMessage,
+    ShutdownSummary, Splash, SplashError, SplashEvent, Submission, BOOTSTRAP_RETRY_INTERVAL,
+    INTRODUCER_TARGET_PEERS, PENDING_RETRY_INTERVAL, RATE_LIMIT_REPORT_INTERVAL,
+};
+use futures::stream::StreamExt;
+use libp2p::gossipsub::MessageAcceptance;
+use libp2p::multiaddr::Protocol;
+use libp2p::request_response;
+use libp2p::swarm::dial_opts::{DialOpts, PeerCondition};
+use libp2p::swarm::SwarmEvent;
+use libp2p::{autonat, dcutr, gossipsub, identify, kad, relay, upnp, Multiaddr, PeerId, Swarm};
+use log::warn;
+use rand::seq::IteratorRandom;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+use tokio::{select, time};
+
+impl Splash {
+    // Start listening and dialing, then run the event loop until the node shuts down
+    pub(crate) async fn run(
+        &mut self,
+        mut swarm: Swarm<SplashBehaviour>,
+        mut event_tx: events::EventSender,
+        offer_topics: topics::OfferTopics,
+        negotiated_security: transport::NegotiatedSecurity,
+        mut connection_book: connections::ConnectionBook,
+        dns_introducers: bool,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let family = self.address_family;
+        let mut listen_addresses = Vec::new();
+        if !self.listen_addresses.is_empty() {
+            for addr in self.listen_addresses.iter() {
+                if !family.allows(addr) {
+                    warn!(
+                        "Not listening on {}, it is not an {:?} address",
+                        addr, family
+                    );
+                    continue;
+                }
+                listen_addresses.push(addresses::resolve_listen(addr, family).await?);
+            }
+        } else if self.tor {
+            // Only reachable through the onion service
+            listen_addresses.push("/ip4/127.0.0.1/tcp/0".parse()?);
+        } else {
+            // Fallback to default addresses if no listen addresses are provided
+            if family != AddressFamily::Ipv6 {
+                listen_addresses.push("/ip4/0.0.0.0/tcp/0".parse()?);
+            }
+            if family != AddressFamily::Ipv4 {
+                listen_addresses.push("/ip6/::/tcp/0".parse()?);
+            }
+        }
+
+        // Reserve a slot on each relay, peers can then reach us via <relay>/p2p-circuit/p2p/<us>
+        for addr in self.circuit_relays.iter() {
+            if !matches!(addr.iter().last(), Some(Protocol::P2p(_))) {
+                return Err("Expect relay multiaddr to contain peer ID.".into());
+            }
+            listen_addresses.push(addr.clone().with(Protocol::P2pCircuit));
+        }
+
+        let mut listeners = listeners::Listeners::default();
+        for addr in listen_addresses {
+            listeners.started(swarm.listen_on(addr.clone())?, addr, false);
+        }
+
+        let mut internal_listeners = HashSet::new();
+        for addr in self.internal_listen_addresses.iter() {
+            let listener_id = swarm.listen_on(addr.clone())?;
+            listeners.started(listener_id, addr.clone(), true);
+            internal_listeners.insert(listener_id);
+        }
+        let mut relisten_interval: Option<time::Interval> = None;
+
+        // Configured external addresses take the place of observed ones
+        let configured_externals: HashSet<Multiaddr> = self
+            .external_addresses
+            .iter()
+            .chain(self.onion_address.iter())
+            .cloned()
+            .collect();
+        for addr in configured_externals.iter() {
+            swarm.add_external_address(addr.clone());
+        }
+        let explicit_external = !self.external_addresses.is_empty();
+        let private_addresses = self.private_addresses;
+        let tor = self.tor;
+        let advertise_map = self.advertise_map.clone();
+        // External addresses taken from our own listen addresses, by listen address, the rest were observed by peers
+        let mut listen_externals: HashMap<Multiaddr, Multiaddr> = HashMap::new();
+        let mut dial_back = dialback::DialBack::default();
+        let mut canaries = canary::Canaries::default();
+        let canary_responder = self.canary_responder;
+        let mut recent_offers = self.sync_window.map(sync::RecentOffers::new);
+        let mut backfill = self.backfill.map(sync::Backfill::new);
+        let mut fetches = sync::Fetches::default();
+
+        let mut pushes_in_flight = HashSet::new();
+        let mut submissions_closed = false;
+        let mut draining: Option<(time::Instant, oneshot::Sender<ShutdownSummary>)> = None;
+        let mut drain_check_interval: Option<time::Interval> = None;
+
+        let mut trusted_relays = Vec::new();
+        for addr in self.trusted_relays.iter() {
+            let Some(Protocol::P2p(peer_id)) = addr.iter().last() else {
+                return Err("Expect relay multiaddr to contain peer ID.".into());
+            };
+            swarm.add_peer_address(peer_id, addr.clone());
+            swarm.dial(addr.clone()).ok();
+            trusted_relays.push(peer_id);
+        }
+
+        // Subscribe to the plain and/or compressed offers topic
+        offer_topics.subscribe(&mut swarm.behaviour_mut().gossipsub)?;
+        let topic = offer_topics.primary().clone();
+        let control_topic = gossipsub::IdentTopic::new(self.control_topic());
+        swarm.behaviour_mut().gossipsub.subscribe(&control_topic)?;
+        let status_topic = gossipsub::IdentTopic::new(self.status_topic());
+        swarm.behaviour_mut().gossipsub.subscribe(&status_topic)?;
+
+        let target_peers = if self.introducer {
+            self.target_peers.max(INTRODUCER_TARGET_PEERS)
+        } else {
+            self.target_peers
+        };
+        let mut discovery = discovery::AdaptiveDiscovery::new(target_peers);
+        let peer_discovery = time::sleep(discovery.interval());
+        tokio::pin!(peer_discovery);
+
+        // Check the mesh once per gossipsub heartbeat
+        let mut mesh_interval = time::interval(self.mesh.heartbeat_interval);
+        let mut mesh_tracker = mesh::MeshTracker::default();
+        // Callers of `wait_for_mesh` until the mesh is ready, afterwards they are answered right away
+        let mesh_ready_peers = self.mesh_ready_peers;
+        let mut mesh_ready = false;
+        let mut mesh_waiters: Vec<oneshot::Sender<usize>> = Vec::new();
+
+        let mut inbound_pipeline = self.inbound_pipeline()?;
+        if let Some(state) = self.restored_state.take() {
+            if state.seen_offers_format == state::SEEN_OFFERS_FORMAT {
+                for key in state.seen_offers {
+                    inbound_pipeline.seen.insert(key);
+                }
+            } else {
+                warn!("Not restoring the seen offers of a snapshot in an older format");
+            }
+        }
+
+        let mut command_receiver = self
+            .command_receiver
+            .take()
+            .ok_or("Command receiver already consumed")?;
+        let identity = self.keys.to_protobuf_encoding()?;
+
+        let pending = self.pending.clone();
+        let publish_retry_max_age = self.publish_retry_max_age.map(|max_age| max_age.as_secs());
+        let mut pending_retry_interval = pending
+            .as_ref()
+            .map(|_| time::interval(PENDING_RETRY_INTERVAL));
+        let mut dedup_stats_interval = time::interval(Duration::from_secs(60));
+
+        let mut dht_cleanup_interval = time::interval(dht::CLEANUP_INTERVAL);
+        // The first check waits one interval, giving bootstrapping a moment
+        let mut alerts = alerts::Alerts::new(self.alert_thresholds);
+        let mut status_reporter = status::StatusReporter::new();
+        let mut status_interval = self
+            .status_interval
+            .map(|interval| time::interval_at(time::Instant::now() + interval, interval));
+        let mut alert_check_interval = alerts.is_enabled().then(|| {
+            time::interval_at(
+                time::Instant::now() + alerts::ALERT_CHECK_INTERVAL,
+                alerts::ALERT_CHECK_INTERVAL,
+            )
+        });
+        let bootstrap_peers: Vec<PeerId> = self
+            .known_peers
+            .iter()
+            .filter_map(|addr| match addr.iter().last() {
+                Some(Protocol::P2p(peer_id)) => Some(peer_id),
+                _ => None,
+            })
+            .collect();
+        let mut stale_peers = dht::StalePeers::new(bootstrap_peers.iter().copied());
+
+        let mut reconnect = reconnect::Reconnect::new(&self.known_peers);
+        let mut reconnect_interval =
+            (!reconnect.is_empty()).then(|| time::interval(reconnect::RECONNECT_CHECK_INTERVAL));
+
+        let mut bootstrap_retry_interval = self.lazy_bootstrap.then(|| {
+            time::interval_at(
+                time::Instant::now() + BOOTSTRAP_RETRY_INTERVAL,
+                BOOTSTRAP_RETRY_INTERVAL,
+            )
+        });
+
+        let (cluster_tx, mut cluster_rx) = mpsc::channel(1);
+        let mut cluster_discovery = self
+            .cluster_service
+            .clone()
+            .map(|(service, port)| cluster::ClusterDiscovery::new(service, port, cluster_tx));
+        let mut cluster_discovery_interval = cluster_discovery
+            .as_ref()
+            .map(|_| time::interval(cluster::DISCOVERY_INTERVAL));
+
+        let (blocklist_tx, mut blocklist_rx) = mpsc::channel(1);
+        let mut blocklist = match self.blocklist.as_ref() {
+            Some((url, signer)) => Some(blocklist::BlocklistSubscription::new(
+                url.clone(),
+                signer,
+                self.proxy,
+                blocklist_tx,
+            )?),
+            None => None,
+        };
+        let mut blocklist_interval = blocklist
+            .as_ref()
+            .map(|_| time::interval(blocklist::REFRESH_INTERVAL));
+
+        let (introducers_tx, mut introducers_rx) = mpsc::channel(1);
+        let mut introducer_refresh = dns_introducers.then(|| {
+            dns::IntroducerRefresh::new(self.network_name.clone(), self.proxy, introducers_tx)
+        });
+        let mut introducer_refresh_interval = introducer_refresh.as_ref().map(|_| {
+            time::interval_at(
+                time::Instant::now() + dns::REFRESH_INTERVAL,
+                dns::REFRESH_INTERVAL,
+            )
+        });
+
+        let connection_manager = connections::ConnectionManager::new(
+            self.min_peers,
+            self.max_peers,
+            bootstrap_peers.iter().chain(trusted_relays.iter()).copied(),
+        );
+        let mut connection_check_interval = connection_manager
+            .is_enabled()
+            .then(|| time::interval(connections::CHECK_INTERVAL));
+
+        // Take submission_receiver early to avoid partial move error
+        let submission_receiver = self
+            .submission_receiver
+            .take()
+            .ok_or("Submission receiver already consumed")?;
+
+        let chaos = self.chaos.clone();
+        let raw_messages = self.raw_messages.clone();
+        let (filtered_tx, mut filtered_rx) = mpsc::channel::<middleware::Filtered>(100);
+        let mut submission_receiver = chaos.delay_submissions(submission_receiver);
+        let mut publish_limiter = self
+            .publish_rate
+            .map(|(rate, burst)| throttle::PublishLimiter::new(rate, burst));
+        let mut chaos_disconnect_interval = chaos.disconnect_interval();
+        let mut inbound_limiter =
+            throttle::InboundLimiter::new(self.inbound_rate.0, self.inbound_rate.1);
+        let mut rate_limit_report_interval = time::interval(RATE_LIMIT_REPORT_INTERVAL);
+        let mut propagation_log = self.propagation_log.map(propagation::PropagationLog::new);
+
+        let local_peer_id = *swarm.local_peer_id();
+        let mut authenticator = self.auth_token.clone().map(auth::Authenticator::new);
+        let mut auth_check_interval = authenticator
+            .as_ref()
+            .map(|_| time::interval(Duration::from_secs(1)));
+
+        event_tx.send(SplashEvent::Initialized(self.keys.public().to_peer_id()));
+
+        // Main event loop
+        tokio::spawn(async move {
+            loop {
+                select! {
+                    Some(middleware::Filtered { gossip, offer }) = filtered_rx.recv() => {
+                        if let Some((message_id, propagation_source)) = gossip {
+                            let acceptance = if offer.is_some() { MessageAcceptance::Accept } else { MessageAcceptance::Ignore };
+                            swarm.behaviour_mut().gossipsub.report_message_validation_result(&message_id, &propagation_source, acceptance).ok();
+                        }
+
+                        if let Some(offer) = offer {
+                            alerts.offer_received();
+                            status_reporter.offer_received();
+                            if let Some(recent) = recent_offers.as_mut() {
+                                recent.insert(&mut swarm.behaviour_mut().kademlia, offer.offer.clone());
+                            }
+                            event_tx.send(SplashEvent::OfferReceived(offer.offer, offer.metadata));
+                        }
+                    },
+                    submission = async {
+                        if let Some(limiter) = publish_limiter.as_ref() {
+                            limiter.ready().await;
+                        }
+                        submission_receiver.recv().await
+                    }, if !submissions_closed => {
+                        let Some(Submission { offer, metadata, expires_at, receipt }) = submission else {
+                            submissions_closed = true;
+                            continue;
+                        };
+
+                        // Waited in the submission channel for too long, e.g. behind the publish rate limit
+                        if expires_at.is_some_and(|at| time::Instant::now() >= at) {
+                            remove_pending(&pending, &offer);
+                            if let Some(receipt) = receipt {
+                                receipt.send(Err(SplashError::Expired)).ok();
+                            }
+                            event_tx.send(SplashEvent::OfferExpired(String::from_utf8_lossy(&offer).to_string()));
+                            continue;
+                        }
+
+                        if let Some(limiter) = publish_limiter.as_mut() {
+                            limiter.take();
+                        }
+
+                        inbound_pipeline.seen.insert(seen_key(&offer));
+
+                        // Relays get the offer regardless of the publish outcome, that's the point of them
+                        for peer_id in trusted_relays.iter() {
+                            pushes_in_flight.insert(swarm.behaviour_mut().push.send_request(peer_id, push::PushOffer { offer: String::from_utf8_lossy(&offer).into_owned() }));
+                        }
+
+                        let submission = Submission { offer, metadata, expires_at, receipt };
+                        publish_submission(&mut swarm, &offer_topics, &pending, &mut recent_offers, &mut event_tx, submission);
+                    },
+                    _ = tick_optional(&mut pending_retry_interval) => {
+                        let (expired, due) = match pending.as_ref() {
+                            Some(pending) => {
+                                let mut pending = pending.lock().expect("pending queue lock poisoned");
+                                let expired = pending.expire(publish_retry_max_age).unwrap_or_else(|e| {
+                                    warn!("Failed to update pending submission queue: {}", e);
+                                    Vec::new()
+                                });
+                                (expired, pending.due(PENDING_RETRY_INTERVAL.as_secs()))
+                            }
+                            None => (Vec::new(), Vec::new()),
+                        };
+
+                        for offer in expired {
+                            event_tx.send(SplashEvent::OfferExpired(offer));
+                        }
+
+                        for pending_offer in due {
+                            let submission = Submission { offer: pending_offer.offer.into_bytes(), metadata: pending_offer.metadata, expires_at: None, receipt: None };
+                            publish_submission(&mut swarm, &offer_topics, &pending, &mut recent_offers, &mut event_tx, submission);
+                        }
+                    },
+                    _ = &mut peer_discovery => {
+                        swarm.behaviour_mut().kademlia.get_closest_peers(PeerId::random());
+                        let next = discovery.next_interval(swarm.connected_peers().count());
+                        peer_discovery.as_mut().reset(time::Instant::now() + next);
+                    },
+                    Some(command) = command_receiver.recv() => match command {
+                        Command::ExportState(response) => {
+                            let state = NodeState {
+                                identity: identity.clone(),
+                                peers: state::address_book(&mut swarm.behaviour_mut().kademlia),
+                                seen_offers: inbound_pipeline.seen.keys(),
+                                seen_offers_format: state::SEEN_OFFERS_FORMAT,
+                            };
+                            response.send(state).ok();
+                        }
+                        Command::Connections(response) => {
+                            response.send(connection_book.snapshot(&negotiated_security)).ok();
+                        }
+                        Command::Propagation(response) => {
+                            response.send(propagation_log.as_ref().map(|log| log.snapshot()).unwrap_or_default()).ok();
+                        }
+                        Command::PublishControl(message, response) => {
+                            let topic = if message.is_status() { status_topic.clone() } else { control_topic.clone() };
+                            let result = swarm.behaviour_mut().gossipsub.publish(topic, message.to_bytes());
+                            response.send(result.map(|_| ()).map_err(|e| SplashError::PublishFailed(e.to_string()))).ok();
+                        }
+                        Command::Canary(peer_id, response) => {
+                            let nonce = format!("{:016x}", rand::random::<u64>());
+                            let request_id = swarm.behaviour_mut().canary.send_request(&peer_id, canary::CanaryRequest { nonce: nonce.clone() });
+                            canaries.sent(request_id, nonce, response);
+                        }
+                        Command::FetchOffer(offer_id, response) => {
+                            if let Some(offer) = recent_offers.as_mut().and_then(|recent| recent.get(&offer_id)) {
+                                response.send(Ok(offer)).ok();
+                                continue;
+                            }
+                            let query_id = swarm.behaviour_mut().kademlia.get_providers(kad::RecordKey::new(&offer_id));
+                            fetches.lookup(query_id, offer_id, response);
+                        }
+                        Command::WaitForMesh(response) => {
+                            if mesh_ready {
+                                response.send(swarm.behaviour().gossipsub.mesh_peers(&topic.hash()).count()).ok();
+                            } else {
+                                mesh_waiters.push(response);
+                            }
+                        }
+                        Command::Shutdown(drain, response) => {
+                            // Offers still in the channel are published, new ones are refused
+                            submission_receiver.close();
+                            draining = Some((time::Instant::now() + drain, response));
+                            drain_check_interval = Some(time::interval(Duration::from_millis(100)));
+                        }
+                    },
+                    _ = tick_optional(&mut relisten_interval) => {
+                        for listener in listeners.due() {
+                            match swarm.listen_on(listener.address.clone()) {
+                                Ok(listener_id) => {
+                                    if listener.internal {
+                                        internal_listeners.insert(listener_id);
+                                    }
+                                    event_tx.send(SplashEvent::ListenerRestored(listener.address.clone()));
+                                    listeners.restarted(listener_id, listener);
+                                }
+                                Err(e) => {
+                                    let address = listener.address.clone();
+                                    let retry_in = listeners.failed(listener);
+                                    warn!("Failed to listen on {} again: {}, retrying in {:?}", address, e, retry_in);
+                                }
+                            }
+                        }
+
+                        if !listeners.is_retrying() {
+                            relisten_interval = None;
+                        }
+                    },
+                    _ = tick_optional(&mut reconnect_interval) => {
+                        for (peer_id, address) in reconnect.due() {
+                            let opts = DialOpts::peer_id(peer_id).addresses(vec![address]).condition(PeerCondition::DisconnectedAndNotDialing).build();
+                            if let Err(e) = swarm.dial(opts) {
+                                log::debug!("Failed to redial known peer {}: {}", peer_id, e);
+                                if let Some(retry) = reconnect.lost(peer_id) {
+                                    event_tx.send(retry.into_event(peer_id));
+                                }
+                            }
+                        }
+                    },
+                    _ = tick_optional(&mut bootstrap_retry_interval) => {
+                        if swarm.connected_peers().next().is_none() {
+                            if let Some(refresh) = introducer_refresh.as_mut() {
+                                refresh.start();
+                            }
+                            for (peer_id, addr) in reconnect.known_peers() {
+                                let opts = DialOpts::peer_id(peer_id).addresses(vec![addr]).condition(PeerCondition::DisconnectedAndNotDialing).build();
+                                swarm.dial(opts).ok();
+                            }
+                            swarm.behaviour_mut().kademlia.bootstrap().ok();
+                        }
+                    },
+                    _ = tick_optional(&mut cluster_discovery_interval) => {
+                        if let Some(cluster) = cluster_discovery.as_mut() {
+                            cluster.start();
+                        }
+                    },
+                    Some(result) = cluster_rx.recv() => {
+                        let Some(cluster) = cluster_discovery.as_mut() else {
+                            continue;
+                        };
+
+                        match result {
+                            Ok(addresses) => {
+                                let listen_addresses: HashSet<Multiaddr> = swarm.listeners().cloned().collect();
+                                for address in cluster.to_dial(addresses, &listen_addresses, |peer_id| swarm.is_connected(peer_id)) {
+                                    swarm.dial(address).ok();
+                                }
+                            }
+                            Err(e) => {
+                                cluster.failed();
+                                warn!("Failed to resolve cluster peers: {}", e);
+                            }
+                        }
+                    },
+                    _ = tick_optional(&mut blocklist_interval) => {
+                        if let Some(blocklist) = blocklist.as_mut() {
+                            blocklist.start();
+                        }
+                    },
+                    Some(result) = blocklist_rx.recv() => {
+                        if let Some(blocklist) = blocklist.as_mut() {
+                            blocklist.finished();
+                        }
+
+                        match result {
+                            Ok((cidrs, peers)) => {
+                                let event = SplashEvent::BlocklistUpdated { cidrs: cidrs.len(), peers: peers.len() };
+                                swarm.behaviour_mut().gate.set_subscribed(cidrs, peers);
+
+                                let denied: Vec<PeerId> = swarm.connected_peers().filter(|peer_id| swarm.behaviour().gate.is_denied(peer_id)).copied().collect();
+                                for peer_id in denied {
+                                    swarm.disconnect_peer_id(peer_id).ok();
+                                }
+                                event_tx.send(event);
+                            }
+                            Err(e) => {
+                                event_tx.send(SplashEvent::BlocklistRefreshFailed(e));
+                            }
+                        }
+                    },
+                    _ = tick_optional(&mut introducer_refresh_interval) => {
+                        if let Some(refresh) = introducer_refresh.as_mut() {
+                            refresh.start();
+                        }
+                    },
+                    Some(result) = introducers_rx.recv() => {
+                        if let Some(refresh) = introducer_refresh.as_mut() {
+                            refresh.finished();
+                        }
+
+                        let introducers = match result {
+                            Ok(introducers) => introducers,
+                            Err(e) => {
+                                warn!("Failed to resolve peers from DNS again: {}", e);
+                                continue;
+                            }
+                        };
+
+                        // New introducers are dialed right away, known ones only if we have no peers left
+                        let isolated = swarm.connected_peers().next().is_none();
+                        for addr in introducers.into_iter().filter(|addr| family.allows(addr)) {
+                            let Some(Protocol::P2p(peer_id)) = addr.iter().last() else {
+                                continue;
+                            };
+
+                            swarm.behaviour_mut().kademlia.add_address(&peer_id, addr.clone());
+                            if reconnect.add(peer_id, addr.clone()) || isolated {
+                                let opts = DialOpts::peer_id(peer_id).addresses(vec![addr]).condition(PeerCondition::DisconnectedAndNotDialing).build();
+                                swarm.dial(opts).ok();
+                            }
+                        }
+                        reconnect_interval.get_or_insert_with(|| time::interval(reconnect::RECONNECT_CHECK_INTERVAL));
+                    },
+                    _ = tick_optional(&mut drain_check_interval) => {
+                        let drained = submissions_closed && pushes_in_flight.is_empty();
+                        if !drained && draining.as_ref().is_some_and(|(deadline, _)| time::Instant::now() < *deadline) {
+                            continue;
+                        }
+
+                        let mut unpublished_offers = 0;
+                        while submission_receiver.try_recv().is_ok() {
+                            unpublished_offers += 1;
+                        }
+
+                        let (mut queued_offers, persistent) = pending.as_ref().map_or((0, true), |pending| {
+                            let pending = pending.lock().expect("pending queue lock poisoned");
+                            (pending.len(), pending.is_persistent())
+                        });
+                        // Offers retried in memory are lost with the node, the submissions still in the channel are among them
+                        if !persistent {
+                            unpublished_offers = unpublished_offers.max(queued_offers);
+                            queued_offers = 0;
+                        }
+
+                        let summary = ShutdownSummary {
+                            unpublished_offers,
+                            unacknowledged_pushes: pushes_in_flight.len(),
+                            queued_offers,
+                        };
+
+                        if let Some((_, response)) = draining.take() {
+                            response.send(summary.clone()).ok();
+                        }
+                        event_tx.send(SplashEvent::ShutdownComplete(summary));
+                        break;
+                    },
+                    _ = dedup_stats_interval.tick() => {
+                        inbound_pipeline.prune();
+                        event_tx.send(SplashEvent::DedupCacheStats(inbound_pipeline.seen.stats()));
+                        event_tx.send(SplashEvent::DhtStats(dht::stats(&mut swarm.behaviour_mut().kademlia)));
+                    },
+                    _ = rate_limit_report_interval.tick() => {
+                        for (peer_id, dropped) in inbound_limiter.take_dropped() {
+                            event_tx.send(SplashEvent::OffersRateLimited { peer_id, dropped });
+                        }
+                    },
+                    _ = tick_optional(&mut alert_check_interval) => {
+                        for event in alerts.check(swarm.connected_peers().count()) {
+                            event_tx.send(event);
+                        }
+                    },
+                    _ = tick_optional(&mut status_interval) => {
+                        let summary = status_reporter.summary(
+                            swarm.connected_peers().count(),
+                            swarm.behaviour().gossipsub.mesh_peers(&topic.hash()).count(),
+                            submission_receiver.len(),
+                            inbound_pipeline.seen.stats().entries,
+                        );
+                        event_tx.send(SplashEvent::Status(summary));
+                    },
+                    _ = dht_cleanup_interval.tick() => {
+                        let routing_peers = dht::routing_peers(&mut swarm.behaviour_mut().kademlia);
+                        for peer_id in stale_peers.expired(routing_peers, |peer_id| swarm.is_connected(peer_id)) {
+                            log::debug!("Removing stale peer {} from routing table", peer_id);
+                            swarm.behaviour_mut().kademlia.remove_peer(&peer_id);
+                        }
+                        if let Some(recent) = recent_offers.as_mut() {
+                            recent.expire_provided(&mut swarm.behaviour_mut().kademlia);
+                        }
+                    },
+                    _ = tick_optional(&mut connection_check_interval) => {
+                        let topic_hash = topic.hash();
+                        let gossipsub = &swarm.behaviour().gossipsub;
+                        let mesh: HashSet<PeerId> = gossipsub.mesh_peers(&topic_hash).copied().collect();
+                        let candidates: Vec<connections::Candidate> = gossipsub
+                            .all_peers()
+                            .filter(|(_, topics)| topics.contains(&&topic_hash))
+                            .map(|(peer_id, _)| connections::Candidate { peer_id: *peer_id, in_mesh: mesh.contains(peer_id), score: gossipsub.peer_score(peer_id) })
+                            .collect();
+                        let peers = candidates.len();
+
+                        for peer_id in connection_manager.to_prune(candidates) {
+                            log::debug!("Pruning connection to {}, above the maximum peer count", peer_id);
+                            swarm.disconnect_peer_id(peer_id).ok();
+                        }
+
+                        let routing_peers = dht::routing_peers(&mut swarm.behaviour_mut().kademlia);
+                        for peer_id in connection_manager.to_dial(peers, routing_peers, |peer_id| swarm.is_connected(peer_id)) {
+                            swarm.dial(DialOpts::peer_id(peer_id).condition(PeerCondition::DisconnectedAndNotDialing).build()).ok();
+                        }
+                    },
+                    _ = mesh_interval.tick() => {
+                        let events: Vec<SplashEvent> = {
+                            let topic_hash = topic.hash();
+                            let gossipsub = &swarm.behaviour().gossipsub;
+                            let (added, removed) = mesh_tracker.update(gossipsub.mesh_peers(&topic_hash).copied());
+
+                            let mut events: Vec<SplashEvent> = added.into_iter().map(SplashEvent::MeshPeerAdded).collect();
+
+                            for peer_id in removed {
+                                let subscribed = gossipsub.all_peers().any(|(peer, topics)| *peer == peer_id && topics.contains(&&topic_hash));
+                                let reason = if !swarm.is_connected(&peer_id) {
+                                    MeshRemovalReason::Disconnected
+                                } else if !subscribed {
+                                    MeshRemovalReason::Unsubscribed
+                                } else {
+                                    MeshRemovalReason::Pruned
+                                };
+
+                                events.push(SplashEvent::MeshPeerRemoved { peer_id, reason, score: gossipsub.peer_score(&peer_id) });
+                            }
+
+                            let mesh_peers = gossipsub.mesh_peers(&topic_hash).count();
+                            if !mesh_ready && mesh_peers >= mesh_ready_peers {
+                                mesh_ready = true;
+                                for waiter in mesh_waiters.drain(..) {
+                                    waiter.send(mesh_peers).ok();
+                                }
+                                events.push(SplashEvent::MeshReady(mesh_peers));
+                            }
+
+                            events
+                        };
+
+                        for event in events {
+                            event_tx.send(event);
+                        }
+                    },
+                    _ = tick_optional(&mut auth_check_interval) => {
+                        if let Some(authenticator) = authenticator.as_mut() {
+                            for peer_id in authenticator.expired() {
+                                warn!("Peer {} did not authenticate in time", peer_id);
+                                swarm.disconnect_peer_id(peer_id).ok();
+                                event_tx.send(SplashEvent::PeerAuthenticationFailed(peer_id));
+                            }
+                        }
+                    },
+                    _ = tick_optional(&mut chaos_disconnect_interval) => {
+                        if let Some(peer_id) = chaos::random_peer(swarm.connected_peers().copied()) {
+                            warn!("Chaos: disconnecting peer {}", peer_id);
+                            swarm.disconnect_peer_id(peer_id).ok();
+                        }
+                    },
+                    event = swarm.select_next_some() => match event {
+                        SwarmEvent::ConnectionEstablished { peer_id, connection_id, endpoint, num_established, .. } => {
+                            dial_back.connection_established(connection_id, peer_id, endpoint.get_remote_address().clone());
+                            connection_book.established(connection_id, peer_id, endpoint.clone());
+                            reconnect.connected(&peer_id);
+                            if let Some(cluster) = cluster_discovery.as_mut().filter(|_| endpoint.is_dialer()) {
+                                cluster.connected(endpoint.get_remote_address(), peer_id);
+                            }
+
+                            // A connection we opened to answer a dial-back request, it served its purpose
+                            if let Some(channel) = dial_back.dialed(connection_id) {
+                                swarm.behaviour_mut().dial_back.send_response(channel, dialback::DialBackResponse { reachable: true }).ok();
+                                swarm.close_connection(connection_id);
+                            }
+
+                            if let Some(authenticator) = authenticator.as_mut() {
+                                if num_established.get() == 1 && !authenticator.is_authenticated(&peer_id) {
+                                    let challenge = authenticator.challenge(peer_id);
+                                    if let Some(auth) = swarm.behaviour_mut().auth.as_mut() {
+                                        auth.send_request(&peer_id, challenge);
+                                    }
+                                }
+                            }
+                            event_tx.send(SplashEvent::PeerConnected(peer_id));
+                        },
+                        SwarmEvent::ConnectionClosed { peer_id, connection_id, num_established, .. } => {
+                            dial_back.connection_closed(connection_id);
+                            connection_book.closed(connection_id, peer_id, num_established);
+
+                            if num_established == 0 {
+                                stale_peers.seen(peer_id);
+                                inbound_limiter.disconnected(&peer_id);
+                                canaries.disconnected(&peer_id);
+                                negotiated_security.lock().expect("negotiated security lock poisoned").remove(&peer_id);
+
+                                if let Some(authenticator) = authenticator.as_mut() {
+                                    authenticator.forget(&peer_id);
+                                }
+
+                                if let Some(next) = discovery.on_peer_lost(swarm.connected_peers().count()) {
+                                    peer_discovery.as_mut().reset(time::Instant::now() + next);
+                                }
+
+                                if let Some(refresh) = introducer_refresh.as_mut().filter(|_| swarm.connected_peers().next().is_none()) {
+                                    refresh.on_peers_lost();
+                                }
+                            }
+                            event_tx.send(SplashEvent::PeerDisconnected(peer_id));
+
+                            if num_established == 0 && draining.is_none() {
+                                if let Some(retry) = reconnect.lost(peer_id) {
+                                    event_tx.send(retry.into_event(peer_id));
+                                }
+                            }
+                        },
+                        SwarmEvent::OutgoingConnectionError { connection_id, peer_id, .. } => {
+                            if let Some(channel) = dial_back.dialed(connection_id) {
+                                swarm.behaviour_mut().dial_back.send_response(channel, dialback::DialBackResponse { reachable: false }).ok();
+                            }
+
+                            if let Some(peer_id) = peer_id.filter(|peer_id| !swarm.is_connected(peer_id)) {
+                                if let Some(retry) = reconnect.lost(peer_id) {
+                                    event_tx.send(retry.into_event(peer_id));
+                                }
+                            }
+                        },
+                        SwarmEvent::NewExternalAddrCandidate { address } => {
+                            // Ask a random peer to dial the address observed for us before advertising it
+                            let advertised = private_addresses || addresses::is_global(&address);
+                            if !tor && !explicit_external && advertised && dial_back.is_new_candidate(&address) {
+                                if let Some(peer_id) = swarm.connected_peers().copied().choose(&mut rand::thread_rng()) {
+                                    let request_id = swarm.behaviour_mut().dial_back.send_request(&peer_id, dialback::DialBackRequest { address: address.clone() });
+                                    dial_back.requested(request_id, address);
+                                }
+                            }
+                        },
+                        SwarmEvent::Behaviour(SplashBehaviourEvent::DialBack(event)) => match event {
+                            request_response::Event::Message { peer, message: request_response::Message::Request { request, channel, .. }, .. } => {
+                                if !dial_back.may_dial(&peer, &request.address) {
+                                    swarm.behaviour_mut().dial_back.send_response(channel, dialback::DialBackResponse { reachable: false }).ok();
+                                    continue;
+                                }
+
+                                let opts = DialOpts::peer_id(peer).addresses(vec![request.address]).condition(PeerCondition::Always).build();
+                                let dial_id = opts.connection_id();
+                                if swarm.dial(opts).is_ok() {
+                                    dial_back.dialing(dial_id, channel);
+                                } else {
+                                    swarm.behaviour_mut().dial_back.send_response(channel, dialback::DialBackResponse { reachable: false }).ok();
+                                }
+                            },
+                            request_response::Event::Message { peer, message: request_response::Message::Response { request_id, response }, .. } => {
+                                if let Some(address) = dial_back.answered(request_id) {
+                                    if response.reachable {
+                                        swarm.add_external_address(address.clone());
+                                        event_tx.send(SplashEvent::AddressConfirmed { address, peer_id: peer });
+                                    } else {
+                                        event_tx.send(SplashEvent::AddressUnreachable { address, peer_id: peer });
+                                    }
+                                }
+                            },
+                            request_response::Event::OutboundFailure { request_id, .. } => {
+                                dial_back.failed(request_id);
+                            },
+                            _ => {}
+                        },
+                        SwarmEvent::Behaviour(SplashBehaviourEvent::Canary(event)) => match event {
+                            // Canaries over the rate limit or from unauthenticated peers go unanswered
+                            request_response::Event::Message { peer, message: request_response::Message::Request { request, channel, .. }, .. } => {
+                                let authenticated = authenticator.as_ref().map_or(true, |a| a.is_authenticated(&peer));
+                                if canary_responder && authenticated && canaries.may_answer(peer) {
+                                    swarm.behaviour_mut().canary.send_response(channel, canary::CanaryResponse { nonce: request.nonce }).ok();
+                                }
+                            },
+                            request_response::Event::Message { message: request_response::Message::Response { request_id, response }, .. } => {
+                                canaries.answered(request_id, &response.nonce);
+                            },
+                            request_response::Event::OutboundFailure { request_id, error, .. } => {
+                                canaries.failed(request_id, error.to_string());
+                            },
+                            _ => {}
+                        },
+                        SwarmEvent::Behaviour(SplashBehaviourEvent::Sync(event)) => match event {
+                            // Backfills over the rate limit or requests from unauthenticated peers get no offers
+                            request_response::Event::Message { peer, message: request_response::Message::Request { request, channel, .. }, .. } => {
+                                let authenticated = authenticator.as_ref().map_or(true, |a| a.is_authenticated(&peer));
+                                let offers = match (recent_offers.as_mut(), request.offer_id) {
+                                    (Some(recent), Some(offer_id)) if authenticated => recent.get(&offer_id).into_iter().collect(),
+                                    (Some(recent), None) if authenticated && recent.may_answer(peer) => recent.since(Duration::from_secs(request.since_secs)),
+                                    _ => Vec::new(),
+                                };
+                                swarm.behaviour_mut().sync.send_response(channel, sync::SyncResponse { offers }).ok();
+                            },
+                            // Backfilled offers are old news to the mesh, so they're only delivered, not republished
+                            request_response::Event::Message { peer, message: request_response::Message::Response { request_id, response }, .. } => {
+                                if fetches.answered(request_id, &response.offers) {
+                                    continue;
+                                }
+
+                                let mut backfilled = 0;
+                                for offer in response.offers {
+                                    if Splash::validate_offer(&offer).is_err() {
+                                        warn!("Received invalid backfilled offer from {}", peer);
+                                        continue;
+                                    }
+                                    if !inbound_pipeline.seen.insert(seen_key(offer.as_bytes())) {
+                                        continue;
+                                    }
+
+                                    backfilled += 1;
+                                    if !inbound_pipeline.has_middleware() {
+                                        alerts.offer_received();
+                                        status_reporter.offer_received();
+                                        if let Some(recent) = recent_offers.as_mut() {
+                                            recent.insert(&mut swarm.behaviour_mut().kademlia, offer.clone());
+                                        }
+                                        event_tx.send(SplashEvent::OfferReceived(offer, OfferMetadata::default()));
+                                    } else {
+                                        let offer = InboundOffer { offer, metadata: OfferMetadata::default(), propagation_source: peer, annotations: BTreeMap::new() };
+                                        middleware::spawn_inbound(inbound_pipeline.filter(offer), None, filtered_tx.clone());
+                                    }
+                                }
+                                log::info!("Backfilled {} offers from {}", backfilled, peer);
+                            },
+                            request_response::Event::OutboundFailure { peer, request_id, error, .. } => {
+                                if !fetches.failed(request_id, error.to_string()) {
+                                    warn!("Failed to backfill offers from {}: {}", peer, error);
+                                }
+                            },
+                            _ => {}
+                        },
+                        SwarmEvent::Behaviour(SplashBehaviourEvent::Push(event)) => match event {
+                            request_response::Event::Message { peer, message: request_response::Message::Request { request, channel, .. }, .. } => {
+                                let authenticated = authenticator.as_ref().map_or(true, |a| a.is_authenticated(&peer));
+                                let accepted = authenticated && Splash::validate_offer(&request.offer).is_ok();
+                                swarm.behaviour_mut().push.send_response(channel, push::PushAck { accepted }).ok();
+
+                                if let (true, Some(log)) = (accepted, propagation_log.as_mut()) {
+                                    log.delivered(request.offer.as_bytes(), peer);
+                                }
+
+                                if !accepted {
+                                    warn!("Received invalid pushed offer from {}", peer);
+                                } else if inbound_pipeline.seen.insert(seen_key(request.offer.as_bytes())) {
+                                    // Pushed offers enter the mesh through us
+                                    offer_topics.publish(&mut swarm.behaviour_mut().gossipsub, request.offer.as_bytes(), &OfferMetadata::default()).ok();
+                                    if !inbound_pipeline.has_middleware() {
+                                        alerts.offer_received();
+                                        status_reporter.offer_received();
+                                        if let Some(recent) = recent_offers.as_mut() {
+                                            recent.insert(&mut swarm.behaviour_mut().kademlia, request.offer.clone());
+                                        }
+                                        event_tx.send(SplashEvent::OfferReceived(request.offer, OfferMetadata::default()));
+                                    } else {
+                                        let offer = InboundOffer { offer: request.offer, metadata: OfferMetadata::default(), propagation_source: peer, annotations: BTreeMap::new() };
+                                        middleware::spawn_inbound(inbound_pipeline.filter(offer), None, filtered_tx.clone());
+                                    }
+                                }
+                            },
+                            request_response::Event::Message { peer, message: request_response::Message::Response { request_id, response }, .. } => {
+                                pushes_in_flight.remove(&request_id);
+                                if !response.accepted {
+                                    warn!("Trusted relay {} rejected pushed offer", peer);
+                                }
+                            },
+                            request_response::Event::OutboundFailure { peer, request_id, error, .. } => {
+                                pushes_in_flight.remove(&request_id);
+                                warn!("Failed to push offer to trusted relay {}: {}", peer, error);
+                            },
+                            _ => {}
+                        },
+                        SwarmEvent::Behaviour(SplashBehaviourEvent::Auth(event)) => {
+                            let Some(authenticator) = authenticator.as_mut() else {
+                                continue;
+                            };
+
+                            match event {
+                                request_response::Event::Message { peer, message: request_response::Message::Request { request, channel, .. }, .. } => {
+                                    let proof = authenticator.prove(&local_peer_id, &request);
+                                    if let Some(auth) = swarm.behaviour_mut().auth.as_mut() {
+                                        auth.send_response(channel, proof).ok();
+                                    }
+                                    log::debug!("Answered auth challenge from {}", peer);
+                                },
+                                request_response::Event::Message { peer, message: request_response::Message::Response { response, .. }, .. } => {
+                                    if authenticator.verify(peer, &response) {
+                                        event_tx.send(SplashEvent::PeerAuthenticated(peer));
+                                    } else {
+                                        warn!("Peer {} failed to authenticate", peer);
+                                        swarm.disconnect_peer_id(peer).ok();
+                                        event_tx.send(SplashEvent::PeerAuthenticationFailed(peer));
+                                    }
+                                },
+                                request_response::Event::OutboundFailure { peer, error, .. } => {
+                                    warn!("Peer {} failed to authenticate: {}", peer, error);
+                                    authenticator.forget(&peer);
+                                    swarm.disconnect_peer_id(peer).ok();
+                                    event_tx.send(SplashEvent::PeerAuthenticationFailed(peer));
+                                },
+                                _ => {}
+                            }
+                        },
+                        SwarmEvent::Behaviour(SplashBehaviourEvent::Gossipsub(gossipsub::Event::Message {
+                            propagation_source,
+                            message_id,
+                            message,
+                        })) => {
+                            // Sending only fails without subscribers
+                            raw_messages.send(RawMessage {
+                                message_id: message_id.clone(),
+                                propagation_source,
+                                source: message.source,
+                                sequence_number: message.sequence_number,
+                                data: message.data.clone(),
+                                received_at: std::time::SystemTime::now(),
+                            }).ok();
+
+                            // Gossip from peers that haven't proven the shared token is neither delivered nor forwarded
+                            if authenticator.as_ref().is_some_and(|a| !a.is_authenticated(&propagation_source)) {
+                                swarm.behaviour_mut().gossipsub.report_message_validation_result(&message_id, &propagation_source, MessageAcceptance::Ignore).ok();
+                                continue;
+                            }
+
+                            if chaos.drop_incoming() {
+                                swarm.behaviour_mut().gossipsub.report_message_validation_result(&message_id, &propagation_source, MessageAcceptance::Ignore).ok();
+                                continue;
+                            }
+
+                            // Rejecting counts as an invalid message, so flooding peers end up graylisted
+                            if !inbound_limiter.allow(propagation_source) {
+                                swarm.behaviour_mut().gossipsub.report_message_validation_result(&message_id, &propagation_source, MessageAcceptance::Reject).ok();
+                                continue;
+                            }
+
+                            if message.topic == control_topic.hash() || message.topic == status_topic.hash() {
+                                let acceptance = match control::ControlMessage::parse(&message.data, message.topic == status_topic.hash()) {
+                                    Ok(control) => {
+                                        event_tx.send(control.into_event());
+                                        MessageAcceptance::Accept
+                                    }
+                                    Err(e) => {
+                                        warn!("Received invalid control message: {}", e);
+                                        MessageAcceptance::Reject
+                                    }
+                                };
+                                swarm.behaviour_mut().gossipsub.report_message_validation_result(&message_id, &propagation_source, acceptance).ok();
+                                continue;
+                            }
+
+                            let inbound = inbound_pipeline.process(&message.topic, message.data);
+                            if let Some(offer) = inbound.offer() {
+                                if let Some(log) = propagation_log.as_mut() {
+                                    log.delivered(offer.as_bytes(), propagation_source);
+                                }
+                                event_tx.send(SplashEvent::OfferArrived { topic: message.topic.to_string(), duplicate: matches!(inbound, Inbound::Duplicate(..)) });
+                            }
+
+                            match inbound {
+                                Inbound::Dropped(acceptance) | Inbound::Duplicate(_, acceptance) => {
+                                    swarm.behaviour_mut().gossipsub.report_message_validation_result(&message_id, &propagation_source, acceptance).ok();
+                                }
+                                Inbound::New(offer, metadata) => {
+                                    alerts.offer_received();
+                                    status_reporter.offer_received();
+                                    if let Some(recent) = recent_offers.as_mut() {
+                                        recent.insert(&mut swarm.behaviour_mut().kademlia, offer.clone());
+                                    }
+                                    event_tx.send(SplashEvent::OfferReceived(offer, metadata));
+                                    swarm.behaviour_mut().gossipsub.report_message_validation_result(&message_id, &propagation_source, MessageAcceptance::Accept).ok();
+                                }
+                                Inbound::Filter(offer, metadata) => {
+                                    // Validated once the chain is done, so dropped offers aren't forwarded either
+                                    let offer = InboundOffer { offer, metadata, propagation_source, annotations: BTreeMap::new() };
+                                    middleware::spawn_inbound(inbound_pipeline.filter(offer), Some((message_id, propagation_source)), filtered_tx.clone());
+                                }
+                            }
+                        },
+                        SwarmEvent::Behaviour(SplashBehaviourEvent::Kademlia(kad::Event::OutboundQueryProgressed { id, result: kad::QueryResult::GetProviders(result), step, .. })) => {
+                            if let Ok(kad::GetProvidersOk::FoundProviders { providers, .. }) = result {
+                                let provider = providers.into_iter().find(|provider| *provider != local_peer_id);
+                                if let (Some(provider), Some(request)) = (provider, fetches.request(id)) {
+                                    let request_id = swarm.behaviour_mut().sync.send_request(&provider, request);
+                                    fetches.sent(id, request_id);
+                                    if let Some(mut query) = swarm.behaviour_mut().kademlia.query_mut(&id) {
+                                        query.finish();
+                                    }
+                                }
+                            }
+                            if step.last {
+                                fetches.not_found(id);
+                            }
+                        },
+                        SwarmEvent::Behaviour(SplashBehaviourEvent::Kademlia(kad::Event::RoutingUpdated { peer, addresses: peer_addresses, .. })) => {
+                            // Addresses also reach the routing table from connections and other peers' responses
+                            if !private_addresses {
+                                for addr in peer_addresses.iter().filter(|addr| !addresses::is_global(addr)) {
+                                    swarm.behaviour_mut().kademlia.remove_address(&peer, addr);
+                                }
+                            }
+                        },
+                        SwarmEvent::Behaviour(SplashBehaviourEvent::Identify(identify::Event::Received { info: identify::Info { observed_addr, listen_addrs, agent_version, protocols, .. }, peer_id, connection_id: _ })) => {
+                            connection_book.identified(peer_id, agent_version.clone(), protocols);
+                            let capabilities = capabilities::parse(&agent_version);
+                            let sync_responder = capabilities.iter().any(|capability| capability == capabilities::SYNC);
+                            if let Some(request) = backfill.as_mut().and_then(|backfill| backfill.request(peer_id, sync_responder)) {
+                                swarm.behaviour_mut().sync.send_request(&peer_id, request);
+                            }
+                            event_tx.send(SplashEvent::PeerIdentified { peer_id, agent_version, capabilities });
+
+                            for addr in listen_addrs {
+                                // If the node is advertising a non-global address, ignore it
+                                if (!private_addresses && !addresses::is_global(&addr)) || !family.allows(&addr) {
+                                    continue;
+                                }
+
+                                swarm.behaviour_mut().kademlia.add_address(&peer_id, addr);
+                            }
+                            // The address observed by the peer is only a candidate, AutoNAT confirms it with dial-backs
+                            log::debug!("Peer {} observed us at {}", peer_id, observed_addr);
+                        },
+                        SwarmEvent::Behaviour(SplashBehaviourEvent::Autonat(autonat::Event::StatusChanged { new, .. })) => {
+                            event_tx.send(SplashEvent::NatStatusChanged(new));
+                        },
+                        SwarmEvent::Behaviour(SplashBehaviourEvent::RelayClient(relay::client::Event::ReservationReqAccepted { relay_peer_id, .. })) => {
+                            log::info!("Reserved circuit on relay {}", relay_peer_id);
+                        },
+                        SwarmEvent::Behaviour(SplashBehaviourEvent::Dcutr(dcutr::Event { remote_peer_id, result })) => match result {
+                            Ok(_) => log::info!("Upgraded relayed connection to {} to a direct one", remote_peer_id),
+                            Err(e) => log::debug!("Hole punching to {} failed: {}", remote_peer_id, e),
+                        },
+                        SwarmEvent::Behaviour(SplashBehaviourEvent::Upnp(event)) => match event {
+                            upnp::Event::NewExternalAddr(address) => {
+                                event_tx.send(SplashEvent::PortMapped(address));
+                            },
+                            upnp::Event::ExpiredExternalAddr(address) => {
+                                event_tx.send(SplashEvent::PortMappingExpired(address));
+                            },
+                            upnp::Event::GatewayNotFound => log::debug!("No UPnP gateway found"),
+                            upnp::Event::NonRoutableGateway => log::debug!("UPnP gateway is not exposed to the public network"),
+                        },
+                        SwarmEvent::IncomingConnectionError { send_back_addr, error, .. } => {
+                            if transport::is_handshake_timeout(&error) {
+                                log::debug!("Handshake with {} timed out", send_back_addr);
+                                event_tx.send(SplashEvent::HandshakeTimeout(send_back_addr));
+                            }
+                        },
+                        SwarmEvent::NewListenAddr { listener_id, address } => {
+                            // Listen addresses are hidden from identify, announce the public ones explicitly. Mapped ones are
+                            // announced as the address they are reached on
+                            let mapped = addresses::translate(&advertise_map, &address);
+                            let advertised = mapped.is_some() || private_addresses || addresses::is_global(&address);
+                            if !tor && !explicit_external && advertised && !internal_listeners.contains(&listener_id) {
+                                let external = mapped.unwrap_or_else(|| address.clone());
+                                swarm.add_external_address(external.clone());
+                                listen_externals.insert(address.clone(), external);
+                            }
+
+                            // A new interface address, e.g. after joining a Wi-Fi or VPN
+                            dial_back.forget_candidates();
+                            listeners.retry_now();
+                            event_tx.send(SplashEvent::NewListenAddress(address));
+                        },
+                        SwarmEvent::ExternalAddrConfirmed { address } => {
+                            event_tx.send(SplashEvent::ExternalAddressConfirmed(address));
+                        },
+                        SwarmEvent::ExpiredListenAddr { address, .. } => {
+                            // The interface went away, addresses peers observed for us through it are likely stale too.
+                            // They are dropped and confirmed again as peers report what they see now
+                            log::info!("Listen address {} expired, refreshing external addresses", address);
+                            // A mapped address stays while other interfaces of its listener still map to it
+                            listen_externals.remove(&address);
+
+                            let stale: Vec<Multiaddr> = swarm
+                                .external_addresses()
+                                .filter(|external| !configured_externals.contains(*external) && !listen_externals.values().any(|listen_external| listen_external == *external))
+                                .cloned()
+                                .collect();
+                            for external in stale {
+                                swarm.remove_external_address(&external);
+                            }
+
+                            dial_back.forget_candidates();
+                            listeners.retry_now();
+                            event_tx.send(SplashEvent::ListenAddressExpired(address));
+                        },
+                        SwarmEvent::ListenerClosed { listener_id, reason, .. } => {
+                            internal_listeners.remove(&listener_id);
+                            if let Some((address, retry_in)) = listeners.closed(listener_id) {
+                                warn!("Listener on {} closed ({:?}), retrying in {:?}", address, reason.err(), retry_in);
+                                relisten_interval.get_or_insert_with(|| time::interval(listeners::RELISTEN_CHECK_INTERVAL));
+                                event_tx.send(SplashEvent::ListenerClosed { address, retry_in });
+                            }
+                        },
+                        SwarmEvent::ListenerError { listener_id, error } => {
+                            // Not fatal, listeners that can't continue are closed right after
+                            warn!("Listener {:?} error: {}", listener_id, error);
+                        },
+                        _ => {}
+                    }
+                }
+            }
+        });
+
+        Ok(())
+    }
+}
+
+// Tick an optional interval, pending forever if there is none
+async fn tick_optional(interval: &mut Option<time::Interval>) {
+    match interval {
+        Some(interval) => {
+            interval.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+// Publishes a submitted or retried offer and reports the outcome. Offers that failed for lack of peers
+// stay in the pending queue for another attempt, so only the other outcomes are final
+fn publish_submission(
+    swarm: &mut Swarm<SplashBehaviour>,
+    offer_topics: &topics::OfferTopics,
+    pending: &Option<Arc<Mutex<queue::PendingQueue>>>,
+    recent_offers: &mut Option<sync::RecentOffers>,
+    event_tx: &mut events::EventSender,
+    submission: Submission,
+) {
+    let Submission {
+        offer,
+        metadata,
+        receipt,
+        ..
+    } = submission;
+    let result = offer_topics.publish(&mut swarm.behaviour_mut().gossipsub, &offer, &metadata);
+    let retrying = settle_pending(pending, &offer, &result);
+
+    match result {
+        Ok(message_id) => {
+            if let Some(recent) = recent_offers.as_mut() {
+                recent.insert(
+                    &mut swarm.behaviour_mut().kademlia,
+                    String::from_utf8_lossy(&offer).into_owned(),
+                );
+            }
+            let mesh_peers = swarm
+                .behaviour()
+                .gossipsub
+                .mesh_peers(&offer_topics.primary().hash())
+                .count();
+            if let Some(receipt) = receipt {
+                receipt
+                    .send(Ok(PublishReceipt {
+                        message_id,
+                        mesh_peers,
+                    }))
+                    .ok();
+            }
+            event_tx.send(SplashEvent::OfferBroadcasted(
+                String::from_utf8_lossy(&offer).to_string(),
+            ));
+        }
+        Err(_) if retrying => {
+            if let Some(receipt) = receipt {
+                receipt.send(Err(SplashError::Retrying)).ok();
+            }
+        }
+        Err(e) => {
+            if let Some(receipt) = receipt {
+                receipt
+                    .send(Err(SplashError::PublishFailed(e.to_string())))
+                    .ok();
+            }
+            event_tx.send(SplashEvent::OfferBroadcastFailed(e));
+        }
+    }
+}
+
+// Drop an offer from the pending queue unless publishing it failed for lack of peers, returns whether
+// it's kept for a retry
+fn settle_pending(
+    pending: &Option<Arc<Mutex<queue::PendingQueue>>>,
+    offer: &[u8],
+    result: &Result<gossipsub::MessageId, gossipsub::PublishError>,
+) -> bool {
+    if pending.is_some() && matches!(result, Err(gossipsub::PublishError::InsufficientPeers)) {
+        return true;
+    }
+    remove_pending(pending, offer);
+    false
+}
+
+fn remove_pending(pending: &Option<Arc<Mutex<queue::PendingQueue>>>, offer: &[u8]) {
+    let Some(pending) = pending.as_ref() else {
+        return;
+    };
+
+    let offer = String::from_utf8_lossy(offer);
+    if let Err(e) = pending
+        .lock()
+        .expect("pending queue lock poisoned")
+        .remove(&offer)
+    {
+        warn!("Failed to update pending submission queue: {}", e);
+    }
+}
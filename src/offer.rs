@@ -0,0 +1,69 @@
+use crate::SplashError;
+use bech32::{FromBase32, ToBase32, Variant};
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use std::io::{Read, Write};
+
+// Version 0 of Chia's offer compression uses an empty puzzle dictionary, i.e. plain zlib
+const COMPRESSION_VERSION: u16 = 0;
+// Bounds the decompressed size, so a small offer can't expand without limit
+const MAX_SPEND_BUNDLE_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Encode a serialized SpendBundle as an `offer1...` string
+pub fn encode_offer(spend_bundle: &[u8]) -> Result<String, SplashError> {
+    let mut encoder = ZlibEncoder::new(
+        COMPRESSION_VERSION.to_be_bytes().to_vec(),
+        Compression::best(),
+    );
+    encoder
+        .write_all(spend_bundle)
+        .map_err(|_| SplashError::InvalidOfferFormat)?;
+    let compressed = encoder
+        .finish()
+        .map_err(|_| SplashError::InvalidOfferFormat)?;
+
+    bech32::encode("offer", compressed.to_base32(), Variant::Bech32m)
+        .map_err(|_| SplashError::InvalidOfferFormat)
+}
+
+/// Decode an `offer1...` string back into its serialized SpendBundle, the inverse of `encode_offer`.
+/// Only version 0 of the offer compression is supported, offers using a puzzle dictionary are refused
+pub fn decode_offer(offer: &str) -> Result<Vec<u8>, SplashError> {
+    let (hrp, data, variant) =
+        bech32::decode(offer).map_err(|_| SplashError::InvalidOfferFormat)?;
+    if hrp != "offer" || variant != Variant::Bech32m {
+        return Err(SplashError::InvalidOfferFormat);
+    }
+
+    let bytes = Vec::<u8>::from_base32(&data).map_err(|_| SplashError::InvalidOfferFormat)?;
+    let compressed = bytes
+        .strip_prefix(&COMPRESSION_VERSION.to_be_bytes())
+        .ok_or(SplashError::InvalidOfferFormat)?;
+
+    let mut spend_bundle = Vec::new();
+    ZlibDecoder::new(compressed)
+        .take(MAX_SPEND_BUNDLE_SIZE)
+        .read_to_end(&mut spend_bundle)
+        .map_err(|_| SplashError::InvalidOfferFormat)?;
+    Ok(spend_bundle)
+}
+
+// A bech32m string with the `offer` prefix and at least the 2 byte compression version as data, offers
+// failing this are rejected and not forwarded to the mesh
+pub(crate) fn is_bech32_offer(offer: &str) -> bool {
+    match bech32::decode(offer) {
+        Ok((hrp, data, Variant::Bech32m)) => {
+            hrp == "offer" && data.len() * 5 / 8 >= COMPRESSION_VERSION.to_be_bytes().len()
+        }
+        _ => false,
+    }
+}
+
+/// Accept the contents of an offer file, either the `offer1...` text or a serialized SpendBundle
+pub fn offer_from_bytes(bytes: &[u8]) -> Result<String, SplashError> {
+    match std::str::from_utf8(bytes).map(str::trim) {
+        Ok(text) if text.starts_with("offer1") => Ok(text.to_string()),
+        _ => encode_offer(bytes),
+    }
+}
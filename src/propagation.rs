@@ -0,0 +1,86 @@
+use libp2p::PeerId;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::SystemTime;
+
+/// How an offer reached this node, for studying propagation across the network
+#[derive(Clone, Debug)]
+pub struct PropagationRecord {
+    /// SHA-256 of the offer string, hex encoded, comparable between nodes
+    pub offer_sha256: String,
+    pub first_seen: SystemTime,
+    pub first_peer: PeerId,
+    /// Deliveries after the first one. Gossipsub drops duplicates within its duplicate cache time
+    /// before we see them, so these are mostly late re-broadcasts and direct pushes
+    pub duplicates: u64,
+    /// Distinct peers that delivered the offer, including the first one
+    pub peers: usize,
+}
+
+struct Entry {
+    first_seen: SystemTime,
+    first_peer: PeerId,
+    duplicates: u64,
+    peers: HashSet<PeerId>,
+}
+
+// Deliveries of the most recent offers, the oldest are evicted once `capacity` offers are tracked
+pub(crate) struct PropagationLog {
+    capacity: usize,
+    entries: HashMap<[u8; 32], Entry>,
+    order: VecDeque<[u8; 32]>,
+}
+
+impl PropagationLog {
+    pub(crate) fn new(capacity: usize) -> Self {
+        PropagationLog {
+            capacity: capacity.max(1),
+            entries: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    pub(crate) fn delivered(&mut self, offer: &[u8], peer_id: PeerId) {
+        let hash: [u8; 32] = Sha256::digest(offer).into();
+
+        if let Some(entry) = self.entries.get_mut(&hash) {
+            entry.duplicates += 1;
+            entry.peers.insert(peer_id);
+            return;
+        }
+
+        if self.order.len() >= self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.entries.remove(&oldest);
+            }
+        }
+
+        self.order.push_back(hash);
+        self.entries.insert(
+            hash,
+            Entry {
+                first_seen: SystemTime::now(),
+                first_peer: peer_id,
+                duplicates: 0,
+                peers: HashSet::from([peer_id]),
+            },
+        );
+    }
+
+    // Records in the order the offers were first seen
+    pub(crate) fn snapshot(&self) -> Vec<PropagationRecord> {
+        self.order
+            .iter()
+            .filter_map(|hash| {
+                let entry = self.entries.get(hash)?;
+                Some(PropagationRecord {
+                    offer_sha256: hash.iter().map(|b| format!("{:02x}", b)).collect(),
+                    first_seen: entry.first_seen,
+                    first_peer: entry.first_peer,
+                    duplicates: entry.duplicates,
+                    peers: entry.peers.len(),
+                })
+            })
+            .collect()
+    }
+}
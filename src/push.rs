@@ -0,0 +1,28 @@
+use libp2p::request_response::{self, ProtocolSupport};
+use libp2p::StreamProtocol;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PushOffer {
+    pub(crate) offer: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PushAck {
+    pub(crate) accepted: bool,
+}
+
+pub(crate) type Behaviour = request_response::json::Behaviour<PushOffer, PushAck>;
+
+// Every node accepts pushed offers, only nodes with trusted relays configured send them
+pub(crate) fn behaviour(protocol_prefix: &str) -> Behaviour {
+    request_response::json::Behaviour::new(
+        [(
+            StreamProtocol::try_from_owned(format!("{}/push/1", protocol_prefix))
+                .expect("protocol name is valid"),
+            ProtocolSupport::Full,
+        )],
+        request_response::Config::default().with_request_timeout(Duration::from_secs(30)),
+    )
+}
@@ -0,0 +1,134 @@
+use crate::OfferMetadata;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct PendingOffer {
+    pub(crate) offer: String,
+    pub(crate) queued_at: u64,
+    // Unix timestamp after which the offer is dropped instead of published
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) expires_at: Option<u64>,
+    // Published along the offer on every attempt, queues written before it existed have none
+    #[serde(default)]
+    pub(crate) metadata: OfferMetadata,
+}
+
+// Submissions that were accepted but not yet published, mirrored to disk on every change unless the
+// queue only lives in memory
+pub(crate) struct PendingQueue {
+    path: Option<PathBuf>,
+    offers: Vec<PendingOffer>,
+}
+
+impl PendingQueue {
+    pub(crate) fn load(path: PathBuf) -> io::Result<Self> {
+        let offers = match fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+
+        Ok(PendingQueue {
+            path: Some(path),
+            offers,
+        })
+    }
+
+    pub(crate) fn in_memory() -> Self {
+        PendingQueue {
+            path: None,
+            offers: Vec::new(),
+        }
+    }
+
+    // Whether the queued offers survive a restart
+    pub(crate) fn is_persistent(&self) -> bool {
+        self.path.is_some()
+    }
+
+    pub(crate) fn push(
+        &mut self,
+        offer: &str,
+        expires_at: Option<u64>,
+        metadata: &OfferMetadata,
+    ) -> io::Result<()> {
+        if self.offers.iter().any(|pending| pending.offer == offer) {
+            return Ok(());
+        }
+
+        self.offers.push(PendingOffer {
+            offer: offer.to_string(),
+            queued_at: unix_timestamp(),
+            expires_at,
+            metadata: metadata.clone(),
+        });
+        self.persist()
+    }
+
+    pub(crate) fn remove(&mut self, offer: &str) -> io::Result<()> {
+        let len = self.offers.len();
+        self.offers.retain(|pending| pending.offer != offer);
+
+        if self.offers.len() == len {
+            return Ok(());
+        }
+
+        self.persist()
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.offers.len()
+    }
+
+    // Offers queued at least `min_age` seconds ago, younger ones are still on their first attempt
+    pub(crate) fn due(&self, min_age: u64) -> Vec<PendingOffer> {
+        let now = unix_timestamp();
+        self.offers
+            .iter()
+            .filter(|pending| now.saturating_sub(pending.queued_at) >= min_age)
+            .cloned()
+            .collect()
+    }
+
+    // Removes offers whose TTL passed or that were queued longer than `max_age` seconds before they
+    // could be published and returns them
+    pub(crate) fn expire(&mut self, max_age: Option<u64>) -> io::Result<Vec<String>> {
+        let now = unix_timestamp();
+        let (expired, offers): (Vec<_>, Vec<_>) = self.offers.drain(..).partition(|pending| {
+            pending.expires_at.is_some_and(|at| at <= now)
+                || max_age.is_some_and(|max_age| now.saturating_sub(pending.queued_at) >= max_age)
+        });
+        self.offers = offers;
+
+        if expired.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        self.persist()?;
+        Ok(expired.into_iter().map(|pending| pending.offer).collect())
+    }
+
+    // Write to a temporary file first so a crash can't leave a truncated queue behind
+    fn persist(&self) -> io::Result<()> {
+        let Some(path) = self.path.as_ref() else {
+            return Ok(());
+        };
+        let mut tmp_path = path.clone().into_os_string();
+        tmp_path.push(".tmp");
+
+        let file = File::create(&tmp_path)?;
+        serde_json::to_writer(file, &self.offers)?;
+        fs::rename(tmp_path, path)
+    }
+}
+
+pub(crate) fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
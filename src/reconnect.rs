@@ -0,0 +1,121 @@
+use crate::SplashEvent;
+use libp2p::multiaddr::Protocol;
+use libp2p::{Multiaddr, PeerId};
+use rand::Rng;
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::time::Instant;
+
+const MIN_RECONNECT_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_BACKOFF: Duration = Duration::from_secs(5 * 60);
+const MAX_RECONNECT_ATTEMPTS: u32 = 10;
+pub(crate) const RECONNECT_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+pub(crate) enum Retry {
+    Scheduled { attempt: u32, retry_in: Duration },
+    GaveUp,
+}
+
+impl Retry {
+    pub(crate) fn into_event(self, peer_id: PeerId) -> SplashEvent {
+        match self {
+            Retry::Scheduled { attempt, retry_in } => SplashEvent::ReconnectScheduled {
+                peer_id,
+                attempt,
+                retry_in,
+            },
+            Retry::GaveUp => SplashEvent::ReconnectGaveUp(peer_id),
+        }
+    }
+}
+
+// Redials known peers that we lost the connection to, backing off exponentially with jitter
+pub(crate) struct Reconnect {
+    known_peers: HashMap<PeerId, Multiaddr>,
+    attempts: HashMap<PeerId, u32>,
+    scheduled: HashMap<PeerId, Instant>,
+}
+
+impl Reconnect {
+    pub(crate) fn new(known_peers: &[Multiaddr]) -> Self {
+        let known_peers = known_peers
+            .iter()
+            .filter_map(|addr| match addr.iter().last() {
+                Some(Protocol::P2p(peer_id)) => Some((peer_id, addr.clone())),
+                _ => None,
+            })
+            .collect();
+
+        Reconnect {
+            known_peers,
+            attempts: HashMap::new(),
+            scheduled: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn known_peers(&self) -> Vec<(PeerId, Multiaddr)> {
+        self.known_peers
+            .iter()
+            .map(|(peer_id, addr)| (*peer_id, addr.clone()))
+            .collect()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.known_peers.is_empty()
+    }
+
+    // Remembers another known peer, e.g. an introducer resolved later on, returns whether it is new
+    pub(crate) fn add(&mut self, peer_id: PeerId, address: Multiaddr) -> bool {
+        self.known_peers.insert(peer_id, address).is_none()
+    }
+
+    pub(crate) fn connected(&mut self, peer_id: &PeerId) {
+        self.attempts.remove(peer_id);
+        self.scheduled.remove(peer_id);
+    }
+
+    // The connection to a peer was lost or a dial failed, schedules the next attempt for known peers
+    pub(crate) fn lost(&mut self, peer_id: PeerId) -> Option<Retry> {
+        if !self.known_peers.contains_key(&peer_id) || self.scheduled.contains_key(&peer_id) {
+            return None;
+        }
+
+        let attempt = self.attempts.entry(peer_id).or_default();
+        if *attempt >= MAX_RECONNECT_ATTEMPTS {
+            self.attempts.remove(&peer_id);
+            return Some(Retry::GaveUp);
+        }
+        *attempt += 1;
+
+        let backoff = MIN_RECONNECT_BACKOFF
+            .saturating_mul(1 << (*attempt - 1))
+            .min(MAX_RECONNECT_BACKOFF);
+        let retry_in = backoff.mul_f64(rand::thread_rng().gen_range(0.8..1.2));
+        self.scheduled.insert(peer_id, Instant::now() + retry_in);
+
+        Some(Retry::Scheduled {
+            attempt: *attempt,
+            retry_in,
+        })
+    }
+
+    // Known peers whose backoff elapsed, to be dialed now
+    pub(crate) fn due(&mut self) -> Vec<(PeerId, Multiaddr)> {
+        let now = Instant::now();
+        let due: Vec<PeerId> = self
+            .scheduled
+            .iter()
+            .filter(|(_, at)| **at <= now)
+            .map(|(peer_id, _)| *peer_id)
+            .collect();
+
+        due.into_iter()
+            .filter_map(|peer_id| {
+                self.scheduled.remove(&peer_id);
+                self.known_peers
+                    .get(&peer_id)
+                    .map(|addr| (peer_id, addr.clone()))
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,61 @@
+use libp2p::gossipsub::{PeerScoreParams, PeerScoreThresholds, TopicHash, TopicScoreParams};
+use std::time::Duration;
+
+/// Gossipsub v1.1 peer scoring for the offers topic. Peers below the thresholds stop receiving our
+/// gossip, our publishes and finally any of their messages are ignored (graylisted)
+#[derive(Clone, Debug)]
+pub struct PeerScoring {
+    /// Weight of the offers topic in the total score
+    pub topic_weight: f64,
+    /// Penalty per invalid offer, squared and decaying by half about every minute
+    pub invalid_message_weight: f64,
+    pub gossip_threshold: f64,
+    pub publish_threshold: f64,
+    pub graylist_threshold: f64,
+}
+
+impl Default for PeerScoring {
+    fn default() -> Self {
+        PeerScoring {
+            topic_weight: 1.0,
+            invalid_message_weight: -10.0,
+            gossip_threshold: -10.0,
+            publish_threshold: -50.0,
+            graylist_threshold: -80.0,
+        }
+    }
+}
+
+impl PeerScoring {
+    pub(crate) fn params(&self, topics: Vec<TopicHash>) -> (PeerScoreParams, PeerScoreThresholds) {
+        // Offers are infrequent, so peers aren't penalized for delivering few of them
+        let topic_params = TopicScoreParams {
+            topic_weight: self.topic_weight,
+            time_in_mesh_weight: 0.01,
+            time_in_mesh_quantum: Duration::from_secs(1),
+            time_in_mesh_cap: 3600.0,
+            first_message_deliveries_weight: 1.0,
+            first_message_deliveries_decay: 0.9,
+            first_message_deliveries_cap: 50.0,
+            mesh_message_deliveries_weight: 0.0,
+            mesh_failure_penalty_weight: 0.0,
+            invalid_message_deliveries_weight: self.invalid_message_weight,
+            invalid_message_deliveries_decay: 0.99,
+            ..TopicScoreParams::default()
+        };
+
+        let mut params = PeerScoreParams::default();
+        for topic in topics {
+            params.topics.insert(topic, topic_params.clone());
+        }
+
+        let thresholds = PeerScoreThresholds {
+            gossip_threshold: self.gossip_threshold,
+            publish_threshold: self.publish_threshold,
+            graylist_threshold: self.graylist_threshold,
+            ..PeerScoreThresholds::default()
+        };
+
+        (params, thresholds)
+    }
+}
@@ -0,0 +1,113 @@
+use std::collections::{HashSet, VecDeque};
+use std::mem::size_of;
+use std::time::Duration;
+use tokio::time::Instant;
+
+// Rough per entry cost: the set slot plus the eviction queue slot
+const SEEN_ENTRY_BYTES: usize = size_of::<u64>() + size_of::<(u64, Instant)>();
+
+const DEFAULT_MEMORY_BUDGET: usize = 8 * 1024 * 1024;
+const DEFAULT_DUPLICATE_CACHE_TIME: Duration = Duration::from_secs(60);
+const DEFAULT_SEEN_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// Trade-off between memory usage and duplicate suppression
+#[derive(Clone, Debug)]
+pub struct DedupConfig {
+    /// How long gossipsub remembers message ids to drop duplicates within the mesh
+    pub duplicate_cache_time: Duration,
+    /// How long delivered offers are remembered to suppress late re-broadcasts
+    pub seen_ttl: Duration,
+    /// Maximum number of remembered offers, the oldest are evicted first
+    pub seen_capacity: usize,
+}
+
+impl Default for DedupConfig {
+    fn default() -> Self {
+        DedupConfig::from_memory_budget(DEFAULT_MEMORY_BUDGET)
+    }
+}
+
+impl DedupConfig {
+    /// Derive cache sizes and lifetimes from a memory budget in bytes, lifetimes scale with the
+    /// budget relative to the 8 MiB default (between 1/6 and 4 times the default)
+    pub fn from_memory_budget(bytes: usize) -> Self {
+        let factor = (bytes as f64 / DEFAULT_MEMORY_BUDGET as f64).clamp(1.0 / 6.0, 4.0);
+
+        DedupConfig {
+            duplicate_cache_time: DEFAULT_DUPLICATE_CACHE_TIME.mul_f64(factor),
+            seen_ttl: DEFAULT_SEEN_TTL.mul_f64(factor),
+            seen_capacity: (bytes / SEEN_ENTRY_BYTES).max(1),
+        }
+    }
+}
+
+/// Current usage of the seen-offer cache
+#[derive(Clone, Debug)]
+pub struct DedupStats {
+    pub entries: usize,
+    pub capacity: usize,
+    pub approx_bytes: usize,
+}
+
+pub(crate) struct SeenCache {
+    entries: HashSet<u64>,
+    order: VecDeque<(u64, Instant)>,
+    ttl: Duration,
+    capacity: usize,
+}
+
+impl SeenCache {
+    pub(crate) fn new(config: &DedupConfig) -> Self {
+        SeenCache {
+            entries: HashSet::new(),
+            order: VecDeque::new(),
+            ttl: config.seen_ttl,
+            capacity: config.seen_capacity,
+        }
+    }
+
+    // Returns false if the key was already seen
+    pub(crate) fn insert(&mut self, key: u64) -> bool {
+        self.prune();
+
+        if self.entries.contains(&key) {
+            return false;
+        }
+
+        while self.entries.len() >= self.capacity {
+            let Some((oldest, _)) = self.order.pop_front() else {
+                break;
+            };
+            self.entries.remove(&oldest);
+        }
+
+        self.entries.insert(key);
+        self.order.push_back((key, Instant::now()));
+
+        true
+    }
+
+    pub(crate) fn prune(&mut self) {
+        let now = Instant::now();
+
+        while let Some((key, inserted)) = self.order.front().copied() {
+            if now.duration_since(inserted) < self.ttl {
+                break;
+            }
+            self.order.pop_front();
+            self.entries.remove(&key);
+        }
+    }
+
+    pub(crate) fn keys(&self) -> Vec<u64> {
+        self.order.iter().map(|(key, _)| *key).collect()
+    }
+
+    pub(crate) fn stats(&self) -> DedupStats {
+        DedupStats {
+            entries: self.entries.len(),
+            capacity: self.capacity,
+            approx_bytes: self.entries.len() * SEEN_ENTRY_BYTES,
+        }
+    }
+}
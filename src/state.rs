@@ -0,0 +1,47 @@
+use libp2p::kad::{self, store::MemoryStore};
+use libp2p::{identity, Multiaddr, PeerId};
+use serde::{Deserialize, Serialize};
+
+// Format of `seen_offers`, snapshots in another format restore without the seen cache
+pub(crate) const SEEN_OFFERS_FORMAT: u32 = 1;
+
+/// Portable snapshot of a node, used to migrate it without losing its network standing
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct NodeState {
+    /// Protobuf encoded keypair
+    pub identity: Vec<u8>,
+    /// Address book from the Kademlia routing table
+    pub peers: Vec<KnownPeer>,
+    /// Recently delivered offers, the first 8 bytes of their SHA-256 as a big endian integer
+    pub seen_offers: Vec<u64>,
+    /// Format of `seen_offers`, missing in snapshots that stored build specific hashes
+    #[serde(default)]
+    pub seen_offers_format: u32,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct KnownPeer {
+    pub peer_id: PeerId,
+    pub addresses: Vec<Multiaddr>,
+}
+
+impl NodeState {
+    pub fn keys(&self) -> Result<identity::Keypair, identity::DecodingError> {
+        identity::Keypair::from_protobuf_encoding(&self.identity)
+    }
+}
+
+pub(crate) fn address_book(kademlia: &mut kad::Behaviour<MemoryStore>) -> Vec<KnownPeer> {
+    let mut peers = Vec::new();
+
+    for bucket in kademlia.kbuckets() {
+        for entry in bucket.iter() {
+            peers.push(KnownPeer {
+                peer_id: *entry.node.key.preimage(),
+                addresses: entry.node.value.iter().cloned().collect(),
+            });
+        }
+    }
+
+    peers
+}
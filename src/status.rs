@@ -0,0 +1,57 @@
+use tokio::time::Instant;
+
+/// Heartbeat of the node's state, see `Splash::with_status_interval`
+#[derive(Clone, Debug)]
+pub struct StatusSummary {
+    pub peers: usize,
+    pub mesh_peers: usize,
+    /// Offers delivered as `OfferReceived` since the previous summary, per minute
+    pub offers_per_minute: f64,
+    /// Offers waiting in the submission channel, e.g. behind the publish rate limit
+    pub pending_submissions: usize,
+    pub dedup_entries: usize,
+}
+
+// Counts the offers delivered between summaries
+pub(crate) struct StatusReporter {
+    since: Instant,
+    offers: u64,
+}
+
+impl StatusReporter {
+    pub(crate) fn new() -> Self {
+        StatusReporter {
+            since: Instant::now(),
+            offers: 0,
+        }
+    }
+
+    pub(crate) fn offer_received(&mut self) {
+        self.offers += 1;
+    }
+
+    pub(crate) fn summary(
+        &mut self,
+        peers: usize,
+        mesh_peers: usize,
+        pending_submissions: usize,
+        dedup_entries: usize,
+    ) -> StatusSummary {
+        let minutes = self.since.elapsed().as_secs_f64() / 60.0;
+        let offers_per_minute = if minutes > 0.0 {
+            self.offers as f64 / minutes
+        } else {
+            0.0
+        };
+        self.since = Instant::now();
+        self.offers = 0;
+
+        StatusSummary {
+            peers,
+            mesh_peers,
+            offers_per_minute,
+            pending_submissions,
+            dedup_entries,
+        }
+    }
+}
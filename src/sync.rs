@@ -0,0 +1,266 @@
+use crate::chunks::offer_id;
+use crate::SplashError;
+use libp2p::kad::{self, store::MemoryStore, QueryId};
+use libp2p::request_response::{self, OutboundRequestId, ProtocolSupport};
+use libp2p::{PeerId, StreamProtocol};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::Duration;
+use tokio::sync::oneshot;
+use tokio::time::Instant;
+
+// Offers kept for sync requests regardless of the window, and at most returned per response so it stays
+// below the response size limit of the JSON codec
+const MAX_RECENT_OFFERS: usize = 10_000;
+const MAX_RESPONSE_OFFERS: usize = 500;
+// The newest offers are announced as provider records, up to the DHT store's limit of provided keys
+const MAX_PROVIDED_OFFERS: usize = crate::dht::MAX_PROVIDED_KEYS;
+
+// A responder answers each peer at most once per interval, further requests get an empty response
+const RESPONSE_INTERVAL: Duration = Duration::from_secs(60);
+
+// Peers asked for a backfill after startup, the first ones identified as sync responders
+const BACKFILL_PEERS: usize = 3;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SyncRequest {
+    pub(crate) since_secs: u64,
+    // Asks for this offer only, regardless of its age, see `Splash::fetch_offer`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) offer_id: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SyncResponse {
+    pub(crate) offers: Vec<String>,
+}
+
+pub(crate) type Behaviour = request_response::json::Behaviour<SyncRequest, SyncResponse>;
+
+// Every node can ask for a backfill, only responders advertise and answer the protocol
+pub(crate) fn behaviour(protocol_prefix: &str, responder: bool) -> Behaviour {
+    request_response::json::Behaviour::new(
+        [(
+            StreamProtocol::try_from_owned(format!("{}/sync/1", protocol_prefix))
+                .expect("protocol name is valid"),
+            if responder {
+                ProtocolSupport::Full
+            } else {
+                ProtocolSupport::Outbound
+            },
+        )],
+        request_response::Config::default().with_request_timeout(Duration::from_secs(30)),
+    )
+}
+
+struct RecentOffer {
+    at: Instant,
+    id: String,
+    offer: String,
+}
+
+// Offers received or published within the window, oldest first, the ones we announce as provider and
+// the peers we answered
+pub(crate) struct RecentOffers {
+    window: Duration,
+    offers: VecDeque<RecentOffer>,
+    provided: VecDeque<(Instant, kad::RecordKey)>,
+    answered: HashMap<PeerId, Instant>,
+}
+
+impl RecentOffers {
+    pub(crate) fn new(window: Duration) -> Self {
+        RecentOffers {
+            window,
+            offers: VecDeque::new(),
+            provided: VecDeque::new(),
+            answered: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn insert(&mut self, kademlia: &mut kad::Behaviour<MemoryStore>, offer: String) {
+        self.prune();
+        self.expire_provided(kademlia);
+        if self.offers.len() >= MAX_RECENT_OFFERS {
+            self.offers.pop_front();
+        }
+        let id = offer_id(&offer);
+
+        if self.provided.len() >= MAX_PROVIDED_OFFERS {
+            if let Some((_, key)) = self.provided.pop_front() {
+                kademlia.stop_providing(&key);
+            }
+        }
+        let key = kad::RecordKey::new(&id);
+        match kademlia.start_providing(key.clone()) {
+            Ok(_) => self.provided.push_back((Instant::now(), key)),
+            Err(e) => log::debug!("Failed to provide offer {}: {}", id, e),
+        }
+
+        self.offers.push_back(RecentOffer {
+            at: Instant::now(),
+            id,
+            offer,
+        });
+    }
+
+    // Offers leaving the window are no longer announced, remote provider records expire on their own
+    pub(crate) fn expire_provided(&mut self, kademlia: &mut kad::Behaviour<MemoryStore>) {
+        let now = Instant::now();
+        while let Some((at, _)) = self.provided.front() {
+            if now.duration_since(*at) <= self.window {
+                break;
+            }
+            if let Some((_, key)) = self.provided.pop_front() {
+                kademlia.stop_providing(&key);
+            }
+        }
+    }
+
+    pub(crate) fn get(&mut self, offer_id: &str) -> Option<String> {
+        self.prune();
+        self.offers
+            .iter()
+            .find(|recent| recent.id == offer_id)
+            .map(|recent| recent.offer.clone())
+    }
+
+    // The newest offers of the last `since`, limited to the window we keep
+    pub(crate) fn since(&mut self, since: Duration) -> Vec<String> {
+        self.prune();
+        let now = Instant::now();
+        let mut offers: Vec<String> = self
+            .offers
+            .iter()
+            .rev()
+            .take_while(|recent| now.duration_since(recent.at) <= since)
+            .take(MAX_RESPONSE_OFFERS)
+            .map(|recent| recent.offer.clone())
+            .collect();
+        offers.reverse();
+        offers
+    }
+
+    // Whether to answer a sync request of this peer, at most once per RESPONSE_INTERVAL
+    pub(crate) fn may_answer(&mut self, peer_id: PeerId) -> bool {
+        let now = Instant::now();
+        self.answered
+            .retain(|_, last| now.duration_since(*last) < RESPONSE_INTERVAL);
+        if self.answered.contains_key(&peer_id) {
+            return false;
+        }
+        self.answered.insert(peer_id, now);
+        true
+    }
+
+    fn prune(&mut self) {
+        let now = Instant::now();
+        while let Some(recent) = self.offers.front() {
+            if now.duration_since(recent.at) <= self.window {
+                break;
+            }
+            self.offers.pop_front();
+        }
+    }
+}
+
+// The peers a starting node asks for the offers it missed
+pub(crate) struct Backfill {
+    window: Duration,
+    asked: HashSet<PeerId>,
+}
+
+impl Backfill {
+    pub(crate) fn new(window: Duration) -> Self {
+        Backfill {
+            window,
+            asked: HashSet::new(),
+        }
+    }
+
+    // The request for an identified peer, if it's a sync responder and we still ask peers
+    pub(crate) fn request(&mut self, peer_id: PeerId, responder: bool) -> Option<SyncRequest> {
+        if !responder || self.asked.len() >= BACKFILL_PEERS || !self.asked.insert(peer_id) {
+            return None;
+        }
+        Some(SyncRequest {
+            since_secs: self.window.as_secs(),
+            offer_id: None,
+        })
+    }
+}
+
+struct PendingFetch {
+    offer_id: String,
+    response: oneshot::Sender<Result<String, SplashError>>,
+}
+
+// Offers being fetched, first looking up their providers in the DHT, then asking one of them
+#[derive(Default)]
+pub(crate) struct Fetches {
+    lookups: HashMap<QueryId, PendingFetch>,
+    requests: HashMap<OutboundRequestId, PendingFetch>,
+}
+
+impl Fetches {
+    pub(crate) fn lookup(
+        &mut self,
+        query_id: QueryId,
+        offer_id: String,
+        response: oneshot::Sender<Result<String, SplashError>>,
+    ) {
+        self.lookups
+            .insert(query_id, PendingFetch { offer_id, response });
+    }
+
+    // The request for a provider found by the lookup, if it's still pending
+    pub(crate) fn request(&self, query_id: QueryId) -> Option<SyncRequest> {
+        self.lookups.get(&query_id).map(|fetch| SyncRequest {
+            since_secs: 0,
+            offer_id: Some(fetch.offer_id.clone()),
+        })
+    }
+
+    // Only the first provider found is asked
+    pub(crate) fn sent(&mut self, query_id: QueryId, request_id: OutboundRequestId) {
+        if let Some(fetch) = self.lookups.remove(&query_id) {
+            self.requests.insert(request_id, fetch);
+        }
+    }
+
+    pub(crate) fn not_found(&mut self, query_id: QueryId) {
+        if let Some(fetch) = self.lookups.remove(&query_id) {
+            fetch
+                .response
+                .send(Err(SplashError::FetchFailed(
+                    "no provider found".to_string(),
+                )))
+                .ok();
+        }
+    }
+
+    // Whether the response answered a fetch rather than a backfill
+    pub(crate) fn answered(&mut self, request_id: OutboundRequestId, offers: &[String]) -> bool {
+        let Some(fetch) = self.requests.remove(&request_id) else {
+            return false;
+        };
+        let result = offers
+            .iter()
+            .find(|offer| offer_id(offer) == fetch.offer_id)
+            .cloned()
+            .ok_or_else(|| SplashError::FetchFailed("provider doesn't have the offer".to_string()));
+        fetch.response.send(result).ok();
+        true
+    }
+
+    pub(crate) fn failed(&mut self, request_id: OutboundRequestId, error: String) -> bool {
+        let Some(fetch) = self.requests.remove(&request_id) else {
+            return false;
+        };
+        fetch
+            .response
+            .send(Err(SplashError::FetchFailed(error)))
+            .ok();
+        true
+    }
+}
@@ -0,0 +1,224 @@
+use futures::{AsyncRead, AsyncWrite};
+use libp2p::PeerId;
+use std::collections::HashMap;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::time::{Instant, Sleep};
+
+// Upload and download limits of a single connection in bytes per second
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct RateLimits {
+    pub(crate) upload: Option<u64>,
+    pub(crate) download: Option<u64>,
+}
+
+// Token bucket allowing bursts of up to one second worth of traffic
+struct Bucket {
+    rate: u64,
+    tokens: u64,
+    refilled_at: Instant,
+    delay: Option<Pin<Box<Sleep>>>,
+}
+
+impl Bucket {
+    fn new(rate: u64) -> Self {
+        Bucket {
+            rate,
+            tokens: rate,
+            refilled_at: Instant::now(),
+            delay: None,
+        }
+    }
+
+    // Bytes that may be transferred right now, waits for the next refill if there are none
+    fn poll_available(&mut self, cx: &mut Context<'_>) -> Poll<usize> {
+        loop {
+            let now = Instant::now();
+            let refill =
+                (now.duration_since(self.refilled_at).as_secs_f64() * self.rate as f64) as u64;
+            if refill > 0 {
+                self.tokens = (self.tokens + refill).min(self.rate);
+                self.refilled_at = now;
+            }
+
+            if self.tokens > 0 {
+                self.delay = None;
+                return Poll::Ready(self.tokens as usize);
+            }
+
+            let delay = self.delay.get_or_insert_with(|| {
+                let wait = std::time::Duration::from_secs_f64(1.0 / self.rate as f64);
+                Box::pin(tokio::time::sleep(wait))
+            });
+            match delay.as_mut().poll(cx) {
+                Poll::Ready(()) => self.delay = None,
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+
+    fn consume(&mut self, bytes: usize) {
+        self.tokens = self.tokens.saturating_sub(bytes as u64);
+    }
+}
+
+// Stream of a single connection, throttled to the configured rates
+pub(crate) struct Throttled<S> {
+    inner: S,
+    upload: Option<Bucket>,
+    download: Option<Bucket>,
+}
+
+impl<S> Throttled<S> {
+    pub(crate) fn new(inner: S, limits: RateLimits) -> Self {
+        Throttled {
+            inner,
+            upload: limits.upload.map(Bucket::new),
+            download: limits.download.map(Bucket::new),
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Throttled<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let Some(bucket) = this.download.as_mut() else {
+            return Pin::new(&mut this.inner).poll_read(cx, buf);
+        };
+
+        let available = match bucket.poll_available(cx) {
+            Poll::Ready(available) => available.min(buf.len()),
+            Poll::Pending => return Poll::Pending,
+        };
+
+        let result = Pin::new(&mut this.inner).poll_read(cx, &mut buf[..available]);
+        if let Poll::Ready(Ok(read)) = result {
+            bucket.consume(read);
+        }
+        result
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Throttled<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let Some(bucket) = this.upload.as_mut() else {
+            return Pin::new(&mut this.inner).poll_write(cx, buf);
+        };
+
+        let available = match bucket.poll_available(cx) {
+            Poll::Ready(available) => available.min(buf.len()),
+            Poll::Pending => return Poll::Pending,
+        };
+
+        let result = Pin::new(&mut this.inner).poll_write(cx, &buf[..available]);
+        if let Poll::Ready(Ok(written)) = result {
+            bucket.consume(written);
+        }
+        result
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_close(cx)
+    }
+}
+
+// Token bucket for publishing offers, submissions beyond the rate wait in the submission channel
+pub(crate) struct PublishLimiter {
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl PublishLimiter {
+    pub(crate) fn new(offers_per_second: f64, burst: u32) -> Self {
+        PublishLimiter {
+            rate: offers_per_second,
+            burst: burst.max(1) as f64,
+            tokens: burst.max(1) as f64,
+            refilled_at: Instant::now(),
+        }
+    }
+
+    fn available(&self) -> f64 {
+        let elapsed = self.refilled_at.elapsed().as_secs_f64();
+        (self.tokens + elapsed * self.rate).min(self.burst)
+    }
+
+    // Resolves once an offer may be published
+    pub(crate) async fn ready(&self) {
+        let available = self.available();
+        if available < 1.0 {
+            tokio::time::sleep(std::time::Duration::from_secs_f64(
+                (1.0 - available) / self.rate,
+            ))
+            .await;
+        }
+    }
+
+    pub(crate) fn take(&mut self) {
+        self.tokens = (self.available() - 1.0).max(0.0);
+        self.refilled_at = Instant::now();
+    }
+}
+
+// Token bucket per propagation source for received offers, excess messages are dropped and counted
+pub(crate) struct InboundLimiter {
+    rate: f64,
+    burst: f64,
+    peers: HashMap<PeerId, (f64, Instant)>,
+    dropped: HashMap<PeerId, u64>,
+}
+
+impl InboundLimiter {
+    pub(crate) fn new(offers_per_second: f64, burst: u32) -> Self {
+        InboundLimiter {
+            rate: offers_per_second,
+            burst: burst.max(1) as f64,
+            peers: HashMap::new(),
+            dropped: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn allow(&mut self, peer_id: PeerId) -> bool {
+        let (tokens, refilled_at) = self
+            .peers
+            .entry(peer_id)
+            .or_insert((self.burst, Instant::now()));
+        let available = (*tokens + refilled_at.elapsed().as_secs_f64() * self.rate).min(self.burst);
+        *refilled_at = Instant::now();
+
+        if available < 1.0 {
+            *tokens = available;
+            *self.dropped.entry(peer_id).or_default() += 1;
+            return false;
+        }
+
+        *tokens = available - 1.0;
+        true
+    }
+
+    pub(crate) fn disconnected(&mut self, peer_id: &PeerId) {
+        self.peers.remove(peer_id);
+    }
+
+    // Messages dropped per peer since the last call
+    pub(crate) fn take_dropped(&mut self) -> HashMap<PeerId, u64> {
+        std::mem::take(&mut self.dropped)
+    }
+}
@@ -0,0 +1,178 @@
+use crate::codec::{GossipOffer, OfferEnvelope, OfferMetadata};
+use crate::compression::OfferCompression;
+use crate::MAX_MESSAGE_SIZE;
+use crate::{chunks, queue};
+use libp2p::gossipsub::{self, IdentTopic, MessageId, PublishError, SubscriptionError, TopicHash};
+use std::io;
+
+const ZSTD_LEVEL: i32 = 9;
+
+// Versions of the plain offers topic, all are subscribed to so a format change can roll out without
+// splitting the network
+pub(crate) const VERSIONS: [u32; 2] = [1, 2];
+// From this version on offers are wrapped in an envelope with their metadata, earlier ones are bare
+pub(crate) const ENVELOPE_VERSION: u32 = 2;
+// Creation timestamps further than this in the future are dropped, the sender's clock is off
+const MAX_CLOCK_SKEW: u64 = 300;
+
+// The offers topics we're subscribed to, per the configured compression. Offers larger than a chunk
+// are split up and always go to the chunks topic
+pub(crate) struct OfferTopics {
+    // Every version of the plain topic or the custom topics, with whether we publish on them
+    plain: Vec<(IdentTopic, bool)>,
+    // The plain topic carrying enveloped offers, custom topics are bare
+    envelope: Option<TopicHash>,
+    compressed: Option<IdentTopic>,
+    chunks: IdentTopic,
+}
+
+impl OfferTopics {
+    // The plain topics come with whether we publish on them
+    pub(crate) fn new(
+        plain: Vec<(String, bool)>,
+        envelope: Option<String>,
+        compressed: String,
+        chunks: String,
+        compression: OfferCompression,
+    ) -> Self {
+        let plain = plain
+            .into_iter()
+            .filter(|_| compression != OfferCompression::Only)
+            .map(|(topic, publish)| (IdentTopic::new(topic), publish))
+            .collect();
+
+        OfferTopics {
+            plain,
+            envelope: envelope.map(|topic| IdentTopic::new(topic).hash()),
+            compressed: (compression != OfferCompression::Off).then(|| IdentTopic::new(compressed)),
+            chunks: IdentTopic::new(chunks),
+        }
+    }
+
+    // The topic whose mesh is tracked and reported, the first plain one we publish on unless offers
+    // are only compressed
+    pub(crate) fn primary(&self) -> &IdentTopic {
+        self.plain
+            .iter()
+            .find(|(_, publish)| *publish)
+            .map(|(topic, _)| topic)
+            .or(self.compressed.as_ref())
+            .expect("at least one offers topic")
+    }
+
+    fn all(&self) -> impl Iterator<Item = &IdentTopic> {
+        self.plain
+            .iter()
+            .map(|(topic, _)| topic)
+            .chain(self.compressed.iter())
+            .chain(std::iter::once(&self.chunks))
+    }
+
+    pub(crate) fn names(&self) -> Vec<String> {
+        self.all().map(IdentTopic::to_string).collect()
+    }
+
+    pub(crate) fn hashes(&self) -> Vec<TopicHash> {
+        self.all().map(IdentTopic::hash).collect()
+    }
+
+    pub(crate) fn subscribe(
+        &self,
+        gossipsub: &mut gossipsub::Behaviour,
+    ) -> Result<(), SubscriptionError> {
+        for topic in self.all() {
+            gossipsub.subscribe(topic)?;
+        }
+        Ok(())
+    }
+
+    // Publishes on every topic, succeeds if any topic accepted the offer. Large offers succeed once
+    // all of their chunks were published. Only the envelope topic carries the metadata
+    pub(crate) fn publish(
+        &self,
+        gossipsub: &mut gossipsub::Behaviour,
+        offer: &[u8],
+        metadata: &OfferMetadata,
+    ) -> Result<MessageId, PublishError> {
+        // Chunks are bare, a chunked offer loses its metadata
+        if offer.len() > chunks::CHUNK_SIZE {
+            // Chunks that made it out before a failed attempt are duplicates when it's retried
+            let mut result = Err(PublishError::Duplicate);
+            for chunk in chunks::split(&String::from_utf8_lossy(offer)) {
+                match gossipsub.publish(self.chunks.clone(), chunk) {
+                    Ok(message_id) => result = Ok(message_id),
+                    Err(PublishError::Duplicate) => {}
+                    Err(e) => return Err(e),
+                }
+            }
+            return result;
+        }
+
+        let mut results: Vec<Result<MessageId, PublishError>> = self
+            .plain
+            .iter()
+            .filter(|(_, publish)| *publish)
+            .map(|(topic, _)| {
+                let data = if self.envelope.as_ref() == Some(&topic.hash()) {
+                    let metadata = OfferMetadata {
+                        created_at: metadata
+                            .created_at
+                            .or_else(|| Some(queue::unix_timestamp())),
+                        ..metadata.clone()
+                    };
+                    let offer = String::from_utf8_lossy(offer).into_owned();
+                    GossipOffer::V2(OfferEnvelope::new(offer, metadata)).encode()
+                } else {
+                    offer.to_vec()
+                };
+                gossipsub.publish(topic.clone(), data)
+            })
+            .collect();
+        if let Some(topic) = self.compressed.as_ref() {
+            results.push(
+                zstd::bulk::compress(offer, ZSTD_LEVEL)
+                    .map_err(PublishError::TransformFailed)
+                    .and_then(|data| gossipsub.publish(topic.clone(), data)),
+            );
+        }
+
+        // The first success, otherwise the first error
+        let mut error = None;
+        for result in results {
+            match result {
+                Ok(message_id) => return Ok(message_id),
+                Err(e) => {
+                    error.get_or_insert(e);
+                }
+            }
+        }
+        Err(error.expect("at least one offers topic"))
+    }
+
+    pub(crate) fn is_chunk(&self, topic: &TopicHash) -> bool {
+        self.chunks.hash() == *topic
+    }
+
+    // The offer as published with its metadata, compressed messages larger than an offer may be are
+    // refused
+    pub(crate) fn decode(
+        &self,
+        topic: &TopicHash,
+        data: Vec<u8>,
+    ) -> io::Result<(Vec<u8>, OfferMetadata)> {
+        if self.envelope.as_ref() == Some(topic) {
+            let message = GossipOffer::decode(ENVELOPE_VERSION, &data)?;
+            let mut metadata = message.metadata();
+            let skew_limit = queue::unix_timestamp() + MAX_CLOCK_SKEW;
+            metadata.created_at = metadata.created_at.filter(|at| *at <= skew_limit);
+            return Ok((message.offer().as_bytes().to_vec(), metadata));
+        }
+        match self.compressed.as_ref() {
+            Some(compressed) if compressed.hash() == *topic => Ok((
+                zstd::bulk::decompress(&data, MAX_MESSAGE_SIZE)?,
+                OfferMetadata::default(),
+            )),
+            _ => Ok((data, OfferMetadata::default())),
+        }
+    }
+}
@@ -0,0 +1,550 @@
+use crate::throttle::{RateLimits, Throttled};
+use futures::future::{self, BoxFuture, Either, FutureExt, TryFutureExt};
+use futures::{AsyncRead, AsyncWrite};
+use libp2p::core::muxing::StreamMuxerBox;
+use libp2p::core::transport::map_err::MapErr;
+use libp2p::core::transport::timeout::{TransportTimeout, TransportTimeoutError};
+use libp2p::core::transport::{
+    upgrade::Version, Boxed, DialOpts, ListenerId, OrTransport, TransportError, TransportEvent,
+};
+use libp2p::core::upgrade::{InboundConnectionUpgrade, OutboundConnectionUpgrade, UpgradeInfo};
+use libp2p::core::Transport;
+use libp2p::multiaddr::Protocol;
+use libp2p::{dns, identity, noise, tcp, tls, websocket, yamux, Multiaddr, PeerId};
+use std::collections::HashMap;
+use std::io::{self, BufReader};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio_socks::{tcp::Socks5Stream, TargetAddr};
+use tokio_util::compat::{Compat, TokioAsyncReadCompatExt};
+
+// Security protocol negotiated with each peer, for the debug API
+pub(crate) type NegotiatedSecurity = Arc<Mutex<HashMap<PeerId, &'static str>>>;
+
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) enum TransportKind {
+    #[default]
+    Tcp,
+    #[cfg(feature = "simulation")]
+    Memory,
+}
+
+/// Security protocols offered to peers, Noise is preferred when both are offered
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SecurityUpgrades {
+    Noise,
+    Tls,
+    #[default]
+    NoiseAndTls,
+}
+
+impl FromStr for SecurityUpgrades {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "noise" => Ok(SecurityUpgrades::Noise),
+            "tls" => Ok(SecurityUpgrades::Tls),
+            "both" => Ok(SecurityUpgrades::NoiseAndTls),
+            _ => Err(format!("Unknown security upgrades: {}", s)),
+        }
+    }
+}
+
+/// Restricts listening and dialing to one IP family, e.g. on hosts with broken IPv6 routes
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AddressFamily {
+    #[default]
+    Any,
+    Ipv4,
+    Ipv6,
+}
+
+impl AddressFamily {
+    // Addresses without an IP or DNS family, like /onion3 or /memory, are always allowed
+    pub(crate) fn allows(&self, addr: &Multiaddr) -> bool {
+        match (self, addr.iter().next()) {
+            (AddressFamily::Ipv4, Some(Protocol::Ip6(_) | Protocol::Dns6(_))) => false,
+            (AddressFamily::Ipv6, Some(Protocol::Ip4(_) | Protocol::Dns4(_))) => false,
+            _ => true,
+        }
+    }
+}
+
+pub(crate) struct TransportConfig {
+    pub(crate) kind: TransportKind,
+    pub(crate) websocket_tls: Option<websocket::tls::Config>,
+    pub(crate) handshake_timeout: Duration,
+    pub(crate) dial_timeout: Duration,
+    pub(crate) proxy: Option<SocketAddr>,
+    pub(crate) security: SecurityUpgrades,
+    pub(crate) family: AddressFamily,
+    pub(crate) rate_limits: RateLimits,
+}
+
+pub(crate) fn build_transport(
+    keys: &identity::Keypair,
+    config: TransportConfig,
+    negotiated: NegotiatedSecurity,
+) -> Result<Boxed<(PeerId, StreamMuxerBox)>, io::Error> {
+    let TransportConfig {
+        kind,
+        websocket_tls,
+        handshake_timeout,
+        dial_timeout,
+        proxy,
+        security,
+        family,
+        rate_limits,
+    } = config;
+    let security = Security::new(keys, security, negotiated)?;
+
+    let transport = match kind {
+        TransportKind::Tcp => {
+            // Listen addresses ending in /ws or /wss go to the websocket transport, e.g. for browser clients
+            let mut websocket =
+                websocket::WsConfig::new(tcp_transport(proxy, family, dial_timeout)?);
+            if let Some(tls) = websocket_tls {
+                websocket.set_tls_config(tls);
+            }
+
+            websocket
+                .or_transport(tcp_transport(proxy, family, dial_timeout)?)
+                .map(move |stream, _| Throttled::new(stream, rate_limits))
+                .upgrade(Version::V1Lazy)
+                .authenticate(security)
+                .multiplex(yamux::Config::default())
+                .timeout(handshake_timeout)
+                .map(|(peer_id, muxer), _| (peer_id, StreamMuxerBox::new(muxer)))
+                .map_err(handshake_error)
+                .boxed()
+        }
+        #[cfg(feature = "simulation")]
+        TransportKind::Memory => libp2p::core::transport::MemoryTransport::default()
+            .upgrade(Version::V1Lazy)
+            .authenticate(security)
+            .multiplex(yamux::Config::default())
+            .timeout(handshake_timeout)
+            .map(|(peer_id, muxer), _| (peer_id, StreamMuxerBox::new(muxer)))
+            .map_err(handshake_error)
+            .boxed(),
+    };
+
+    Ok(transport)
+}
+
+// Negotiates whichever of the offered security protocols the remote supports
+#[derive(Clone)]
+struct Security {
+    noise: Option<noise::Config>,
+    tls: Option<tls::Config>,
+    negotiated: NegotiatedSecurity,
+}
+
+impl Security {
+    fn new(
+        keys: &identity::Keypair,
+        upgrades: SecurityUpgrades,
+        negotiated: NegotiatedSecurity,
+    ) -> io::Result<Self> {
+        let noise = match upgrades {
+            SecurityUpgrades::Tls => None,
+            _ => Some(noise::Config::new(keys).map_err(io::Error::other)?),
+        };
+
+        let tls = match upgrades {
+            SecurityUpgrades::Noise => None,
+            _ => Some(tls::Config::new(keys).map_err(io::Error::other)?),
+        };
+
+        Ok(Security {
+            noise,
+            tls,
+            negotiated,
+        })
+    }
+
+    fn is_noise(&self, info: &str) -> bool {
+        self.noise
+            .as_ref()
+            .is_some_and(|noise| noise.protocol_info().any(|protocol| protocol == info))
+    }
+}
+
+fn record<S>(
+    negotiated: NegotiatedSecurity,
+    info: &'static str,
+) -> impl FnOnce((PeerId, S)) -> (PeerId, S) {
+    move |(peer_id, stream)| {
+        negotiated
+            .lock()
+            .expect("negotiated security lock poisoned")
+            .insert(peer_id, info);
+        (peer_id, stream)
+    }
+}
+
+impl UpgradeInfo for Security {
+    type Info = &'static str;
+    type InfoIter = Vec<Self::Info>;
+
+    fn protocol_info(&self) -> Self::InfoIter {
+        let mut protocols = Vec::new();
+        if let Some(noise) = self.noise.as_ref() {
+            protocols.extend(noise.protocol_info());
+        }
+        if let Some(tls) = self.tls.as_ref() {
+            protocols.extend(tls.protocol_info());
+        }
+        protocols
+    }
+}
+
+type SecureStream<C> = Either<noise::Output<C>, tls::TlsStream<C>>;
+
+impl<C> InboundConnectionUpgrade<C> for Security
+where
+    C: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    type Output = (PeerId, SecureStream<C>);
+    type Error = io::Error;
+    type Future = BoxFuture<'static, Result<Self::Output, Self::Error>>;
+
+    fn upgrade_inbound(self, socket: C, info: Self::Info) -> Self::Future {
+        let is_noise = self.is_noise(info);
+        let record = record(self.negotiated, info);
+        match (self.noise, self.tls) {
+            (Some(noise), _) if is_noise => noise
+                .upgrade_inbound(socket, info)
+                .map_ok(|(peer_id, stream)| (peer_id, Either::Left(stream)))
+                .map_ok(record)
+                .map_err(io::Error::other)
+                .boxed(),
+            (_, Some(tls)) => tls
+                .upgrade_inbound(socket, info)
+                .map_ok(|(peer_id, stream)| (peer_id, Either::Right(stream)))
+                .map_ok(record)
+                .map_err(io::Error::other)
+                .boxed(),
+            _ => future::ready(Err(io::Error::other("unsupported security protocol"))).boxed(),
+        }
+    }
+}
+
+impl<C> OutboundConnectionUpgrade<C> for Security
+where
+    C: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    type Output = (PeerId, SecureStream<C>);
+    type Error = io::Error;
+    type Future = BoxFuture<'static, Result<Self::Output, Self::Error>>;
+
+    fn upgrade_outbound(self, socket: C, info: Self::Info) -> Self::Future {
+        let is_noise = self.is_noise(info);
+        let record = record(self.negotiated, info);
+        match (self.noise, self.tls) {
+            (Some(noise), _) if is_noise => noise
+                .upgrade_outbound(socket, info)
+                .map_ok(|(peer_id, stream)| (peer_id, Either::Left(stream)))
+                .map_ok(record)
+                .map_err(io::Error::other)
+                .boxed(),
+            (_, Some(tls)) => tls
+                .upgrade_outbound(socket, info)
+                .map_ok(|(peer_id, stream)| (peer_id, Either::Right(stream)))
+                .map_ok(record)
+                .map_err(io::Error::other)
+                .boxed(),
+            _ => future::ready(Err(io::Error::other("unsupported security protocol"))).boxed(),
+        }
+    }
+}
+
+type TcpBase =
+    FamilyFilter<OrTransport<SocksTransport, dns::tokio::Transport<tcp::tokio::Transport>>>;
+type TcpTransport = MapErr<
+    TransportTimeout<TcpBase>,
+    fn(TransportTimeoutError<<TcpBase as Transport>::Error>) -> io::Error,
+>;
+
+// Dials go through the proxy if one is set, listening always uses plain TCP. With a proxy, addresses it
+// can't dial are refused instead of falling through to a direct dial that would reveal our IP. Without
+// one /dns, /dns4, /dns6 and /dnsaddr addresses are resolved on every dial, so peers can change their
+// IP. The dial timeout only covers connecting, the handshake has its own
+fn tcp_transport(
+    proxy: Option<SocketAddr>,
+    family: AddressFamily,
+    dial_timeout: Duration,
+) -> Result<TcpTransport, io::Error> {
+    let tcp = dns::tokio::Transport::system(tcp::tokio::Transport::new(tcp::Config::default()))?;
+    let base = FamilyFilter {
+        inner: SocksTransport { proxy }.or_transport(tcp),
+        family,
+    };
+
+    Ok(TransportTimeout::with_outgoing_timeout(base, dial_timeout)
+        .map_err(dial_error as fn(_) -> _))
+}
+
+fn dial_error<E>(error: TransportTimeoutError<E>) -> io::Error
+where
+    E: std::error::Error + Send + Sync + 'static,
+{
+    match error {
+        TransportTimeoutError::Timeout => io::Error::new(io::ErrorKind::TimedOut, "dial timed out"),
+        error => io::Error::new(io::ErrorKind::Other, error),
+    }
+}
+
+// Rejects addresses of the other IP family right away, instead of running into dial timeouts
+struct FamilyFilter<T> {
+    inner: T,
+    family: AddressFamily,
+}
+
+impl<T: Transport + Unpin> Transport for FamilyFilter<T> {
+    type Output = T::Output;
+    type Error = T::Error;
+    type ListenerUpgrade = T::ListenerUpgrade;
+    type Dial = T::Dial;
+
+    fn listen_on(
+        &mut self,
+        id: ListenerId,
+        addr: Multiaddr,
+    ) -> Result<(), TransportError<Self::Error>> {
+        if !self.family.allows(&addr) {
+            return Err(TransportError::MultiaddrNotSupported(addr));
+        }
+        self.inner.listen_on(id, addr)
+    }
+
+    fn remove_listener(&mut self, id: ListenerId) -> bool {
+        self.inner.remove_listener(id)
+    }
+
+    fn dial(
+        &mut self,
+        addr: Multiaddr,
+        opts: DialOpts,
+    ) -> Result<Self::Dial, TransportError<Self::Error>> {
+        if !self.family.allows(&addr) {
+            return Err(TransportError::MultiaddrNotSupported(addr));
+        }
+        self.inner.dial(addr, opts)
+    }
+
+    fn poll(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<TransportEvent<Self::ListenerUpgrade, Self::Error>> {
+        Pin::new(&mut self.get_mut().inner).poll(cx)
+    }
+}
+
+// Dials TCP addresses through a SOCKS5 proxy, host names are resolved by the proxy
+struct SocksTransport {
+    proxy: Option<SocketAddr>,
+}
+
+impl Transport for SocksTransport {
+    type Output = Compat<Socks5Stream<tokio::net::TcpStream>>;
+    type Error = tokio_socks::Error;
+    type ListenerUpgrade = future::Pending<Result<Self::Output, Self::Error>>;
+    type Dial = BoxFuture<'static, Result<Self::Output, Self::Error>>;
+
+    fn listen_on(
+        &mut self,
+        _id: ListenerId,
+        addr: Multiaddr,
+    ) -> Result<(), TransportError<Self::Error>> {
+        Err(TransportError::MultiaddrNotSupported(addr))
+    }
+
+    fn remove_listener(&mut self, _id: ListenerId) -> bool {
+        false
+    }
+
+    fn dial(
+        &mut self,
+        addr: Multiaddr,
+        _opts: DialOpts,
+    ) -> Result<Self::Dial, TransportError<Self::Error>> {
+        // Without a proxy every dial is direct, with one no dial may be
+        let Some(proxy) = self.proxy else {
+            return Err(TransportError::MultiaddrNotSupported(addr));
+        };
+        let Some(target) = socks_target(&addr) else {
+            return Err(TransportError::Other(
+                tokio_socks::Error::InvalidTargetAddress("not dialable through the proxy"),
+            ));
+        };
+
+        Ok(async move {
+            let stream = Socks5Stream::connect(proxy, target).await?;
+            Ok(stream.compat())
+        }
+        .boxed())
+    }
+
+    fn poll(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+    ) -> Poll<TransportEvent<Self::ListenerUpgrade, Self::Error>> {
+        Poll::Pending
+    }
+}
+
+// e.g. /ip4/1.2.3.4/tcp/11511, /dns4/example.com/tcp/11511 or /onion3/<address>:11511, optionally followed
+// by /p2p/<peer id>
+fn socks_target(addr: &Multiaddr) -> Option<TargetAddr<'static>> {
+    let mut protocols = addr.iter();
+
+    let host = protocols.next()?;
+    let port = match host {
+        Protocol::Onion3(ref onion) => onion.port(),
+        _ => match protocols.next() {
+            Some(Protocol::Tcp(port)) => port,
+            _ => return None,
+        },
+    };
+
+    if !protocols.all(|p| matches!(p, Protocol::P2p(_))) {
+        return None;
+    }
+
+    match host {
+        Protocol::Ip4(ip) => Some(TargetAddr::Ip(SocketAddr::new(ip.into(), port))),
+        Protocol::Ip6(ip) => Some(TargetAddr::Ip(SocketAddr::new(ip.into(), port))),
+        Protocol::Dns(name) | Protocol::Dns4(name) | Protocol::Dns6(name) => {
+            Some(TargetAddr::Domain(name.into_owned().into(), port))
+        }
+        // Displayed as /onion3/<base32 address>:<port>
+        onion @ Protocol::Onion3(_) => {
+            let onion = onion.to_string();
+            let (address, _) = onion.trim_start_matches("/onion3/").split_once(':')?;
+            Some(TargetAddr::Domain(
+                format!("{}.onion", address).into(),
+                port,
+            ))
+        }
+        _ => None,
+    }
+}
+
+// Make timed out handshakes recognizable after the error got boxed
+fn handshake_error<E>(error: TransportTimeoutError<E>) -> io::Error
+where
+    E: std::error::Error + Send + Sync + 'static,
+{
+    match error {
+        TransportTimeoutError::Timeout => {
+            io::Error::new(io::ErrorKind::TimedOut, "connection handshake timed out")
+        }
+        error => io::Error::new(io::ErrorKind::Other, error),
+    }
+}
+
+// Whether a connection error was caused by the handshake timeout, walks through the nested transport errors
+pub(crate) fn is_handshake_timeout(mut error: &(dyn std::error::Error + 'static)) -> bool {
+    loop {
+        if let Some(io_error) = error.downcast_ref::<io::Error>() {
+            if io_error.kind() == io::ErrorKind::TimedOut {
+                return true;
+            }
+
+            // The source of an io::Error skips its immediate inner error
+            if let Some(inner) = io_error.get_ref() {
+                error = inner;
+                continue;
+            }
+        }
+
+        match error.source() {
+            Some(source) => error = source,
+            None => return false,
+        }
+    }
+}
+
+// TLS for /wss listeners from a PEM certificate chain and private key
+pub(crate) fn load_websocket_tls(
+    cert_file: &Path,
+    key_file: &Path,
+) -> io::Result<websocket::tls::Config> {
+    let mut certs = Vec::new();
+    for cert in rustls_pemfile::certs(&mut BufReader::new(std::fs::File::open(cert_file)?)) {
+        certs.push(websocket::tls::Certificate::new(cert?.as_ref().to_vec()));
+    }
+
+    let key = rustls_pemfile::private_key(&mut BufReader::new(std::fs::File::open(key_file)?))?
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "No private key found"))?;
+
+    websocket::tls::Config::new(
+        websocket::tls::PrivateKey::new(key.secret_der().to_vec()),
+        certs,
+    )
+    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libp2p::core::transport::PortUse;
+    use libp2p::core::Endpoint;
+
+    const PEER: &str = "12D3KooWDfuRaHj4ZodqxQnAN4Mbi6BcdsQJrBEVTpdqaTBPx8yq";
+
+    fn dial(transport: &mut TcpTransport, addr: &str) -> Result<(), TransportError<io::Error>> {
+        let opts = DialOpts {
+            role: Endpoint::Dialer,
+            port_use: PortUse::New,
+        };
+        transport.dial(addr.parse().unwrap(), opts).map(|_| ())
+    }
+
+    // Tor mode dials through its SOCKS port, nothing may fall through to a direct dial
+    #[tokio::test]
+    async fn tor_mode_refuses_direct_dials() {
+        let proxy = "127.0.0.1:9050".parse().unwrap();
+        let mut transport =
+            tcp_transport(Some(proxy), AddressFamily::Any, Duration::from_secs(10)).unwrap();
+
+        for addr in [
+            format!("/dnsaddr/bootstrap.dexie.space/p2p/{}", PEER),
+            "/ip4/1.2.3.4/udp/11511/quic-v1".to_string(),
+            "/ip4/1.2.3.4/tcp/11511/ws".to_string(),
+        ] {
+            assert!(
+                matches!(dial(&mut transport, &addr), Err(TransportError::Other(_))),
+                "{} was not refused",
+                addr
+            );
+        }
+
+        for addr in [
+            format!("/ip4/1.2.3.4/tcp/11511/p2p/{}", PEER),
+            "/dns4/example.com/tcp/11511".to_string(),
+            "/onion3/vww6ybal4bd7szmgncyruucpgfkqahzddi37ktceo3ah7ngmcopnpyyd:11511".to_string(),
+        ] {
+            assert!(dial(&mut transport, &addr).is_ok(), "{} was refused", addr);
+        }
+    }
+
+    #[tokio::test]
+    async fn direct_dials_without_proxy() {
+        let mut transport =
+            tcp_transport(None, AddressFamily::Any, Duration::from_secs(10)).unwrap();
+
+        assert!(dial(
+            &mut transport,
+            &format!("/ip4/1.2.3.4/tcp/11511/p2p/{}", PEER)
+        )
+        .is_ok());
+    }
+}
@@ -1,8 +1,20 @@
-use libp2p::identity;
+use futures::StreamExt;
+use libp2p::multiaddr::Protocol;
+use libp2p::{identity, Multiaddr, PeerId};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
+use sha2::{Digest, Sha256};
+use splash::{Cidr, MeshConfig, NodeState};
+use std::collections::HashSet;
 use std::fs::{self, File};
+use std::future::Future;
 use std::io;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use warp::hyper::body::Buf;
+use warp::multipart::FormData;
 
 pub fn load_keypair_from_file(file_path: &str) -> io::Result<identity::Keypair> {
     let contents = fs::read_to_string(file_path)?;
@@ -20,15 +32,293 @@ pub fn save_keypair_to_file(keypair: &identity::Keypair, file_path: &str) -> io:
     Ok(())
 }
 
-pub async fn offer_post_hook(endpoint: &str, offer: &str) -> Result<(), reqwest::Error> {
+pub fn load_state_from_file(file_path: &str) -> io::Result<NodeState> {
+    let contents = fs::read_to_string(file_path)?;
+    Ok(serde_json::from_str(&contents)?)
+}
+
+// Write to a temporary file first so a crash can't leave a truncated snapshot behind
+pub fn save_state_to_file(state: &NodeState, file_path: &str) -> io::Result<()> {
+    let tmp_path = format!("{}.tmp", file_path);
+    let file = File::create(&tmp_path)?;
+    serde_json::to_writer(file, state)?;
+    fs::rename(tmp_path, file_path)
+}
+
+// Offer hook payload version, sent in the X-Splash-Schema header
+#[derive(Clone, Copy, Debug, Default, clap::ValueEnum)]
+pub enum HookSchema {
+    // {"offer": "offer1..."}
+    #[default]
+    #[value(name = "1")]
+    V1,
+    // {"offer": "offer1...", "id": "<sha256 hex>", "received_at": <unix seconds>}
+    #[value(name = "2")]
+    V2,
+}
+
+// Counts running hook deliveries, so shutdown can wait for them
+#[derive(Clone, Default)]
+pub struct HookTasks(Arc<AtomicUsize>);
+
+impl HookTasks {
+    pub fn spawn(&self, task: impl Future<Output = ()> + Send + 'static) {
+        let running = self.0.clone();
+        running.fetch_add(1, Ordering::SeqCst);
+        tokio::spawn(async move {
+            task.await;
+            running.fetch_sub(1, Ordering::SeqCst);
+        });
+    }
+
+    // Wait for running deliveries until the deadline, returns how many didn't finish
+    pub async fn drain(&self, deadline: tokio::time::Instant) -> usize {
+        loop {
+            let running = self.0.load(Ordering::SeqCst);
+            if running == 0 || tokio::time::Instant::now() >= deadline {
+                return running;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    }
+}
+
+// Networks and peers of a deny list file, one CIDR or peer ID per line, # starts a comment
+pub fn load_deny_list(file_path: &str) -> io::Result<(Vec<Cidr>, Vec<PeerId>)> {
+    splash::parse_deny_list(&fs::read_to_string(file_path)?)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+// Mesh parameters as JSON, e.g. {"mesh_n": 8, "mesh_n_high": 16}, omitted ones keep their defaults
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MeshConfigFile {
+    mesh_n: Option<usize>,
+    mesh_n_low: Option<usize>,
+    mesh_n_high: Option<usize>,
+    mesh_outbound_min: Option<usize>,
+    heartbeat_interval_ms: Option<u64>,
+    history_length: Option<usize>,
+    history_gossip: Option<usize>,
+}
+
+pub fn load_mesh_config(file_path: &str) -> io::Result<MeshConfig> {
+    let file: MeshConfigFile = serde_json::from_str(&fs::read_to_string(file_path)?)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+    let defaults = MeshConfig::default();
+    Ok(MeshConfig {
+        mesh_n: file.mesh_n.unwrap_or(defaults.mesh_n),
+        mesh_n_low: file.mesh_n_low.unwrap_or(defaults.mesh_n_low),
+        mesh_n_high: file.mesh_n_high.unwrap_or(defaults.mesh_n_high),
+        mesh_outbound_min: file.mesh_outbound_min.unwrap_or(defaults.mesh_outbound_min),
+        heartbeat_interval: file
+            .heartbeat_interval_ms
+            .map_or(defaults.heartbeat_interval, Duration::from_millis),
+        history_length: file.history_length.unwrap_or(defaults.history_length),
+        history_gossip: file.history_gossip.unwrap_or(defaults.history_gossip),
+    })
+}
+
+// One JSON object per HTTP request on the `splash::access` log target, enable with
+// RUST_LOG=splash::access=info
+pub fn access_log(
+    api: &'static str,
+) -> warp::log::Log<impl Fn(warp::log::Info<'_>) + Clone + Send> {
+    warp::log::custom(move |info| {
+        log::info!(
+            target: "splash::access",
+            "{}",
+            json!({
+                "api": api,
+                "method": info.method().as_str(),
+                "path": info.path(),
+                "status": info.status().as_u16(),
+                "latency_ms": info.elapsed().as_secs_f64() * 1000.0,
+                "remote_ip": info.remote_addr().map(|addr| addr.ip().to_string()),
+                "user_agent": info.user_agent(),
+            })
+        );
+    })
+}
+
+// TXT record that makes the DNS introducer lookup return this node
+pub fn dnsaddr_record(network_name: &str, address: &Multiaddr, peer_id: &PeerId) -> String {
+    let address = match address.iter().last() {
+        Some(Protocol::P2p(_)) => address.clone(),
+        _ => address.clone().with(Protocol::P2p(*peer_id)),
+    };
+
+    format!(
+        "_dnsaddr.{}.dexie.space. IN TXT \"dnsaddr={}\"",
+        network_name, address
+    )
+}
+
+pub async fn offer_post_hook(
+    endpoint: &str,
+    offer: &str,
+    schema: HookSchema,
+) -> Result<(), reqwest::Error> {
     let client = reqwest::Client::new();
 
-    let offer_json = json!({ "offer": offer });
-    client.post(endpoint).json(&offer_json).send().await?;
+    let (version, offer_json) = match schema {
+        HookSchema::V1 => (1, json!({ "offer": offer })),
+        HookSchema::V2 => (
+            2,
+            json!({
+                "offer": offer,
+                "id": hex_sha256(offer.as_bytes()),
+                "received_at": unix_timestamp(),
+            }),
+        ),
+    };
+
+    client
+        .post(endpoint)
+        .header("X-Splash-Schema", version)
+        .json(&offer_json)
+        .send()
+        .await?;
 
     Ok(())
 }
 
+fn hex_sha256(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+// Webhook for node events, given as URL or "url=URL,events=peer_connected|peer_disconnected"
+#[derive(Clone, Debug)]
+pub struct EventHook {
+    pub url: String,
+    // None subscribes to all events
+    pub events: Option<HashSet<String>>,
+}
+
+impl EventHook {
+    pub fn wants(&self, event: &str) -> bool {
+        self.events
+            .as_ref()
+            .map_or(true, |events| events.contains(event))
+    }
+}
+
+impl FromStr for EventHook {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if !s.starts_with("url=") {
+            return Ok(EventHook {
+                url: s.to_string(),
+                events: None,
+            });
+        }
+
+        let mut url = None;
+        let mut events = None;
+
+        for field in s.split(',') {
+            match field.split_once('=') {
+                Some(("url", value)) => url = Some(value.to_string()),
+                Some(("events", value)) => {
+                    events = Some(value.split('|').map(str::to_string).collect())
+                }
+                _ => return Err(format!("Invalid event hook field: {}", field)),
+            }
+        }
+
+        Ok(EventHook {
+            url: url.ok_or("Missing url in event hook")?,
+            events,
+        })
+    }
+}
+
+// Replace this node's metrics group on a Prometheus pushgateway
+pub async fn push_metrics(
+    gateway: &str,
+    job: &str,
+    instance: &str,
+    metrics: String,
+) -> Result<(), reqwest::Error> {
+    let client = reqwest::Client::new();
+    client
+        .put(format!(
+            "{}/metrics/job/{}/instance/{}",
+            gateway.trim_end_matches('/'),
+            job,
+            instance
+        ))
+        .header("Content-Type", "text/plain; version=0.0.4")
+        .body(metrics)
+        .send()
+        .await?
+        .error_for_status()?;
+
+    Ok(())
+}
+
+pub async fn event_post_hook(
+    endpoint: &str,
+    payload: &serde_json::Value,
+) -> Result<(), reqwest::Error> {
+    let client = reqwest::Client::new();
+    client.post(endpoint).json(payload).send().await?;
+
+    Ok(())
+}
+
+// Post an event to every hook subscribed to it
+pub fn notify_event_hooks(
+    tasks: &HookTasks,
+    hooks: &[EventHook],
+    event: &str,
+    mut payload: serde_json::Value,
+) {
+    payload["event"] = event.into();
+
+    for hook in hooks.iter().filter(|hook| hook.wants(event)) {
+        let url = hook.url.clone();
+        let payload = payload.clone();
+        tasks.spawn(async move {
+            if let Err(e) = event_post_hook(&url, &payload).await {
+                eprintln!("Error posting to event hook: {}", e);
+            }
+        });
+    }
+}
+
+// Read the part named "file" of a multipart upload
+pub async fn read_multipart_file(mut form: FormData) -> Result<Vec<u8>, warp::Rejection> {
+    while let Some(Ok(part)) = form.next().await {
+        if part.name() != "file" {
+            continue;
+        }
+
+        let mut data = Vec::new();
+        let mut stream = part.stream();
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk.map_err(|_| warp::reject::reject())?;
+            data.extend_from_slice(chunk.chunk());
+        }
+
+        return Ok(data);
+    }
+
+    Err(warp::reject::reject())
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct IdentityJson {
     identity: Vec<u8>,